@@ -6,7 +6,7 @@ use crate::{Piece, ReadOwned, WriteOwned};
 pub fn pipe() -> (PipeWrite, PipeRead) {
     let (tx, rx) = mpsc::channel(1);
     (
-        PipeWrite { tx },
+        PipeWrite { tx: Some(tx) },
         PipeRead {
             rx,
             state: Default::default(),
@@ -18,7 +18,7 @@ pub fn pipe() -> (PipeWrite, PipeRead) {
 enum PipeEvent {
     Piece(Piece),
     Reset,
-    // close is just dropping the channel
+    // close (or shutdown) is just dropping the channel
 }
 
 #[derive(Clone, Copy, Default)]
@@ -87,13 +87,16 @@ impl ReadOwned for PipeRead {
 }
 
 pub struct PipeWrite {
-    tx: mpsc::Sender<PipeEvent>,
+    /// `None` once shut down
+    tx: Option<mpsc::Sender<PipeEvent>>,
 }
 
 impl PipeWrite {
     /// Simulate a connection reset
     pub async fn reset(self) {
-        self.tx.send(PipeEvent::Reset).await.unwrap()
+        if let Some(tx) = self.tx {
+            tx.send(PipeEvent::Reset).await.unwrap()
+        }
     }
 }
 
@@ -104,7 +107,12 @@ impl WriteOwned for PipeWrite {
             // ignore 0-length writes
         }
 
-        if self.tx.send(PipeEvent::Piece(buf.clone())).await.is_err() {
+        let Some(tx) = self.tx.as_ref() else {
+            let err = std::io::Error::new(std::io::ErrorKind::BrokenPipe, "write after shutdown");
+            return (Err(err), buf);
+        };
+
+        if tx.send(PipeEvent::Piece(buf.clone())).await.is_err() {
             let err = std::io::Error::new(std::io::ErrorKind::BrokenPipe, "simulated broken pipe");
             return (Err(err), buf);
         }
@@ -112,7 +120,9 @@ impl WriteOwned for PipeWrite {
         (Ok(buf.len()), buf)
     }
 
+    /// The read end sees EOF once it's read everything written before this.
     async fn shutdown(&mut self) -> std::io::Result<()> {
+        self.tx = None;
         Ok(())
     }
 }
@@ -188,6 +198,27 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_pipe_shutdown() {
+        crate::start(async move {
+            let (mut w, mut r) = pipe();
+
+            crate::spawn(async move {
+                w.write_all_owned("last words").await.unwrap();
+                w.shutdown().await.unwrap();
+                let err = w.write_all_owned("more").await.unwrap_err();
+                assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+            });
+
+            let (res, buf) = r.read_owned(vec![0u8; 256]).await;
+            let n = res.unwrap();
+            assert_eq!(&buf[..n], b"last words");
+
+            let (res, _) = r.read_owned(vec![0u8; 256]).await;
+            assert_eq!(res.unwrap(), 0, "reached EOF");
+        })
+    }
+
     #[test]
     fn test_pipe_fragmented_read() {
         crate::start(async move {
@@ -20,3 +20,44 @@ impl IntoHalves for tokio::net::TcpStream {
         self.into_split()
     }
 }
+
+/// Lets anything generic over [IntoHalves] (e.g. an HTTP/2 test harness)
+/// talk to a server listening on a Unix domain socket:
+///
+/// ```no_run
+/// # async fn connect() -> std::io::Result<()> {
+/// use fluke_buffet::IntoHalves;
+///
+/// let stream = tokio::net::UnixStream::connect("/tmp/server.sock").await?;
+/// let (r, w) = stream.into_halves();
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(unix)]
+impl IntoHalves for tokio::net::UnixStream {
+    type Read = tokio::net::unix::OwnedReadHalf;
+    type Write = tokio::net::unix::OwnedWriteHalf;
+
+    fn into_halves(self) -> (Self::Read, Self::Write) {
+        self.into_split()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::io::{IntoHalves, ReadOwned, WriteOwned};
+
+    #[test]
+    fn unix_stream_halves() {
+        crate::start(async move {
+            let (a, b) = tokio::net::UnixStream::pair().unwrap();
+            let (_a_r, mut a_w) = a.into_halves();
+            let (mut b_r, _b_w) = b.into_halves();
+
+            a_w.write_all_owned("howdy").await.unwrap();
+            let (res, buf) = b_r.read_owned(vec![0u8; 1024]).await;
+            let n = res.unwrap();
+            assert_eq!(&buf[..n], b"howdy");
+        });
+    }
+}
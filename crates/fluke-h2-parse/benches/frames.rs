@@ -0,0 +1,158 @@
+//! Frame parse and encode throughput, on representative frames, and parse
+//! throughput on a stream of back-to-back frames. Run with
+//! `cargo bench -p fluke-h2-parse`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use fluke_buffet::{Piece, Roll, RollMut};
+use fluke_h2_parse::{parse_one, DataFlags, Frame, FrameType, HeadersFlags, IntoPiece, StreamId};
+
+/// A frame and its payload, as a peer would send them
+struct Sample {
+    name: &'static str,
+    frame: Frame,
+    payload: Roll,
+}
+
+impl Sample {
+    fn new(name: &'static str, frame_type: FrameType, payload: &[u8]) -> Self {
+        Self {
+            name,
+            frame: Frame::new(frame_type, StreamId(1)).with_len(payload.len() as u32),
+            payload: to_roll(payload),
+        }
+    }
+
+    /// The frame header followed by the payload
+    fn wire_bytes(&self) -> Roll {
+        let mut buf = Vec::new();
+        self.frame.write_into(&mut buf).unwrap();
+        buf.extend_from_slice(&self.payload[..]);
+        to_roll(&buf)
+    }
+}
+
+fn to_roll(bytes: &[u8]) -> Roll {
+    let mut roll = RollMut::alloc().unwrap();
+    roll.reserve_at_least(bytes.len()).unwrap();
+    roll.put(bytes).unwrap();
+    roll.take_all()
+}
+
+fn samples() -> Vec<Sample> {
+    // what a browser sends for a page load, more or less
+    let mut enc = fluke_hpack::Encoder::new();
+    let headers = [
+        (":method", "GET"),
+        (":scheme", "https"),
+        (":authority", "www.example.com"),
+        (":path", "/assets/app.js?v=3"),
+        (
+            "user-agent",
+            "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0",
+        ),
+        ("accept", "*/*"),
+        ("accept-language", "en-US,en;q=0.5"),
+        ("accept-encoding", "gzip, deflate, br, zstd"),
+        ("referer", "https://www.example.com/"),
+        ("cookie", "session=8f14e45fceea167a5a36dedd4bea2543"),
+        ("sec-fetch-dest", "script"),
+        ("sec-fetch-mode", "no-cors"),
+    ];
+    let block = enc.encode(headers.iter().map(|(k, v)| (k.as_bytes(), v.as_bytes())));
+
+    vec![
+        Sample::new(
+            "small DATA",
+            FrameType::Data(Default::default()),
+            &[0x42; 16],
+        ),
+        Sample::new(
+            "large DATA",
+            FrameType::Data(DataFlags::EndStream.into()),
+            &[0x42; 16384],
+        ),
+        Sample::new(
+            "HEADERS",
+            FrameType::Headers(HeadersFlags::EndHeaders | HeadersFlags::EndStream),
+            &block,
+        ),
+    ]
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for sample in samples() {
+        let bytes = sample.wire_bytes();
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_function(sample.name, |b| {
+            b.iter(|| {
+                let (rest, (frame, payload)) = parse_one(black_box(bytes.clone())).unwrap();
+                black_box((rest, frame, payload))
+            })
+        });
+    }
+    group.finish();
+}
+
+/// How many frames [parse_stream] goes through per iteration
+const STREAM_FRAMES: usize = 1000;
+
+/// Parses a buffer of back-to-back frames, the way a connection's read
+/// loop would: a mix of SETTINGS, HEADERS, and DATA. Throughput is in
+/// frames.
+fn parse_stream(c: &mut Criterion) {
+    let mut settings = Sample::new(
+        "SETTINGS",
+        FrameType::Settings(Default::default()),
+        // MAX_CONCURRENT_STREAMS = 100, INITIAL_WINDOW_SIZE = 1 MiB
+        &[0, 3, 0, 0, 0, 100, 0, 4, 0, 0x10, 0, 0],
+    );
+    settings.frame.stream_id = StreamId::CONNECTION;
+
+    let mut mix = samples();
+    mix.push(settings);
+
+    // built once, every iteration parses the same bytes
+    let mut buf = Vec::new();
+    for sample in mix.iter().cycle().take(STREAM_FRAMES) {
+        buf.extend_from_slice(&sample.wire_bytes()[..]);
+    }
+    let bytes = to_roll(&buf);
+
+    let mut group = c.benchmark_group("parse_stream");
+    group.throughput(Throughput::Elements(STREAM_FRAMES as u64));
+    group.bench_function("mixed", |b| {
+        b.iter(|| {
+            let mut rest = black_box(bytes.clone());
+            let mut frames = 0;
+            while !rest.is_empty() {
+                let (next, (frame, payload)) = parse_one(rest).unwrap();
+                black_box((frame, payload));
+                rest = next;
+                frames += 1;
+            }
+            assert_eq!(frames, STREAM_FRAMES);
+        })
+    });
+    group.finish();
+}
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    let mut scratch = RollMut::alloc().unwrap();
+    for sample in samples() {
+        group.throughput(Throughput::Bytes(9 + sample.payload.len() as u64));
+        group.bench_function(sample.name, |b| {
+            b.iter(|| {
+                // the payload goes out as-is, only the header is serialized
+                let header = black_box(sample.frame).into_piece(&mut scratch).unwrap();
+                let payload: Piece = sample.payload.clone().into();
+                black_box((header, payload))
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse, parse_stream, encode);
+criterion_main!(benches);
@@ -3,7 +3,7 @@
 //! HTTP/2 <https://httpwg.org/specs/rfc9113.html>
 //! HTTP semantics <https://httpwg.org/specs/rfc9110.html>
 
-use std::{fmt, io::Write, ops::RangeInclusive};
+use std::{borrow::Cow, fmt, io::Write, ops::RangeInclusive};
 
 use byteorder::{BigEndian, WriteBytesExt};
 use enum_repr::EnumRepr;
@@ -48,6 +48,8 @@ pub enum RawFrameType {
     GoAway = 0x07,
     WindowUpdate = 0x08,
     Continuation = 0x09,
+    /// See https://httpwg.org/specs/rfc9218.html#frame
+    PriorityUpdate = 0x10,
 }
 
 /// Typed flags for various frame types
@@ -58,11 +60,12 @@ pub enum FrameType {
     Priority,
     RstStream,
     Settings(BitFlags<SettingsFlags>),
-    PushPromise,
+    PushPromise(BitFlags<PushPromiseFlags>),
     Ping(BitFlags<PingFlags>),
     GoAway,
     WindowUpdate,
     Continuation(BitFlags<ContinuationFlags>),
+    PriorityUpdate,
     Unknown(EncodedFrameType),
 }
 
@@ -106,6 +109,15 @@ pub enum SettingsFlags {
     Ack = 0x01,
 }
 
+/// See https://httpwg.org/specs/rfc9113.html#PUSH_PROMISE
+#[bitflags]
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PushPromiseFlags {
+    Padded = 0x08,
+    EndHeaders = 0x04,
+}
+
 /// See https://httpwg.org/specs/rfc9113.html#PING
 #[bitflags]
 #[repr(u8)]
@@ -145,6 +157,27 @@ impl From<(RawFrameType, u8)> for EncodedFrameType {
 }
 
 impl FrameType {
+    /// The frame type's name as written in RFC 9113 ("DATA", "HEADERS",
+    /// etc.), without any flags. Unknown frame types are named after their
+    /// type byte, e.g. "UNKNOWN(0xff)", which is why this isn't always
+    /// `&'static str`.
+    pub fn name(&self) -> Cow<'static, str> {
+        match self {
+            FrameType::Data(_) => "DATA".into(),
+            FrameType::Headers(_) => "HEADERS".into(),
+            FrameType::Priority => "PRIORITY".into(),
+            FrameType::RstStream => "RST_STREAM".into(),
+            FrameType::Settings(_) => "SETTINGS".into(),
+            FrameType::PushPromise(_) => "PUSH_PROMISE".into(),
+            FrameType::Ping(_) => "PING".into(),
+            FrameType::GoAway => "GOAWAY".into(),
+            FrameType::WindowUpdate => "WINDOW_UPDATE".into(),
+            FrameType::Continuation(_) => "CONTINUATION".into(),
+            FrameType::PriorityUpdate => "PRIORITY_UPDATE".into(),
+            FrameType::Unknown(ft) => format!("UNKNOWN({:#04x})", ft.ty).into(),
+        }
+    }
+
     pub(crate) fn encode(self) -> EncodedFrameType {
         match self {
             FrameType::Data(f) => (RawFrameType::Data, f.bits()).into(),
@@ -152,16 +185,19 @@ impl FrameType {
             FrameType::Priority => (RawFrameType::Priority, 0).into(),
             FrameType::RstStream => (RawFrameType::RstStream, 0).into(),
             FrameType::Settings(f) => (RawFrameType::Settings, f.bits()).into(),
-            FrameType::PushPromise => (RawFrameType::PushPromise, 0).into(),
+            FrameType::PushPromise(f) => (RawFrameType::PushPromise, f.bits()).into(),
             FrameType::Ping(f) => (RawFrameType::Ping, f.bits()).into(),
             FrameType::GoAway => (RawFrameType::GoAway, 0).into(),
             FrameType::WindowUpdate => (RawFrameType::WindowUpdate, 0).into(),
             FrameType::Continuation(f) => (RawFrameType::Continuation, f.bits()).into(),
+            FrameType::PriorityUpdate => (RawFrameType::PriorityUpdate, 0).into(),
             FrameType::Unknown(ft) => ft,
         }
     }
 
-    fn decode(ft: EncodedFrameType) -> Self {
+    /// Decodes a frame type from its wire representation. Unknown types are
+    /// kept as [FrameType::Unknown], unknown flags are dropped.
+    pub fn decode(ft: EncodedFrameType) -> Self {
         match RawFrameType::from_repr(ft.ty) {
             Some(ty) => match ty {
                 RawFrameType::Data => {
@@ -175,7 +211,9 @@ impl FrameType {
                 RawFrameType::Settings => {
                     FrameType::Settings(BitFlags::<SettingsFlags>::from_bits_truncate(ft.flags))
                 }
-                RawFrameType::PushPromise => FrameType::PushPromise,
+                RawFrameType::PushPromise => FrameType::PushPromise(
+                    BitFlags::<PushPromiseFlags>::from_bits_truncate(ft.flags),
+                ),
                 RawFrameType::Ping => {
                     FrameType::Ping(BitFlags::<PingFlags>::from_bits_truncate(ft.flags))
                 }
@@ -184,10 +222,22 @@ impl FrameType {
                 RawFrameType::Continuation => FrameType::Continuation(
                     BitFlags::<ContinuationFlags>::from_bits_truncate(ft.flags),
                 ),
+                RawFrameType::PriorityUpdate => FrameType::PriorityUpdate,
             },
             None => FrameType::Unknown(ft),
         }
     }
+
+    /// Like [FrameType::decode], but returns `None` if any flag bits that
+    /// aren't defined for the frame type are set, instead of dropping them.
+    /// Unknown types keep all their flags, so they always decode.
+    ///
+    /// RFC 9113 requires receivers to ignore undefined flags (Section 4.1):
+    /// this is only meant for testing how strict a peer's encoder is.
+    pub fn decode_strict(ft: EncodedFrameType) -> Option<Self> {
+        let decoded = Self::decode(ft);
+        (decoded.encode().flags == ft.flags).then_some(decoded)
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -197,10 +247,25 @@ impl StreamId {
     /// Stream ID used for connection control frames
     pub const CONNECTION: Self = Self(0);
 
+    /// Largest valid stream ID (2^31-1)
+    pub const MAX: Self = Self((1 << 31) - 1);
+
     /// Server-initiated streams have even IDs
     pub fn is_server_initiated(&self) -> bool {
         self.0 % 2 == 0
     }
+
+    /// Returns the client-initiated stream ID that comes after this one,
+    /// or `None` if it would be larger than [StreamId::MAX].
+    pub fn next_client(&self) -> Option<Self> {
+        // client-initiated streams have odd IDs
+        let step = if self.0 % 2 == 0 { 1 } else { 2 };
+        let next = self.0.checked_add(step)?;
+        if next > Self::MAX.0 {
+            return None;
+        }
+        Some(Self(next))
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -232,6 +297,7 @@ impl fmt::Display for StreamId {
 }
 
 /// See https://httpwg.org/specs/rfc9113.html#FrameHeader
+#[derive(Clone, Copy)]
 pub struct Frame {
     pub frame_type: FrameType,
     pub reserved: u8,
@@ -267,11 +333,12 @@ impl fmt::Debug for Frame {
             FrameType::Priority => "Priority",
             FrameType::RstStream => "RstStream",
             FrameType::Settings(_) => "Settings",
-            FrameType::PushPromise => "PushPromise",
+            FrameType::PushPromise(_) => "PushPromise",
             FrameType::Ping(_) => "Ping",
             FrameType::GoAway => "GoAway",
             FrameType::WindowUpdate => "WindowUpdate",
             FrameType::Continuation(_) => "Continuation",
+            FrameType::PriorityUpdate => "PriorityUpdate",
             FrameType::Unknown(EncodedFrameType { ty, flags }) => {
                 return write!(f, "UnknownFrame({:#x}, {:#x}, len={})", ty, flags, self.len)
             }
@@ -311,6 +378,11 @@ impl fmt::Debug for Frame {
                     s.field("flags", &DisplayDebug(flags));
                 }
             }
+            FrameType::PushPromise(flags) => {
+                if !flags.is_empty() {
+                    s.field("flags", &DisplayDebug(flags));
+                }
+            }
             FrameType::Ping(flags) => {
                 if !flags.is_empty() {
                     s.field("flags", &DisplayDebug(flags));
@@ -341,12 +413,27 @@ impl Frame {
         }
     }
 
+    /// A SETTINGS frame with the ACK flag, which has an empty payload.
+    pub fn settings_ack() -> Self {
+        Self::new(
+            FrameType::Settings(SettingsFlags::Ack.into()),
+            StreamId::CONNECTION,
+        )
+    }
+
     /// Set the frame's length.
     pub fn with_len(mut self, len: u32) -> Self {
         self.len = len;
         self
     }
 
+    /// Set the reserved bit in the frame header. Senders must leave it unset
+    /// and receivers must ignore it, so this is only useful for testing peers.
+    pub fn with_reserved_bit(mut self) -> Self {
+        self.reserved = 1;
+        self
+    }
+
     /// Parse a frame from the given slice
     pub fn parse(i: Roll) -> IResult<Roll, Self> {
         let (i, (len, frame_type, (reserved, stream_id))) = tuple((
@@ -388,6 +475,7 @@ impl Frame {
     pub fn is_end_headers(&self) -> bool {
         match self.frame_type {
             FrameType::Headers(flags) => flags.contains(HeadersFlags::EndHeaders),
+            FrameType::PushPromise(flags) => flags.contains(PushPromiseFlags::EndHeaders),
             FrameType::Continuation(flags) => flags.contains(ContinuationFlags::EndHeaders),
             _ => false,
         }
@@ -401,6 +489,265 @@ impl Frame {
             _ => false,
         }
     }
+
+    /// The frame's flags, as sent on the wire. Flags that aren't defined for
+    /// the frame type are dropped when parsing, so they never show up here.
+    pub fn flags(&self) -> u8 {
+        self.frame_type.encode().flags
+    }
+
+    /// Returns true if all the bits of `flag` are set, e.g. `0x1` for
+    /// END_STREAM on a HEADERS frame.
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags() & flag == flag
+    }
+
+    /// Returns true if none of the bits of `flag` are set.
+    pub fn lacks_flag(&self, flag: u8) -> bool {
+        self.flags() & flag == 0
+    }
+
+    /// Checks what can be checked about a frame from its header alone (cf.
+    /// RFC 9113, Section 6): its length against `max_frame_size` (our
+    /// SETTINGS_MAX_FRAME_SIZE) and the fixed length of its type, and
+    /// whether its type allows stream 0, or only stream 0. Unknown frame
+    /// types are only checked against `max_frame_size`.
+    pub fn validate(&self, max_frame_size: u32) -> Result<(), ProtocolViolation> {
+        if self.len > max_frame_size {
+            return Err(ProtocolViolation::FrameSize {
+                frame_type: self.frame_type,
+                len: self.len,
+                max: max_frame_size,
+            });
+        }
+
+        let (connection_scoped, expected_len) = match self.frame_type {
+            FrameType::Data(_)
+            | FrameType::Headers(_)
+            | FrameType::PushPromise(_)
+            | FrameType::Continuation(_) => (false, None),
+            FrameType::Priority => (false, Some(5)),
+            FrameType::RstStream => (false, Some(4)),
+            FrameType::Settings(flags) => {
+                let valid = if flags.contains(SettingsFlags::Ack) {
+                    self.len == 0
+                } else {
+                    self.len % 6 == 0
+                };
+                if !valid {
+                    return Err(self.invalid_length());
+                }
+                (true, None)
+            }
+            FrameType::Ping(_) => (true, Some(8)),
+            FrameType::GoAway => (true, None),
+            FrameType::PriorityUpdate => (true, None),
+            FrameType::WindowUpdate => {
+                // either stream 0 or a stream will do
+                if self.len != 4 {
+                    return Err(self.invalid_length());
+                }
+                return Ok(());
+            }
+            FrameType::Unknown(_) => return Ok(()),
+        };
+
+        if connection_scoped && self.stream_id != StreamId::CONNECTION {
+            return Err(ProtocolViolation::NonZeroStream {
+                frame_type: self.frame_type,
+                stream_id: self.stream_id,
+            });
+        }
+        if !connection_scoped && self.stream_id == StreamId::CONNECTION {
+            return Err(ProtocolViolation::StreamZero {
+                frame_type: self.frame_type,
+            });
+        }
+        if expected_len.is_some_and(|len| len != self.len) {
+            return Err(self.invalid_length());
+        }
+
+        Ok(())
+    }
+
+    fn invalid_length(&self) -> ProtocolViolation {
+        ProtocolViolation::InvalidLength {
+            frame_type: self.frame_type,
+            len: self.len,
+        }
+    }
+
+    /// Checks that the frame's flags are exactly `expected`, e.g.
+    /// `HeadersFlags::EndHeaders as u8 | HeadersFlags::EndStream as u8`.
+    pub fn assert_flags(&self, expected: u8) -> Result<(), FlagsMismatch> {
+        if self.flags() == expected {
+            return Ok(());
+        }
+        Err(FlagsMismatch {
+            frame_type: self.frame_type,
+            expected,
+        })
+    }
+}
+
+/// Returned by [Frame::assert_flags], names the flags on both sides.
+#[derive(Debug)]
+pub struct FlagsMismatch {
+    pub frame_type: FrameType,
+    pub expected: u8,
+}
+
+impl FlagsMismatch {
+    /// Formats flag bits along with their names for the frame type, e.g.
+    /// `0x05 (EndStream | EndHeaders)`.
+    fn describe(&self, flags: u8) -> String {
+        let ty = self.frame_type.encode().ty;
+        let names = match FrameType::decode(EncodedFrameType { ty, flags }) {
+            FrameType::Data(f) => (!f.is_empty()).then(|| f.to_string()),
+            FrameType::Headers(f) => (!f.is_empty()).then(|| f.to_string()),
+            FrameType::Settings(f) => (!f.is_empty()).then(|| f.to_string()),
+            FrameType::Ping(f) => (!f.is_empty()).then(|| f.to_string()),
+            FrameType::Continuation(f) => (!f.is_empty()).then(|| f.to_string()),
+            _ => None,
+        };
+        match names {
+            Some(names) => format!("{flags:#04x} ({names})"),
+            None if flags == 0 => format!("{flags:#04x} (none)"),
+            None => format!("{flags:#04x}"),
+        }
+    }
+}
+
+impl fmt::Display for FlagsMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} frame has flags {}, expected {}",
+            self.frame_type.name(),
+            self.describe(self.frame_type.encode().flags),
+            self.describe(self.expected)
+        )
+    }
+}
+
+impl std::error::Error for FlagsMismatch {}
+
+#[test]
+fn test_frame_flags() {
+    let frame = Frame::new(
+        FrameType::Headers(HeadersFlags::EndHeaders | HeadersFlags::EndStream),
+        StreamId(1),
+    );
+    assert_eq!(frame.flags(), 0x05);
+    assert!(frame.has_flag(HeadersFlags::EndStream as u8));
+    assert!(frame.lacks_flag(HeadersFlags::Padded as u8));
+    assert!(!frame.has_flag(HeadersFlags::EndStream as u8 | HeadersFlags::Padded as u8));
+    assert!(!frame.lacks_flag(HeadersFlags::EndHeaders as u8));
+
+    frame.assert_flags(0x05).unwrap();
+    let err = frame
+        .assert_flags(HeadersFlags::EndHeaders as u8 | HeadersFlags::Padded as u8)
+        .unwrap_err();
+    let msg = err.to_string();
+    assert!(msg.starts_with("HEADERS frame has flags 0x05 ("), "{msg}");
+    assert!(msg.contains("expected 0x0c ("), "{msg}");
+    assert!(msg.contains("Padded"), "{msg}");
+
+    let frame = Frame::new(FrameType::GoAway, StreamId::CONNECTION);
+    let msg = frame.assert_flags(0x01).unwrap_err().to_string();
+    assert_eq!(msg, "GOAWAY frame has flags 0x00 (none), expected 0x01");
+}
+
+/// Parses exactly one frame: its header, then as many payload bytes as the
+/// header declares. Returns the rest of the input, which may hold further
+/// frames. Returns `Incomplete` if the header or payload isn't all there.
+pub fn parse_one(i: Roll) -> IResult<Roll, (Frame, Roll)> {
+    let (i, frame) = Frame::parse(i)?;
+    let (i, payload) = nom::bytes::streaming::take(frame.len)(i)?;
+    Ok((i, (frame, payload)))
+}
+
+#[test]
+fn test_parse_one() {
+    let mut roll = RollMut::alloc().unwrap();
+    let mut to_roll = |bytes: &[u8]| {
+        roll.reserve_at_least(bytes.len()).unwrap();
+        roll.put(bytes).unwrap();
+        roll.take_all()
+    };
+
+    let mut frame = raw_frame_header(8, 0x6, 0, 0, false).to_vec();
+    frame.extend_from_slice(b"pingpong");
+
+    // exact
+    let (rest, (parsed, payload)) = parse_one(to_roll(&frame)).unwrap();
+    assert!(matches!(parsed.frame_type, FrameType::Ping(_)));
+    assert_eq!(&payload[..], b"pingpong");
+    assert!(rest.is_empty());
+
+    // short: partial header, then partial payload
+    for len in [5, 9, 12] {
+        assert!(matches!(
+            parse_one(to_roll(&frame[..len])),
+            Err(nom::Err::Incomplete(_))
+        ));
+    }
+
+    // extra bytes are left over
+    let mut two_frames = frame.clone();
+    two_frames.extend_from_slice(&frame[..3]);
+    let (rest, (_, payload)) = parse_one(to_roll(&two_frames)).unwrap();
+    assert_eq!(&payload[..], b"pingpong");
+    assert_eq!(&rest[..], &frame[..3]);
+}
+
+#[test]
+fn test_parse_golden_byte_stream() {
+    let bytes = std::fs::read("fixtures/golden/server-handshake-and-response.bin").unwrap();
+    let mut roll = RollMut::alloc().unwrap();
+    roll.reserve_at_least(bytes.len()).unwrap();
+    roll.put(&bytes[..]).unwrap();
+    let mut rest = roll.take_all();
+
+    let mut frames = vec![];
+    while !rest.is_empty() {
+        let (next, (frame, payload)) = parse_one(rest).unwrap();
+        frames.push((frame, payload));
+        rest = next;
+    }
+
+    let summary: Vec<_> = frames
+        .iter()
+        .map(|(frame, payload)| {
+            (
+                frame.frame_type.name(),
+                frame.flags(),
+                frame.stream_id.0,
+                payload.len(),
+            )
+        })
+        .collect();
+    let expected: [(Cow<'static, str>, u8, u32, usize); 5] = [
+        ("SETTINGS".into(), 0x0, 0, 12),
+        ("WINDOW_UPDATE".into(), 0x0, 0, 4),
+        ("SETTINGS".into(), 0x1, 0, 0),
+        ("HEADERS".into(), 0x4, 1, 16),
+        ("DATA".into(), 0x1, 1, 5),
+    ];
+    assert_eq!(summary, expected);
+
+    let mut settings = vec![];
+    Settings::parse(&frames[0].1[..], |id, value| {
+        settings.push((id.repr(), value));
+        Ok::<_, ()>(())
+    })
+    .unwrap();
+    assert_eq!(settings, [(0x3, 100), (0x4, 1 << 20)]);
+
+    let (_, update) = WindowUpdate::parse(frames[1].1.clone()).unwrap();
+    assert_eq!(update.increment, (1 << 20) - 65535);
+
+    assert_eq!(&frames[4].1[..], b"hello");
 }
 
 impl IntoPiece for Frame {
@@ -471,6 +818,45 @@ fn test_pack_and_parse_bit_and_u31() {
     }
 }
 
+#[test]
+fn test_frame_type_name() {
+    let cases = [
+        (FrameType::Data(Default::default()), "DATA"),
+        (
+            FrameType::Headers(HeadersFlags::EndHeaders.into()),
+            "HEADERS",
+        ),
+        (FrameType::Priority, "PRIORITY"),
+        (FrameType::RstStream, "RST_STREAM"),
+        (FrameType::Settings(SettingsFlags::Ack.into()), "SETTINGS"),
+        (FrameType::PushPromise(Default::default()), "PUSH_PROMISE"),
+        (FrameType::Ping(Default::default()), "PING"),
+        (FrameType::GoAway, "GOAWAY"),
+        (FrameType::WindowUpdate, "WINDOW_UPDATE"),
+        (FrameType::Continuation(Default::default()), "CONTINUATION"),
+        (FrameType::PriorityUpdate, "PRIORITY_UPDATE"),
+        (
+            FrameType::Unknown(EncodedFrameType { ty: 0xa, flags: 0 }),
+            "UNKNOWN(0x0a)",
+        ),
+    ];
+    for (frame_type, name) in cases {
+        assert_eq!(frame_type.name(), name);
+    }
+}
+
+#[test]
+fn test_stream_id_next_client() {
+    assert_eq!(StreamId::CONNECTION.next_client(), Some(StreamId(1)));
+    assert_eq!(StreamId(1).next_client(), Some(StreamId(3)));
+    assert_eq!(StreamId(4).next_client(), Some(StreamId(5)));
+    assert_eq!(
+        StreamId(StreamId::MAX.0 - 2).next_client(),
+        Some(StreamId::MAX)
+    );
+    assert_eq!(StreamId::MAX.next_client(), None);
+}
+
 #[test]
 #[should_panic(expected = "bit should be 0 or 1: 2")]
 fn test_pack_bit_and_u31_panic_not_a_bit() {
@@ -483,6 +869,91 @@ fn test_pack_bit_and_u31_panic_val_too_large() {
     pack_bit_and_u31(0, 1 << 31);
 }
 
+/// Builds a frame header with exactly the given field values, for parser
+/// tests. Unlike [Frame::write_into], doesn't go through [FrameType], so any
+/// type/flags combination can be expressed.
+#[cfg(test)]
+fn raw_frame_header(len: u32, ty: u8, flags: u8, stream_id: u32, reserved: bool) -> [u8; 9] {
+    assert!(len < (1 << 24), "len doesn't fit in 24 bits: {len:#x}");
+
+    let mut header = [0u8; 9];
+    header[..3].copy_from_slice(&len.to_be_bytes()[1..]);
+    header[3] = ty;
+    header[4] = flags;
+    header[5..].copy_from_slice(&pack_bit_and_u31(reserved as u8, stream_id));
+    header
+}
+
+#[cfg(test)]
+fn parse_raw_frame_header(header: [u8; 9]) -> Frame {
+    let mut roll = RollMut::alloc().unwrap();
+    roll.reserve_at_least(header.len()).unwrap();
+    roll.put(&header[..]).unwrap();
+    let (rest, frame) = Frame::parse(roll.take_all()).unwrap();
+    assert!(rest.is_empty());
+    frame
+}
+
+#[test]
+fn test_parse_unknown_frame_type() {
+    let frame = parse_raw_frame_header(raw_frame_header(0xab_cdef, 0xfa, 0xff, 7, false));
+    assert!(matches!(
+        frame.frame_type,
+        FrameType::Unknown(EncodedFrameType {
+            ty: 0xfa,
+            flags: 0xff
+        })
+    ));
+    assert_eq!(frame.len, 0xab_cdef);
+    assert_eq!(frame.stream_id, StreamId(7));
+    assert_eq!(frame.reserved, 0);
+
+    // the reserved bit is kept apart from the stream id
+    let frame = parse_raw_frame_header(raw_frame_header(0, 0xfa, 0, StreamId::MAX.0, true));
+    assert!(matches!(frame.frame_type, FrameType::Unknown(_)));
+    assert_eq!(frame.stream_id, StreamId::MAX);
+    assert_eq!(frame.reserved, 1);
+}
+
+#[test]
+fn test_decode_strict() {
+    let ft = |ty: RawFrameType, flags: u8| EncodedFrameType {
+        ty: ty.repr(),
+        flags,
+    };
+
+    // END_STREAM | PADDED are defined for DATA
+    assert!(matches!(
+        FrameType::decode_strict(ft(RawFrameType::Data, 0x09)),
+        Some(FrameType::Data(flags)) if flags == DataFlags::EndStream | DataFlags::Padded
+    ));
+    // ...but END_HEADERS isn't
+    assert!(FrameType::decode_strict(ft(RawFrameType::Data, 0x04)).is_none());
+    assert!(matches!(
+        FrameType::decode(ft(RawFrameType::Data, 0x04)),
+        FrameType::Data(flags) if flags.is_empty()
+    ));
+
+    // frame types without flags don't allow any
+    assert!(FrameType::decode_strict(ft(RawFrameType::GoAway, 0)).is_some());
+    assert!(FrameType::decode_strict(ft(RawFrameType::GoAway, 0x01)).is_none());
+
+    // unknown types carry their flags as-is
+    assert!(matches!(
+        FrameType::decode_strict(EncodedFrameType {
+            ty: 0xfa,
+            flags: 0xff
+        }),
+        Some(FrameType::Unknown(_))
+    ));
+}
+
+#[test]
+#[should_panic(expected = "len doesn't fit in 24 bits: 0x1000000")]
+fn test_raw_frame_header_len_too_large() {
+    raw_frame_header(1 << 24, 0xfa, 0, 0, false);
+}
+
 // cf. https://httpwg.org/specs/rfc9113.html#HEADERS
 #[derive(Debug)]
 pub struct PrioritySpec {
@@ -503,6 +974,25 @@ impl PrioritySpec {
             },
         )(i)
     }
+
+    /// Returns true if this makes `own_stream_id` depend on itself, which
+    /// must be treated as a stream error of type PROTOCOL_ERROR (cf. RFC 9113,
+    /// section 5.3.1).
+    pub fn depends_on_self(&self, own_stream_id: StreamId) -> bool {
+        self.stream_dependency == own_stream_id
+    }
+}
+
+#[test]
+fn test_priority_spec_depends_on_self() {
+    let spec = PrioritySpec {
+        exclusive: false,
+        stream_dependency: StreamId(3),
+        weight: 15,
+    };
+    assert!(spec.depends_on_self(StreamId(3)));
+    assert!(!spec.depends_on_self(StreamId(1)));
+    assert!(!spec.depends_on_self(StreamId(5)));
 }
 
 impl IntoPiece for PrioritySpec {
@@ -613,6 +1103,34 @@ impl TryFrom<ErrorCode> for KnownErrorCode {
     }
 }
 
+#[test]
+fn test_known_error_code_repr() {
+    // cf. https://httpwg.org/specs/rfc9113.html#ErrorCodes
+    let cases = [
+        (KnownErrorCode::NoError, 0x00),
+        (KnownErrorCode::ProtocolError, 0x01),
+        (KnownErrorCode::InternalError, 0x02),
+        (KnownErrorCode::FlowControlError, 0x03),
+        (KnownErrorCode::SettingsTimeout, 0x04),
+        (KnownErrorCode::StreamClosed, 0x05),
+        (KnownErrorCode::FrameSizeError, 0x06),
+        (KnownErrorCode::RefusedStream, 0x07),
+        (KnownErrorCode::Cancel, 0x08),
+        (KnownErrorCode::CompressionError, 0x09),
+        (KnownErrorCode::ConnectError, 0x0a),
+        (KnownErrorCode::EnhanceYourCalm, 0x0b),
+        (KnownErrorCode::InadequateSecurity, 0x0c),
+        (KnownErrorCode::Http1_1Required, 0x0d),
+    ];
+    for (code, repr) in cases {
+        assert_eq!(code.repr(), repr, "{code:?}");
+        assert_eq!(KnownErrorCode::try_from(ErrorCode(repr)), Ok(code));
+    }
+
+    // the first value past the RFC 9113 set isn't known
+    assert_eq!(KnownErrorCode::try_from(ErrorCode(0x0e)), Err(()));
+}
+
 /// cf. https://httpwg.org/specs/rfc9113.html#SettingValues
 #[derive(Clone, Copy, Debug)]
 pub struct Settings {
@@ -754,6 +1272,116 @@ pub enum SettingsError {
     SettingsMaxFrameSizeInvalid { actual: u32 },
 }
 
+/// A way a peer broke the protocol, as found by this crate's validators
+/// (e.g. [Frame::validate]), along with the error code it calls for (see
+/// [ProtocolViolation::error_code]). Whether it's a connection error or a
+/// stream error is up to the caller: for most of them, that depends on the
+/// frame and the state of the stream.
+#[derive(thiserror::Error, Debug, Clone, Copy)]
+pub enum ProtocolViolation {
+    #[error("{} frame of {len} bytes, larger than the maximum of {max}", .frame_type.name())]
+    FrameSize {
+        frame_type: FrameType,
+        len: u32,
+        max: u32,
+    },
+
+    #[error("{} frame with an invalid length of {len} bytes", .frame_type.name())]
+    InvalidLength { frame_type: FrameType, len: u32 },
+
+    #[error("{} frame on stream 0, it must be on a stream", .frame_type.name())]
+    StreamZero { frame_type: FrameType },
+
+    #[error("{} frame on stream {stream_id}, it must be on stream 0", .frame_type.name())]
+    NonZeroStream {
+        frame_type: FrameType,
+        stream_id: StreamId,
+    },
+
+    #[error("ENABLE_PUSH setting is supposed to be either 0 or 1, got {actual}")]
+    EnablePushInvalid { actual: u32 },
+
+    #[error("bad INITIAL_WINDOW_SIZE value {actual}, should be less than or equal to 2^31-1")]
+    InitialWindowSizeTooLarge { actual: u32 },
+
+    #[error(
+        "bad SETTINGS_MAX_FRAME_SIZE value {actual}, should be between 2^14 and 2^24-1 inclusive"
+    )]
+    MaxFrameSizeInvalid { actual: u32 },
+
+    #[error("WINDOW_UPDATE with an increment of 0 on stream {stream_id}")]
+    WindowIncrementZero { stream_id: StreamId },
+
+    #[error("{} frame with {pad_len} bytes of padding, but only {len} bytes of payload", .frame_type.name())]
+    PaddingTooLong {
+        frame_type: FrameType,
+        pad_len: u8,
+        len: u32,
+    },
+}
+
+impl ProtocolViolation {
+    /// The error code RFC 9113 calls for
+    pub fn error_code(&self) -> KnownErrorCode {
+        match self {
+            Self::FrameSize { .. } | Self::InvalidLength { .. } => KnownErrorCode::FrameSizeError,
+            Self::InitialWindowSizeTooLarge { .. } => KnownErrorCode::FlowControlError,
+            Self::StreamZero { .. }
+            | Self::NonZeroStream { .. }
+            | Self::EnablePushInvalid { .. }
+            | Self::MaxFrameSizeInvalid { .. }
+            | Self::WindowIncrementZero { .. }
+            | Self::PaddingTooLong { .. } => KnownErrorCode::ProtocolError,
+        }
+    }
+}
+
+impl From<SettingsError> for ProtocolViolation {
+    fn from(e: SettingsError) -> Self {
+        match e {
+            SettingsError::InvalidEnablePushValue { actual } => Self::EnablePushInvalid { actual },
+            SettingsError::InitialWindowSizeTooLarge { actual } => {
+                Self::InitialWindowSizeTooLarge { actual }
+            }
+            SettingsError::SettingsMaxFrameSizeInvalid { actual } => {
+                Self::MaxFrameSizeInvalid { actual }
+            }
+        }
+    }
+}
+
+/// A [ProtocolViolation] found in a frame payload (see [parse_payload]),
+/// along with the kind of error RFC 9113 calls for (Section 5.4)
+#[derive(thiserror::Error, Debug, Clone, Copy)]
+pub enum Http2Error {
+    /// The whole connection must be closed with GOAWAY
+    #[error("connection error: {0}")]
+    Connection(ProtocolViolation),
+
+    /// Only the frame's stream must be reset with RST_STREAM
+    #[error("stream error: {0}")]
+    Stream(ProtocolViolation),
+}
+
+impl Http2Error {
+    pub fn violation(&self) -> &ProtocolViolation {
+        match self {
+            Self::Connection(v) | Self::Stream(v) => v,
+        }
+    }
+
+    /// The error code RFC 9113 calls for
+    pub fn error_code(&self) -> KnownErrorCode {
+        self.violation().error_code()
+    }
+}
+
+impl From<ProtocolViolation> for Http2Error {
+    fn from(v: ProtocolViolation) -> Self {
+        Self::Connection(v)
+    }
+}
+
 #[EnumRepr(type = "u16")]
 #[derive(Debug, Clone, Copy)]
 pub enum Setting {
@@ -774,29 +1402,261 @@ impl Settings {
     ///
     /// Unknown settings are ignored.
     ///
+    /// If the same identifier appears more than once, the callback is called
+    /// for every occurrence, in order, so applying them one after the other
+    /// means the last value wins (cf. RFC 9113, section 6.5.3). Whether that
+    /// happened is reported in the returned [SettingsParseOutcome].
+    ///
+    /// An empty buffer is valid: a SETTINGS frame without parameters means
+    /// "no changes", and the callback is never called.
+    ///
     /// Panics if the buf isn't a multiple of 6 bytes.
     pub fn parse<E>(
         buf: &[u8],
         mut callback: impl FnMut(Setting, u32) -> Result<(), E>,
-    ) -> Result<(), E> {
+    ) -> Result<SettingsParseOutcome, E> {
         assert!(
             buf.len() % 6 == 0,
             "buffer length must be a multiple of 6 bytes"
         );
 
+        let mut outcome = SettingsParseOutcome::default();
+        // known identifiers are all in 0x1..=0x6, so a bitmask is enough
+        let mut seen: u8 = 0;
+
         for chunk in buf.chunks_exact(6) {
             let id = u16::from_be_bytes([chunk[0], chunk[1]]);
             let value = u32::from_be_bytes([chunk[2], chunk[3], chunk[4], chunk[5]]);
             match Setting::from_repr(id) {
                 None => {}
                 Some(id) => {
+                    let bit = 1 << (id as u16);
+                    if seen & bit != 0 {
+                        outcome.saw_duplicates = true;
+                    }
+                    seen |= bit;
+
                     callback(id, value)?;
                 }
             }
         }
 
-        Ok(())
+        Ok(outcome)
+    }
+
+    /// Encodes these settings as a SETTINGS frame payload, 6 bytes per
+    /// parameter.
+    ///
+    /// Every parameter is written out, not just the ones that differ from
+    /// [Settings::default]: our defaults aren't the protocol's initial values
+    /// (push is off, concurrent streams are capped), so a peer must be told
+    /// about all of them. The exception is limits we don't have:
+    /// `max_concurrent_streams` of `None` and `max_header_list_size` of 0 are
+    /// left out, since the protocol's initial value for both is "unlimited".
+    pub fn encode(&self) -> Vec<u8> {
+        encode_setting_pairs(&self.pairs())
+    }
+
+    /// A SETTINGS frame (without the ACK flag) carrying these settings, see
+    /// [Settings::encode]. SETTINGS frames belong on [StreamId::CONNECTION]:
+    /// other stream ids are only useful to test peers.
+    pub fn to_frame(&self, stream_id: StreamId) -> (Frame, impl IntoPiece) {
+        (
+            Frame::new(FrameType::Settings(Default::default()), stream_id),
+            *self,
+        )
+    }
+
+    /// Encodes the parameters of these settings whose values differ from
+    /// `base` (e.g. what the peer already knows), as a SETTINGS frame
+    /// payload. If nothing differs, the payload is empty, which is a valid
+    /// SETTINGS frame meaning "no changes".
+    ///
+    /// Going back to "unlimited" can't be expressed, see [Settings::encode]:
+    /// such changes are left out.
+    pub fn encode_changes_from(&self, base: &Settings) -> Vec<u8> {
+        encode_setting_pairs(&self.changes_from(base))
+    }
+
+    /// The parameters to send, as (identifier, value) pairs, to go from
+    /// these settings (e.g. the ones currently in effect) to `target`: only
+    /// those whose values differ, so the SETTINGS frame is as small as it can
+    /// be. See [Settings::encode_changes_from], which encodes the same pairs.
+    pub fn minimal_frame_to(&self, target: &Settings) -> Vec<(u16, u32)> {
+        target
+            .changes_from(self)
+            .into_iter()
+            .map(|(id, value)| (id.repr(), value))
+            .collect()
+    }
+
+    /// The parameters of these settings whose values differ from `base`
+    fn changes_from(&self, base: &Settings) -> Vec<(Setting, u32)> {
+        let base = base.pairs();
+        self.pairs()
+            .into_iter()
+            .filter(|&(id, value)| {
+                !base.iter().any(|&(base_id, base_value)| {
+                    base_id.repr() == id.repr() && base_value == value
+                })
+            })
+            .collect()
     }
+
+    /// The parameters [Settings::encode] writes out, in identifier order.
+    fn pairs(&self) -> Vec<(Setting, u32)> {
+        let mut pairs = vec![
+            (Setting::HeaderTableSize, self.header_table_size),
+            (Setting::EnablePush, self.enable_push as u32),
+        ];
+        if let Some(max_concurrent_streams) = self.max_concurrent_streams {
+            pairs.push((Setting::MaxConcurrentStreams, max_concurrent_streams));
+        }
+        pairs.push((Setting::InitialWindowSize, self.initial_window_size));
+        pairs.push((Setting::MaxFrameSize, self.max_frame_size));
+        if self.max_header_list_size != 0 {
+            pairs.push((Setting::MaxHeaderListSize, self.max_header_list_size));
+        }
+        pairs
+    }
+}
+
+fn encode_setting_pairs(pairs: &[(Setting, u32)]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(pairs.len() * 6);
+    for &(id, value) in pairs {
+        buf.extend_from_slice(&(id as u16).to_be_bytes());
+        buf.extend_from_slice(&value.to_be_bytes());
+    }
+    buf
+}
+
+/// What [Settings::parse] noticed while going through a SETTINGS payload
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SettingsParseOutcome {
+    /// Whether a known setting identifier appeared more than once. This is
+    /// legal: the last value wins.
+    pub saw_duplicates: bool,
+}
+
+#[test]
+fn test_settings_parse_duplicates_last_wins() {
+    let mut buf = Vec::new();
+    for (id, value) in [
+        (Setting::InitialWindowSize, 100),
+        (Setting::MaxConcurrentStreams, 10),
+        (Setting::InitialWindowSize, 1),
+    ] {
+        buf.write_u16::<BigEndian>(id as u16).unwrap();
+        buf.write_u32::<BigEndian>(value).unwrap();
+    }
+
+    let mut settings = Settings::default();
+    let outcome = Settings::parse(&buf[..], |k, v| settings.apply(k, v)).unwrap();
+    assert!(outcome.saw_duplicates);
+    assert_eq!(settings.initial_window_size, 1);
+    assert_eq!(settings.max_concurrent_streams, Some(10));
+
+    // without the last entry, there's no duplicate
+    let outcome = Settings::parse(&buf[..12], |k, v| settings.apply(k, v)).unwrap();
+    assert!(!outcome.saw_duplicates);
+    assert_eq!(settings.initial_window_size, 100);
+}
+
+#[test]
+fn test_settings_encode_round_trip() {
+    let settings = Settings {
+        header_table_size: 8192,
+        enable_push: true,
+        max_concurrent_streams: Some(42),
+        initial_window_size: 1 << 20,
+        max_frame_size: 1 << 15,
+        max_header_list_size: 16384,
+    };
+    let buf = settings.encode();
+    assert_eq!(buf.len(), 6 * 6);
+
+    let mut parsed = Settings::default();
+    let outcome = Settings::parse(&buf[..], |k, v| parsed.apply(k, v)).unwrap();
+    assert!(!outcome.saw_duplicates);
+    assert_eq!(parsed.header_table_size, 8192);
+    assert!(parsed.enable_push);
+    assert_eq!(parsed.max_concurrent_streams, Some(42));
+    assert_eq!(parsed.initial_window_size, 1 << 20);
+    assert_eq!(parsed.max_frame_size, 1 << 15);
+    assert_eq!(parsed.max_header_list_size, 16384);
+
+    // unlimited values are left out
+    let settings = Settings {
+        max_concurrent_streams: None,
+        max_header_list_size: 0,
+        ..Default::default()
+    };
+    assert_eq!(settings.encode().len(), 4 * 6);
+}
+
+#[test]
+fn test_settings_frames() {
+    let ack = Frame::settings_ack();
+    assert!(ack.is_ack());
+    assert_eq!(ack.stream_id, StreamId::CONNECTION);
+    assert_eq!(ack.len, 0);
+
+    let settings = Settings::default();
+    let (frame, payload) = settings.to_frame(StreamId::CONNECTION);
+    assert!(matches!(frame.frame_type, FrameType::Settings(_)));
+    assert!(!frame.is_ack());
+    let payload = payload.into_piece(&mut RollMut::alloc().unwrap()).unwrap();
+    assert_eq!(&payload[..], &settings.encode()[..]);
+}
+
+#[test]
+fn test_empty_settings() {
+    // no parameters is valid, and changes nothing
+    let mut parsed = Settings::default();
+    let outcome = Settings::parse(&[], |k, v| parsed.apply(k, v)).unwrap();
+    assert!(!outcome.saw_duplicates);
+    assert_eq!(parsed.encode(), Settings::default().encode());
+
+    let settings = Settings::default();
+    assert!(settings.encode_changes_from(&settings).is_empty());
+    let piece = SettingPairs(&[])
+        .into_piece(&mut RollMut::alloc().unwrap())
+        .unwrap();
+    assert!(piece.is_empty());
+
+    let changed = Settings {
+        initial_window_size: 1,
+        ..settings
+    };
+    let mut buf = Vec::new();
+    buf.write_u16::<BigEndian>(Setting::InitialWindowSize as u16)
+        .unwrap();
+    buf.write_u32::<BigEndian>(1).unwrap();
+    assert_eq!(changed.encode_changes_from(&settings), buf);
+}
+
+#[test]
+fn test_settings_minimal_frame_to() {
+    let current = Settings::default();
+    assert!(current.minimal_frame_to(&current).is_empty());
+
+    let target = Settings {
+        max_frame_size: 1 << 15,
+        max_concurrent_streams: None,
+        ..current
+    };
+    // going back to "unlimited" concurrent streams can't be expressed
+    assert_eq!(
+        current.minimal_frame_to(&target),
+        [(Setting::MaxFrameSize.repr(), 1 << 15)]
+    );
+    assert_eq!(
+        target.minimal_frame_to(&current),
+        [
+            (Setting::MaxConcurrentStreams.repr(), 100),
+            (Setting::MaxFrameSize.repr(), 1 << 14),
+        ]
+    );
 }
 
 pub struct SettingPairs<'a>(pub &'a [(Setting, u32)]);
@@ -815,6 +1675,11 @@ impl<const N: usize> From<&'static [(Setting, u32); N]> for SettingPairs<'static
 
 impl<'a> IntoPiece for SettingPairs<'a> {
     fn into_piece(self, scratch: &mut RollMut) -> std::io::Result<Piece> {
+        // a SETTINGS frame without parameters is valid
+        if self.0.is_empty() {
+            return Ok(Piece::empty());
+        }
+
         let roll = scratch
             .put_to_roll(self.0.len() * 6, |mut slice| {
                 for (id, value) in self.0.iter() {
@@ -926,6 +1791,543 @@ impl WindowUpdate {
             },
         ))
     }
+
+    /// Checks the increment isn't 0 (cf. RFC 9113, Section 6.9). `stream_id`
+    /// is the stream the frame was on, for the error.
+    pub fn validate(&self, stream_id: StreamId) -> Result<(), ProtocolViolation> {
+        if self.increment == 0 {
+            return Err(ProtocolViolation::WindowIncrementZero { stream_id });
+        }
+        Ok(())
+    }
+}
+
+/// Payload for a PRIORITY_UPDATE frame (RFC 9218, Section 7.1), which is
+/// sent on stream 0.
+pub struct PriorityUpdate {
+    /// the stream whose priority is updated (the reserved bit before it is
+    /// ignored)
+    pub prioritized_stream_id: StreamId,
+    /// the Priority header field value, e.g. `u=1, i`, as ASCII text
+    pub field_value: Roll,
+}
+
+impl IntoPiece for PriorityUpdate {
+    fn into_piece(self, scratch: &mut RollMut) -> std::io::Result<Piece> {
+        let roll = scratch
+            .put_to_roll(4 + self.field_value.len(), |mut slice| {
+                slice.write_all(&pack_bit_and_u31(0, self.prioritized_stream_id.0))?;
+                slice.write_all(&self.field_value[..])?;
+                Ok(())
+            })
+            .unwrap();
+        Ok(roll.into())
+    }
+}
+
+impl PriorityUpdate {
+    pub fn parse(i: Roll) -> IResult<Roll, Self> {
+        let (rest, (_reserved, prioritized_stream_id)) = parse_bit_and_u31(i)?;
+        Ok((
+            Roll::empty(),
+            Self {
+                prioritized_stream_id: StreamId(prioritized_stream_id),
+                field_value: rest,
+            },
+        ))
+    }
+
+    /// Parses [PriorityUpdate::field_value], see [PriorityParams::parse]
+    pub fn params(&self) -> Result<PriorityParams, PriorityParamsError> {
+        PriorityParams::parse(&self.field_value[..])
+    }
+}
+
+/// The priority parameters of RFC 9218, Section 4, as carried by
+/// PRIORITY_UPDATE frames and the Priority header field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriorityParams {
+    /// `u`: from 0 (most urgent) to 7 (least urgent)
+    pub urgency: u8,
+    /// `i`: whether the response can be used as it comes in, rather than
+    /// only once it's all there
+    pub incremental: bool,
+}
+
+impl Default for PriorityParams {
+    fn default() -> Self {
+        Self {
+            urgency: Self::DEFAULT_URGENCY,
+            incremental: false,
+        }
+    }
+}
+
+/// What can go wrong in [PriorityParams::parse]
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriorityParamsError {
+    #[error("malformed structured field dictionary (at byte {pos})")]
+    Malformed { pos: usize },
+
+    #[error("urgency must be between 0 and 7, got {actual}")]
+    UrgencyOutOfRange { actual: i64 },
+
+    #[error("the {param} parameter has a value of the wrong type")]
+    WrongType { param: &'static str },
+}
+
+impl PriorityParams {
+    pub const DEFAULT_URGENCY: u8 = 3;
+    pub const MAX_URGENCY: u8 = 7;
+
+    /// Parses a Priority field value, e.g. `u=1, i`, as an RFC 8941
+    /// dictionary. Missing parameters get their default value, and unknown
+    /// ones are ignored, as are member parameters (`u=1;foo`).
+    ///
+    /// This is stricter than RFC 9218, which has receivers ignore values
+    /// that are out of range or of the wrong type: here they're errors, so
+    /// tests can tell.
+    pub fn parse(field_value: &[u8]) -> Result<Self, PriorityParamsError> {
+        use PriorityParamsError::*;
+
+        let mut params = Self::default();
+        let mut p = SfParser {
+            input: field_value,
+            pos: 0,
+        };
+
+        p.skip_sp();
+        if p.at_end() {
+            return Ok(params);
+        }
+        loop {
+            let key = p.key()?;
+            let value = if p.eat(b'=') {
+                p.item_or_inner_list()?
+            } else {
+                SfItem::Boolean(true)
+            };
+            p.parameters()?;
+
+            match (key, value) {
+                (b"u", SfItem::Integer(u)) => {
+                    if !(0..=Self::MAX_URGENCY as i64).contains(&u) {
+                        return Err(UrgencyOutOfRange { actual: u });
+                    }
+                    params.urgency = u as u8;
+                }
+                (b"u", _) => return Err(WrongType { param: "u" }),
+                (b"i", SfItem::Boolean(i)) => params.incremental = i,
+                (b"i", _) => return Err(WrongType { param: "i" }),
+                _ => {}
+            }
+
+            p.skip_ows();
+            if p.at_end() {
+                return Ok(params);
+            }
+            if !p.eat(b',') {
+                return Err(p.malformed());
+            }
+            p.skip_ows();
+            // no trailing comma
+            if p.at_end() {
+                return Err(p.malformed());
+            }
+        }
+    }
+
+    /// Encodes these parameters as a Priority field value, e.g. `u=1, i`
+    pub fn encode(&self) -> Vec<u8> {
+        let mut s = format!("u={}", self.urgency);
+        if self.incremental {
+            s.push_str(", i");
+        }
+        s.into_bytes()
+    }
+}
+
+/// The only structured field item types [PriorityParams::parse] cares about
+enum SfItem {
+    Integer(i64),
+    Boolean(bool),
+    Other,
+}
+
+/// Just enough of an RFC 8941 parser for [PriorityParams::parse]
+struct SfParser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> SfParser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos == self.input.len()
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn malformed(&self) -> PriorityParamsError {
+        PriorityParamsError::Malformed { pos: self.pos }
+    }
+
+    fn skip_sp(&mut self) {
+        while self.eat(b' ') {}
+    }
+
+    fn skip_ows(&mut self) {
+        while self.eat(b' ') || self.eat(b'\t') {}
+    }
+
+    /// Advances past bytes matching `f`, returns them
+    fn take_while(&mut self, f: impl Fn(u8) -> bool) -> &'a [u8] {
+        let start = self.pos;
+        while self.peek().is_some_and(&f) {
+            self.pos += 1;
+        }
+        &self.input[start..self.pos]
+    }
+
+    // cf. RFC 8941, Section 4.2.3.3
+    fn key(&mut self) -> Result<&'a [u8], PriorityParamsError> {
+        match self.peek() {
+            Some(c) if c.is_ascii_lowercase() || c == b'*' => {}
+            _ => return Err(self.malformed()),
+        }
+        Ok(self
+            .take_while(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || b"_-.*".contains(&c)))
+    }
+
+    // cf. RFC 8941, Section 4.2.3.2
+    fn parameters(&mut self) -> Result<(), PriorityParamsError> {
+        while self.eat(b';') {
+            self.skip_sp();
+            self.key()?;
+            if self.eat(b'=') {
+                self.bare_item()?;
+            }
+        }
+        Ok(())
+    }
+
+    // cf. RFC 8941, Sections 4.2.1.1 and 4.2.1.2
+    fn item_or_inner_list(&mut self) -> Result<SfItem, PriorityParamsError> {
+        if !self.eat(b'(') {
+            return self.bare_item();
+        }
+        loop {
+            self.skip_sp();
+            if self.eat(b')') {
+                return Ok(SfItem::Other);
+            }
+            self.bare_item()?;
+            self.parameters()?;
+            if !matches!(self.peek(), Some(b' ' | b')')) {
+                return Err(self.malformed());
+            }
+        }
+    }
+
+    // cf. RFC 8941, Section 4.2.3.1
+    fn bare_item(&mut self) -> Result<SfItem, PriorityParamsError> {
+        match self.peek() {
+            Some(b'-' | b'0'..=b'9') => {
+                let negative = self.eat(b'-');
+                let digits = self.take_while(|c| c.is_ascii_digit());
+                if digits.is_empty() || digits.len() > 15 {
+                    return Err(self.malformed());
+                }
+                if self.eat(b'.') {
+                    // a decimal
+                    let fraction = self.take_while(|c| c.is_ascii_digit());
+                    if fraction.is_empty() || fraction.len() > 3 || digits.len() > 12 {
+                        return Err(self.malformed());
+                    }
+                    return Ok(SfItem::Other);
+                }
+                let value = digits
+                    .iter()
+                    .fold(0i64, |acc, d| acc * 10 + (d - b'0') as i64);
+                Ok(SfItem::Integer(if negative { -value } else { value }))
+            }
+            Some(b'"') => {
+                self.pos += 1;
+                loop {
+                    match self.peek() {
+                        Some(b'"') => {
+                            self.pos += 1;
+                            return Ok(SfItem::Other);
+                        }
+                        Some(b'\\') => {
+                            self.pos += 1;
+                            if !matches!(self.peek(), Some(b'"' | b'\\')) {
+                                return Err(self.malformed());
+                            }
+                            self.pos += 1;
+                        }
+                        Some(0x20..=0x7e) => self.pos += 1,
+                        _ => return Err(self.malformed()),
+                    }
+                }
+            }
+            Some(b'?') => {
+                self.pos += 1;
+                let value = match self.peek() {
+                    Some(b'0') => false,
+                    Some(b'1') => true,
+                    _ => return Err(self.malformed()),
+                };
+                self.pos += 1;
+                Ok(SfItem::Boolean(value))
+            }
+            Some(b':') => {
+                self.pos += 1;
+                self.take_while(|c| c.is_ascii_alphanumeric() || b"+/=".contains(&c));
+                if !self.eat(b':') {
+                    return Err(self.malformed());
+                }
+                Ok(SfItem::Other)
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == b'*' => {
+                // a token
+                self.take_while(|c| c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~:/".contains(&c));
+                Ok(SfItem::Other)
+            }
+            _ => Err(self.malformed()),
+        }
+    }
+}
+
+#[test]
+fn test_priority_params() {
+    let parse = |s: &str| PriorityParams::parse(s.as_bytes());
+    let params = |urgency, incremental| PriorityParams {
+        urgency,
+        incremental,
+    };
+
+    assert_eq!(parse(""), Ok(PriorityParams::default()));
+    assert_eq!(parse("u=1, i"), Ok(params(1, true)));
+    assert_eq!(parse("i=?0,u=7"), Ok(params(7, false)));
+    assert_eq!(parse("i=?1"), Ok(params(3, true)));
+    // unknown members and parameters are ignored, the last value wins
+    assert_eq!(
+        parse(r#"u=5;x=y, foo="a, b", bar=(1 2.5 tok), u=0"#),
+        Ok(params(0, false))
+    );
+
+    assert_eq!(
+        parse("u=8"),
+        Err(PriorityParamsError::UrgencyOutOfRange { actual: 8 })
+    );
+    assert_eq!(
+        parse("u=-1"),
+        Err(PriorityParamsError::UrgencyOutOfRange { actual: -1 })
+    );
+    assert_eq!(
+        parse("u=1.5"),
+        Err(PriorityParamsError::WrongType { param: "u" })
+    );
+    assert_eq!(
+        parse("i=1"),
+        Err(PriorityParamsError::WrongType { param: "i" })
+    );
+    for malformed in ["u=1,", "U=1", "u=1 i", "u=\"open"] {
+        assert!(
+            matches!(parse(malformed), Err(PriorityParamsError::Malformed { .. })),
+            "{malformed}"
+        );
+    }
+
+    for p in [params(3, false), params(0, true)] {
+        assert_eq!(PriorityParams::parse(&p.encode()), Ok(p));
+    }
+}
+
+/// Payload for an ALTSVC frame (RFC 7838, Section 4), through which a server
+/// advertises alternative services.
+///
+/// It's an extension frame, so it's decoded as [FrameType::Unknown]: this
+/// parser is opt-in, for frames whose type is [AltSvc::FRAME_TYPE].
+pub struct AltSvc {
+    /// the origin the alternative services apply to. Empty on streams other
+    /// than 0, where the origin is the stream's.
+    pub origin: Roll,
+    /// the Alt-Svc field value, e.g. `h3=":443"; ma=3600`
+    pub field_value: Roll,
+}
+
+impl AltSvc {
+    /// The ALTSVC frame type
+    pub const FRAME_TYPE: u8 = 0xa;
+
+    /// Whether `ft` is that of an ALTSVC frame
+    pub fn is_alt_svc(ft: EncodedFrameType) -> bool {
+        ft.ty == Self::FRAME_TYPE
+    }
+
+    pub fn parse(i: Roll) -> IResult<Roll, Self> {
+        let (rest, origin_len) = nom::number::complete::be_u16(i)?;
+        let (field_value, origin) = nom::bytes::complete::take(origin_len)(rest)?;
+        Ok((
+            Roll::empty(),
+            Self {
+                origin,
+                field_value,
+            },
+        ))
+    }
+}
+
+/// Payload for a PING frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ping {
+    pub opaque_data: [u8; 8],
+}
+
+impl IntoPiece for Ping {
+    fn into_piece(self, scratch: &mut RollMut) -> std::io::Result<Piece> {
+        let roll = scratch
+            .put_to_roll(8, |mut slice| {
+                slice.write_all(&self.opaque_data)?;
+                Ok(())
+            })
+            .unwrap();
+        Ok(roll.into())
+    }
+}
+
+impl Ping {
+    pub fn parse(i: Roll) -> IResult<Roll, Self> {
+        let (rest, data) = nom::bytes::streaming::take(8usize)(i)?;
+        let mut opaque_data = [0u8; 8];
+        opaque_data.copy_from_slice(&data[..]);
+        Ok((rest, Self { opaque_data }))
+    }
+}
+
+/// Writes every parameter, see [Settings::encode]
+impl IntoPiece for Settings {
+    fn into_piece(self, scratch: &mut RollMut) -> std::io::Result<Piece> {
+        let buf = self.encode();
+        let roll = scratch
+            .put_to_roll(buf.len(), |mut slice| {
+                slice.write_all(&buf)?;
+                Ok(())
+            })
+            .unwrap();
+        Ok(roll.into())
+    }
+}
+
+#[test]
+fn test_payloads_round_trip() {
+    let mut scratch = RollMut::alloc().unwrap();
+    let mut roll = RollMut::alloc().unwrap();
+    let mut to_roll = |piece: Piece| {
+        roll.reserve_at_least(piece.len()).unwrap();
+        roll.put(&piece[..]).unwrap();
+        roll.take_all()
+    };
+
+    let goaway = GoAway {
+        last_stream_id: StreamId(7),
+        error_code: KnownErrorCode::EnhanceYourCalm.into(),
+        additional_debug_data: Piece::from(&b"slow down"[..]),
+    };
+    let piece = goaway.into_piece(&mut scratch).unwrap();
+    let (_, parsed) = GoAway::parse(to_roll(piece)).unwrap();
+    assert_eq!(parsed.last_stream_id, StreamId(7));
+    assert_eq!(
+        parsed.error_code.as_repr(),
+        KnownErrorCode::EnhanceYourCalm as u32
+    );
+    assert_eq!(&parsed.additional_debug_data[..], b"slow down");
+
+    let rst = RstStream {
+        error_code: KnownErrorCode::Cancel.into(),
+    };
+    let piece = rst.into_piece(&mut scratch).unwrap();
+    let (_, parsed) = RstStream::parse(to_roll(piece)).unwrap();
+    assert_eq!(parsed.error_code.as_repr(), KnownErrorCode::Cancel as u32);
+
+    let update = WindowUpdate {
+        reserved: 0,
+        increment: 12345,
+    };
+    let piece = update.into_piece(&mut scratch).unwrap();
+    let (_, parsed) = WindowUpdate::parse(to_roll(piece)).unwrap();
+    assert_eq!(parsed.reserved, 0);
+    assert_eq!(parsed.increment, 12345);
+
+    let update = PriorityUpdate {
+        prioritized_stream_id: StreamId(5),
+        field_value: to_roll(Piece::from(&b"u=1, i"[..])),
+    };
+    let piece = update.into_piece(&mut scratch).unwrap();
+    let (_, parsed) = PriorityUpdate::parse(to_roll(piece)).unwrap();
+    assert_eq!(parsed.prioritized_stream_id, StreamId(5));
+    assert_eq!(&parsed.field_value[..], b"u=1, i");
+
+    let ping = Ping {
+        opaque_data: *b"pingpong",
+    };
+    let piece = ping.into_piece(&mut scratch).unwrap();
+    let (_, parsed) = Ping::parse(to_roll(piece)).unwrap();
+    assert_eq!(parsed, ping);
+
+    let settings = Settings {
+        max_frame_size: 1 << 16,
+        ..Default::default()
+    };
+    let piece = settings.into_piece(&mut scratch).unwrap();
+    let mut parsed = Settings::default();
+    Settings::parse(&to_roll(piece)[..], |k, v| parsed.apply(k, v)).unwrap();
+    assert_eq!(parsed.max_frame_size, 1 << 16);
+}
+
+#[test]
+fn test_alt_svc_parse() {
+    let mut roll = RollMut::alloc().unwrap();
+    let mut to_roll = |bytes: &[u8]| {
+        roll.reserve_at_least(bytes.len()).unwrap();
+        roll.put(bytes).unwrap();
+        roll.take_all()
+    };
+
+    // RFC 7838, Section 4: on stream 0, with an origin
+    let mut payload = vec![0, 19];
+    payload.extend_from_slice(b"https://example.com");
+    payload.extend_from_slice(b"h3=\":443\"; ma=3600");
+    let (rest, alt_svc) = AltSvc::parse(to_roll(&payload)).unwrap();
+    assert!(rest.is_empty());
+    assert_eq!(&alt_svc.origin[..], b"https://example.com");
+    assert_eq!(&alt_svc.field_value[..], b"h3=\":443\"; ma=3600");
+
+    // on a stream, without one
+    let (_, alt_svc) = AltSvc::parse(to_roll(b"\0\0h2=\"alt.example.com:443\"")).unwrap();
+    assert!(alt_svc.origin.is_empty());
+    assert_eq!(&alt_svc.field_value[..], b"h2=\"alt.example.com:443\"");
+
+    // the origin can't be longer than the payload
+    assert!(AltSvc::parse(to_roll(&[0, 19, b'h'])).is_err());
+
+    assert!(AltSvc::is_alt_svc(EncodedFrameType { ty: 0xa, flags: 0 }));
+    assert!(matches!(
+        FrameType::decode(EncodedFrameType { ty: 0xa, flags: 0 }),
+        FrameType::Unknown(_)
+    ));
 }
 
 impl<T> IntoPiece for T
@@ -936,3 +2338,371 @@ where
         Ok(self.into())
     }
 }
+
+#[test]
+fn test_protocol_violations() {
+    let frame = |frame_type: FrameType, stream_id: u32, len: u32| {
+        Frame::new(frame_type, StreamId(stream_id)).with_len(len)
+    };
+
+    let valid = [
+        frame(FrameType::Data(Default::default()), 1, 16384),
+        frame(FrameType::Ping(Default::default()), 0, 8),
+        frame(FrameType::Settings(Default::default()), 0, 12),
+        frame(FrameType::Settings(SettingsFlags::Ack.into()), 0, 0),
+        frame(FrameType::WindowUpdate, 0, 4),
+        frame(FrameType::WindowUpdate, 3, 4),
+    ];
+    for f in valid {
+        f.validate(16384).unwrap();
+    }
+
+    let invalid = [
+        (
+            frame(FrameType::Data(Default::default()), 1, 16385),
+            KnownErrorCode::FrameSizeError,
+            "DATA frame of 16385 bytes, larger than the maximum of 16384",
+        ),
+        (
+            frame(FrameType::Headers(Default::default()), 0, 10),
+            KnownErrorCode::ProtocolError,
+            "HEADERS frame on stream 0, it must be on a stream",
+        ),
+        (
+            frame(FrameType::Ping(Default::default()), 3, 8),
+            KnownErrorCode::ProtocolError,
+            "PING frame on stream 3, it must be on stream 0",
+        ),
+        (
+            frame(FrameType::Ping(Default::default()), 0, 6),
+            KnownErrorCode::FrameSizeError,
+            "PING frame with an invalid length of 6 bytes",
+        ),
+        (
+            frame(FrameType::Settings(SettingsFlags::Ack.into()), 0, 6),
+            KnownErrorCode::FrameSizeError,
+            "SETTINGS frame with an invalid length of 6 bytes",
+        ),
+        (
+            frame(FrameType::RstStream, 1, 3),
+            KnownErrorCode::FrameSizeError,
+            "RST_STREAM frame with an invalid length of 3 bytes",
+        ),
+    ];
+    for (f, code, msg) in invalid {
+        let violation = f.validate(16384).unwrap_err();
+        assert_eq!(violation.error_code(), code);
+        assert_eq!(violation.to_string(), msg);
+    }
+
+    let update = WindowUpdate {
+        reserved: 0,
+        increment: 0,
+    };
+    let violation = update.validate(StreamId(5)).unwrap_err();
+    assert_eq!(violation.error_code(), KnownErrorCode::ProtocolError);
+
+    let violation: ProtocolViolation = SettingsError::InvalidEnablePushValue { actual: 2 }.into();
+    assert_eq!(violation.error_code(), KnownErrorCode::ProtocolError);
+}
+
+/// A frame payload, parsed according to its frame type, see [parse_payload]
+pub enum FramePayload {
+    /// the data, without padding
+    Data(Roll),
+    /// the field block fragment, without padding or the priority fields
+    Headers {
+        priority: Option<PrioritySpec>,
+        fragment: Roll,
+    },
+    Priority(PrioritySpec),
+    RstStream(RstStream),
+    /// the known settings, in order: unknown ones are left out
+    Settings(Vec<(Setting, u32)>),
+    PushPromise {
+        promised_stream_id: StreamId,
+        fragment: Roll,
+    },
+    Ping(Ping),
+    GoAway(GoAway),
+    WindowUpdate(WindowUpdate),
+    Continuation(Roll),
+    PriorityUpdate(PriorityUpdate),
+    /// the payload as-is, for extension frames
+    Unknown(Roll),
+}
+
+/// Parses the payload of a frame of type `frame_type`, for handlers that deal
+/// with any frame. `payload` must be the whole payload, as returned by
+/// [parse_one].
+///
+/// Padding is stripped, and payloads are checked as far as they can be
+/// without knowing the connection's state: fixed lengths, padding, and
+/// SETTINGS values.
+pub fn parse_payload(frame_type: &FrameType, payload: Roll) -> Result<FramePayload, Http2Error> {
+    let frame_type = *frame_type;
+    let len = payload.len() as u32;
+    let invalid_length =
+        || Http2Error::Connection(ProtocolViolation::InvalidLength { frame_type, len });
+    let check_len = |valid: bool| if valid { Ok(()) } else { Err(invalid_length()) };
+
+    Ok(match frame_type {
+        FrameType::Data(flags) => {
+            let payload = strip_padding(frame_type, flags.contains(DataFlags::Padded), payload)?;
+            FramePayload::Data(payload)
+        }
+        FrameType::Headers(flags) => {
+            let payload = strip_padding(frame_type, flags.contains(HeadersFlags::Padded), payload)?;
+            if flags.contains(HeadersFlags::Priority) {
+                check_len(payload.len() >= 5)?;
+                let (priority, fragment) = payload.split_at(5);
+                let (_, priority) = PrioritySpec::parse(priority).map_err(|_| invalid_length())?;
+                FramePayload::Headers {
+                    priority: Some(priority),
+                    fragment,
+                }
+            } else {
+                FramePayload::Headers {
+                    priority: None,
+                    fragment: payload,
+                }
+            }
+        }
+        FrameType::Priority => {
+            // that one only breaks the stream (cf. RFC 9113, Section 6.3)
+            if len != 5 {
+                return Err(Http2Error::Stream(ProtocolViolation::InvalidLength {
+                    frame_type,
+                    len,
+                }));
+            }
+            let (_, priority) = PrioritySpec::parse(payload).map_err(|_| invalid_length())?;
+            FramePayload::Priority(priority)
+        }
+        FrameType::RstStream => {
+            check_len(len == 4)?;
+            let (_, rst_stream) = RstStream::parse(payload).map_err(|_| invalid_length())?;
+            FramePayload::RstStream(rst_stream)
+        }
+        FrameType::Settings(_) => {
+            check_len(len % 6 == 0)?;
+            let mut pairs = vec![];
+            // only to check values
+            let mut settings = Settings::default();
+            Settings::parse(&payload[..], |setting, value| {
+                settings.apply(setting, value)?;
+                pairs.push((setting, value));
+                Ok::<_, SettingsError>(())
+            })
+            .map_err(ProtocolViolation::from)?;
+            FramePayload::Settings(pairs)
+        }
+        FrameType::PushPromise(flags) => {
+            let payload = strip_padding(
+                frame_type,
+                flags.contains(PushPromiseFlags::Padded),
+                payload,
+            )?;
+            check_len(payload.len() >= 4)?;
+            let (fragment, (_reserved, promised_stream_id)) =
+                parse_reserved_and_stream_id(payload).map_err(|_| invalid_length())?;
+            FramePayload::PushPromise {
+                promised_stream_id,
+                fragment,
+            }
+        }
+        FrameType::Ping(_) => {
+            check_len(len == 8)?;
+            let (_, ping) = Ping::parse(payload).map_err(|_| invalid_length())?;
+            FramePayload::Ping(ping)
+        }
+        FrameType::GoAway => {
+            check_len(len >= 8)?;
+            let (_, goaway) = GoAway::parse(payload).map_err(|_| invalid_length())?;
+            FramePayload::GoAway(goaway)
+        }
+        FrameType::WindowUpdate => {
+            check_len(len == 4)?;
+            let (_, update) = WindowUpdate::parse(payload).map_err(|_| invalid_length())?;
+            FramePayload::WindowUpdate(update)
+        }
+        FrameType::Continuation(_) => FramePayload::Continuation(payload),
+        FrameType::PriorityUpdate => {
+            check_len(len >= 4)?;
+            let (_, update) = PriorityUpdate::parse(payload).map_err(|_| invalid_length())?;
+            FramePayload::PriorityUpdate(update)
+        }
+        FrameType::Unknown(_) => FramePayload::Unknown(payload),
+    })
+}
+
+/// Strips the pad length field and padding off a DATA, HEADERS or
+/// PUSH_PROMISE payload, if `padded` (cf. RFC 9113, Section 6.1)
+fn strip_padding(
+    frame_type: FrameType,
+    padded: bool,
+    payload: Roll,
+) -> Result<Roll, ProtocolViolation> {
+    if !padded {
+        return Ok(payload);
+    }
+
+    let len = payload.len() as u32;
+    let Some(&pad_len) = payload.first() else {
+        return Err(ProtocolViolation::InvalidLength { frame_type, len });
+    };
+    // the padding can't take up the whole payload
+    if pad_len as u32 >= len {
+        return Err(ProtocolViolation::PaddingTooLong {
+            frame_type,
+            pad_len,
+            len,
+        });
+    }
+    Ok(payload.slice(1..(len - pad_len as u32) as usize))
+}
+
+#[test]
+fn test_parse_payload() {
+    let mut roll = RollMut::alloc().unwrap();
+    let mut to_roll = |bytes: &[u8]| {
+        roll.reserve_at_least(bytes.len()).unwrap();
+        roll.put(bytes).unwrap();
+        roll.take_all()
+    };
+
+    let payload = parse_payload(
+        &FrameType::Data(DataFlags::Padded.into()),
+        to_roll(b"\x02hello\0\0"),
+    );
+    assert!(matches!(payload, Ok(FramePayload::Data(data)) if &data[..] == b"hello"));
+
+    let payload = parse_payload(
+        &FrameType::Headers(HeadersFlags::Priority.into()),
+        to_roll(b"\x80\0\0\x03\x0ffragment"),
+    );
+    let Ok(FramePayload::Headers {
+        priority: Some(priority),
+        fragment,
+    }) = payload
+    else {
+        panic!("expected HEADERS with priority");
+    };
+    assert!(priority.exclusive);
+    assert_eq!(priority.stream_dependency, StreamId(3));
+    assert_eq!(priority.weight, 15);
+    assert_eq!(&fragment[..], b"fragment");
+
+    let payload = parse_payload(&FrameType::Priority, to_roll(b"\0\0\0\x01\x10"));
+    assert!(matches!(payload, Ok(FramePayload::Priority(p)) if p.stream_dependency == StreamId(1)));
+
+    let payload = parse_payload(&FrameType::RstStream, to_roll(b"\0\0\0\x08"));
+    assert!(
+        matches!(payload, Ok(FramePayload::RstStream(r)) if r.error_code.as_repr() == KnownErrorCode::Cancel as u32)
+    );
+
+    let payload = parse_payload(
+        &FrameType::Settings(Default::default()),
+        to_roll(b"\0\x04\0\0\x01\0\x0a\x0a\0\0\0\x01"),
+    );
+    // the unknown setting 0x0a0a is left out
+    assert!(matches!(
+        payload.as_ref().map(|p| match p {
+            FramePayload::Settings(pairs) => &pairs[..],
+            _ => &[],
+        }),
+        Ok([(Setting::InitialWindowSize, 256)])
+    ));
+
+    let payload = parse_payload(
+        &FrameType::PushPromise(Default::default()),
+        to_roll(b"\0\0\0\x02fragment"),
+    );
+    assert!(matches!(
+        payload,
+        Ok(FramePayload::PushPromise { promised_stream_id, fragment })
+            if promised_stream_id == StreamId(2) && &fragment[..] == b"fragment"
+    ));
+
+    let payload = parse_payload(
+        &FrameType::PushPromise(PushPromiseFlags::Padded.into()),
+        to_roll(b"\x02\0\0\0\x04fragment\0\0"),
+    );
+    assert!(matches!(
+        payload,
+        Ok(FramePayload::PushPromise { promised_stream_id, fragment })
+            if promised_stream_id == StreamId(4) && &fragment[..] == b"fragment"
+    ));
+
+    let payload = parse_payload(&FrameType::Ping(Default::default()), to_roll(b"pingpong"));
+    assert!(matches!(payload, Ok(FramePayload::Ping(p)) if &p.opaque_data == b"pingpong"));
+
+    let payload = parse_payload(&FrameType::GoAway, to_roll(b"\0\0\0\x07\0\0\0\0bye"));
+    assert!(matches!(
+        payload,
+        Ok(FramePayload::GoAway(g))
+            if g.last_stream_id == StreamId(7) && &g.additional_debug_data[..] == b"bye"
+    ));
+
+    let payload = parse_payload(&FrameType::WindowUpdate, to_roll(b"\0\0\x01\0"));
+    assert!(matches!(payload, Ok(FramePayload::WindowUpdate(u)) if u.increment == 256));
+
+    let payload = parse_payload(
+        &FrameType::Continuation(Default::default()),
+        to_roll(b"fragment"),
+    );
+    assert!(matches!(payload, Ok(FramePayload::Continuation(c)) if &c[..] == b"fragment"));
+
+    let payload = parse_payload(&FrameType::PriorityUpdate, to_roll(b"\0\0\0\x05u=1"));
+    assert!(matches!(
+        payload,
+        Ok(FramePayload::PriorityUpdate(u))
+            if u.prioritized_stream_id == StreamId(5) && &u.field_value[..] == b"u=1"
+    ));
+
+    let unknown = FrameType::Unknown(EncodedFrameType { ty: 0xfa, flags: 0 });
+    let payload = parse_payload(&unknown, to_roll(b"whatever"));
+    assert!(matches!(payload, Ok(FramePayload::Unknown(u)) if &u[..] == b"whatever"));
+
+    // malformed payloads
+    let violation = parse_payload(&FrameType::Ping(Default::default()), to_roll(b"ping"))
+        .err()
+        .unwrap();
+    assert_eq!(violation.error_code(), KnownErrorCode::FrameSizeError);
+
+    let violation = parse_payload(
+        &FrameType::Data(DataFlags::Padded.into()),
+        to_roll(b"\x05hey"),
+    )
+    .err()
+    .unwrap();
+    assert!(matches!(
+        violation,
+        Http2Error::Connection(ProtocolViolation::PaddingTooLong {
+            pad_len: 5,
+            len: 4,
+            ..
+        })
+    ));
+    assert_eq!(violation.error_code(), KnownErrorCode::ProtocolError);
+
+    let violation = parse_payload(&FrameType::Priority, to_roll(b"\0\0\0\x01"))
+        .err()
+        .unwrap();
+    assert!(matches!(
+        violation,
+        Http2Error::Stream(ProtocolViolation::InvalidLength { len: 4, .. })
+    ));
+    assert_eq!(violation.error_code(), KnownErrorCode::FrameSizeError);
+
+    let violation = parse_payload(
+        &FrameType::Settings(Default::default()),
+        to_roll(b"\0\x02\0\0\0\x02"),
+    )
+    .err()
+    .unwrap();
+    assert!(matches!(
+        violation,
+        Http2Error::Connection(ProtocolViolation::EnablePushInvalid { actual: 2 })
+    ));
+}
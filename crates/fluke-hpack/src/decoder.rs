@@ -265,6 +265,18 @@ impl<E> From<DecoderError> for DecoderOrCallbackError<E> {
 /// The result returned by the `decode` method of the `Decoder`.
 pub type DecoderResult = Result<Vec<(Vec<u8>, Vec<u8>)>, DecoderError>;
 
+/// A header field returned by the `decode_fields` method of the `Decoder`:
+/// its name and value, and how it was represented.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedField {
+    pub name: Vec<u8>,
+    pub value: Vec<u8>,
+    /// Whether the field came as a "literal never indexed" representation
+    /// (RFC 7541, Section 6.2.3). An intermediary forwarding it must use the
+    /// same representation, so it never gets compressed (Section 7.1.3).
+    pub sensitive: bool,
+}
+
 /// Decodes headers encoded using HPACK.
 ///
 /// For now, incremental decoding is not supported, i.e. it is necessary
@@ -336,6 +348,12 @@ impl<'a> Decoder<'a> {
             .set_max_table_size(new_max_size);
     }
 
+    /// Returns the current size of the dynamic table in octets, as defined
+    /// by the HPACK spec (section 4.1)
+    pub fn table_size(&self) -> usize {
+        self.header_table.dynamic_table.get_size()
+    }
+
     /// Sets max allowed table size: any "dynamic table size updates" that try
     /// to bring the table size over that value will error out with
     /// [DecoderError::InvalidMaxDynamicSize]
@@ -366,6 +384,17 @@ impl<'a> Decoder<'a> {
         &mut self,
         buf: &[u8],
         mut cb: impl FnMut(Cow<[u8]>, Cow<[u8]>),
+    ) -> Result<(), DecoderError> {
+        self.decode_with_sensitivity_cb(buf, |name, value, _sensitive| cb(name, value))
+    }
+
+    /// Like `decode_with_cb`, but the callback is also told whether each
+    /// header was sent as "literal never indexed", i.e. is sensitive: see
+    /// [DecodedField::sensitive].
+    pub fn decode_with_sensitivity_cb(
+        &mut self,
+        buf: &[u8],
+        mut cb: impl FnMut(Cow<[u8]>, Cow<[u8]>, bool),
     ) -> Result<(), DecoderError> {
         let mut current_octet_index = 0;
 
@@ -383,7 +412,7 @@ impl<'a> Decoder<'a> {
             let consumed = match field_representation {
                 FieldRepresentation::Indexed => {
                     let ((name, value), consumed) = self.decode_indexed(buffer_leftover)?;
-                    cb(Cow::Borrowed(name), Cow::Borrowed(value));
+                    cb(Cow::Borrowed(name), Cow::Borrowed(value), false);
 
                     consumed
                 }
@@ -391,7 +420,7 @@ impl<'a> Decoder<'a> {
                     let ((name, value), consumed) = {
                         let ((name, value), consumed) =
                             self.decode_literal(buffer_leftover, true)?;
-                        cb(Cow::Borrowed(&name), Cow::Borrowed(&value));
+                        cb(Cow::Borrowed(&name), Cow::Borrowed(&value), false);
 
                         // Since we are to add the decoded header to the header table, we need to
                         // convert them into owned buffers that the decoder can keep internally.
@@ -411,17 +440,15 @@ impl<'a> Decoder<'a> {
                 }
                 FieldRepresentation::LiteralWithoutIndexing => {
                     let ((name, value), consumed) = self.decode_literal(buffer_leftover, false)?;
-                    cb(name, value);
+                    cb(name, value, false);
 
                     consumed
                 }
                 FieldRepresentation::LiteralNeverIndexed => {
-                    // Same as the previous one, except if we were also a proxy
-                    // we would need to make sure not to change the
-                    // representation received here. We don't care about this
-                    // for now.
+                    // Same as the previous one, except a proxy must not change
+                    // the representation received here: let the callback know.
                     let ((name, value), consumed) = self.decode_literal(buffer_leftover, false)?;
-                    cb(name, value);
+                    cb(name, value, true);
 
                     consumed
                 }
@@ -465,6 +492,23 @@ impl<'a> Decoder<'a> {
         Ok(header_list)
     }
 
+    /// Decodes the header block found in the given buffer, like `decode`,
+    /// but keeps track of which fields were sent as "literal never indexed",
+    /// see [DecodedField::sensitive].
+    pub fn decode_fields(&mut self, buf: &[u8]) -> Result<Vec<DecodedField>, DecoderError> {
+        let mut fields = Vec::new();
+
+        self.decode_with_sensitivity_cb(buf, |name, value, sensitive| {
+            fields.push(DecodedField {
+                name: name.into_owned(),
+                value: value.into_owned(),
+                sensitive,
+            })
+        })?;
+
+        Ok(fields)
+    }
+
     /// Decodes an indexed header representation.
     fn decode_indexed(&self, buf: &[u8]) -> Result<DecodedLiteralSlice<'_>, DecoderError> {
         let (index, consumed) = decode_integer(buf, 7)?;
@@ -947,6 +991,39 @@ mod tests {
         assert_eq!(decoder.header_table.dynamic_table.len(), 0);
     }
 
+    /// Tests that only fields with a "never indexed" representation are
+    /// flagged as sensitive.
+    #[test]
+    fn test_decode_fields_sensitivity() {
+        let mut decoder = Decoder::new();
+        // `:method: GET`, indexed
+        let mut hex_dump = vec![0x82];
+        // `password: secret`, literal never indexed (C.2.3.)
+        hex_dump.extend_from_slice(&[
+            0x10, 0x08, 0x70, 0x61, 0x73, 0x73, 0x77, 0x6f, 0x72, 0x64, 0x06, 0x73, 0x65, 0x63,
+            0x72, 0x65, 0x74,
+        ]);
+        // `:path: /sample/path`, literal without indexing (C.2.2.)
+        hex_dump.extend_from_slice(&[
+            0x04, 0x0c, 0x2f, 0x73, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2f, 0x70, 0x61, 0x74, 0x68,
+        ]);
+
+        let fields = decoder.decode_fields(&hex_dump).unwrap();
+
+        let summary: Vec<_> = fields
+            .iter()
+            .map(|f| (&f.name[..], &f.value[..], f.sensitive))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (&b":method"[..], &b"GET"[..], false),
+                (&b"password"[..], &b"secret"[..], true),
+                (&b":path"[..], &b"/sample/path"[..], false),
+            ]
+        );
+    }
+
     /// Tests that a each header list from a sequence of requests is correctly
     /// decoded.
     /// (example from: HPACK-draft-10, C.3.*)
@@ -194,6 +194,13 @@ impl<'a> Encoder<'a> {
             .set_max_table_size(new_max_size);
     }
 
+    /// Returns the current size of the dynamic table in octets, as defined
+    /// by the HPACK spec (section 4.1). A decoder that has seen everything
+    /// this encoder produced has a table of the same size.
+    pub fn table_size(&self) -> usize {
+        self.header_table.dynamic_table.get_size()
+    }
+
     /// Encodes the given headers using the HPACK rules and returns a newly
     /// allocated `Vec` containing the bytes representing the encoded header
     /// set.
@@ -339,6 +346,28 @@ mod tests {
 
     use super::super::Decoder;
 
+    #[test]
+    fn test_table_size_in_sync() {
+        let mut encoder = Encoder::new();
+        let mut decoder = Decoder::new();
+        assert_eq!(encoder.table_size(), 0);
+
+        let headers: [(&[u8], &[u8]); 2] = [(b":method", b"GET"), (b"x-custom", b"value")];
+        for _ in 0..2 {
+            // the name isn't in the static table, so the header is added
+            // the first time, and indexed after that
+            let block = encoder.encode(headers);
+            decoder.decode(&block).unwrap();
+            assert_eq!(encoder.table_size(), 8 + 5 + 32);
+            assert_eq!(decoder.table_size(), 8 + 5 + 32);
+        }
+
+        let block = encoder.encode([(&b"x-other"[..], &b"v"[..])]);
+        decoder.decode(&block).unwrap();
+        assert_eq!(encoder.table_size(), 2 * 32 + 8 + 5 + 7 + 1);
+        assert_eq!(decoder.table_size(), encoder.table_size());
+    }
+
     #[test]
     fn test_encode_integer() {
         assert_eq!(encode_integer(10, 5), [10]);
@@ -32,6 +32,10 @@ pub(crate) struct StreamIncoming {
     // incoming capacity (that we decide, we get to tell
     // the peer how much we can handle with window updates)
     pub(crate) capacity: i64,
+
+    // how much of `total_received` we're done with, and gave back to
+    // the peer (or are about to)
+    released: u64,
 }
 
 impl StreamIncoming {
@@ -45,9 +49,19 @@ impl StreamIncoming {
             total_received: 0,
             content_length,
             capacity: initial_window_size as i64,
+            released: 0,
         }
     }
 
+    /// Returns how many octets of DATA were received since the last call,
+    /// which the peer can have back, on the connection (and on the stream,
+    /// if it goes on).
+    pub(crate) fn take_unreleased(&mut self) -> u64 {
+        let unreleased = self.total_received - self.released;
+        self.released = self.total_received;
+        unreleased
+    }
+
     pub(crate) async fn write_chunk(
         &mut self,
         chunk: Piece,
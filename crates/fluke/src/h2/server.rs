@@ -378,6 +378,7 @@ impl<D: ServerDriver + 'static, W: WriteOwned> ServerContext<D, W> {
                 maybe_frame = rx.recv() => {
                     if let Some((frame, payload)) = maybe_frame {
                         self.process_frame(frame, payload, &mut rx).await?;
+                        self.replenish_connection_window().await?;
                     } else {
                         debug!("h2 process task: peer hung up");
                         break;
@@ -816,16 +817,32 @@ impl<D: ServerDriver + 'static, W: WriteOwned> ServerContext<D, W> {
                     });
                 }
 
+                // the whole frame payload (padding included) counts against
+                // the connection window, whatever state the stream is in
+                let next_conn_cap = self.state.incoming_capacity - frame.len as i64;
+                if next_conn_cap < 0 {
+                    return Err(H2ConnectionError::ConnectionWindowUnderflow);
+                }
+                self.state.incoming_capacity = next_conn_cap;
+                // padding is never handed to anyone, give it back right away
+                let padding = frame.len as i64 - payload.len() as i64;
+                self.state.incoming_released += padding;
+
                 let ss = self.state.streams.get_mut(&frame.stream_id).ok_or(
                     H2ConnectionError::StreamClosed {
                         stream_id: frame.stream_id,
                     },
                 )?;
 
+                // DATA that was handed over to the body, which the stream
+                // window can have back, if the stream goes on
+                let mut stream_released = 0;
+
                 match ss {
                     StreamState::Open { incoming, .. }
                     | StreamState::HalfClosedLocal { incoming } => {
-                        let next_cap = incoming.capacity - payload.len() as i64;
+                        // the stream window is debited the same way
+                        let next_cap = incoming.capacity - frame.len as i64;
                         if next_cap < 0 {
                             return Err(H2ConnectionError::WindowUnderflow {
                                 stream_id: frame.stream_id,
@@ -839,11 +856,17 @@ impl<D: ServerDriver + 'static, W: WriteOwned> ServerContext<D, W> {
                             ChunkPosition::NotLast
                         };
 
-                        // TODO: give back capacity to peer at some point
                         if let Err(e) = incoming.write_chunk(payload.into(), which).await {
                             self.rst(frame.stream_id, e).await?;
-                        } else if flags.contains(DataFlags::EndStream) {
-                            if let StreamState::Open { .. } = ss {
+                        } else {
+                            // the chunk was handed over to the body: the
+                            // connection window can have it back
+                            let released = incoming.take_unreleased() as i64;
+                            self.state.incoming_released += released;
+                            if !flags.contains(DataFlags::EndStream) {
+                                incoming.capacity += released + padding;
+                                stream_released = released + padding;
+                            } else if let StreamState::Open { .. } = ss {
                                 let outgoing = match std::mem::take(ss) {
                                     StreamState::Open { outgoing, .. } => outgoing,
                                     _ => unreachable!(),
@@ -863,11 +886,18 @@ impl<D: ServerDriver + 'static, W: WriteOwned> ServerContext<D, W> {
                             stream_id = %frame.stream_id,
                             "Received data for closed stream"
                         );
+                        self.state.incoming_released += payload.len() as i64;
                         self.rst(frame.stream_id, H2StreamError::StreamClosed)
                             .await?;
                     }
                     StreamState::Transition => unreachable!(),
                 }
+
+                if stream_released > 0 {
+                    debug!(stream_id = %frame.stream_id, increment = %stream_released, "replenishing stream window");
+                    self.write_window_update(frame.stream_id, stream_released as u32)
+                        .await?;
+                }
             }
             FrameType::Headers(flags) => {
                 if flags.contains(HeadersFlags::Priority) {
@@ -877,7 +907,7 @@ impl<D: ServerDriver + 'static, W: WriteOwned> ServerContext<D, W> {
                         .map_err(|err| eyre::eyre!("parsing error: {err:?}"))?;
                     debug!(exclusive = %pri_spec.exclusive, stream_dependency = ?pri_spec.stream_dependency, weight = %pri_spec.weight, "received priority, exclusive");
 
-                    if pri_spec.stream_dependency == frame.stream_id {
+                    if pri_spec.depends_on_self(frame.stream_id) {
                         return Err(H2ConnectionError::HeadersInvalidPriority {
                             stream_id: frame.stream_id,
                         });
@@ -1035,7 +1065,7 @@ impl<D: ServerDriver + 'static, W: WriteOwned> ServerContext<D, W> {
                 };
                 debug!(?pri_spec, "received priority frame");
 
-                if pri_spec.stream_dependency == frame.stream_id {
+                if pri_spec.depends_on_self(frame.stream_id) {
                     return Err(H2ConnectionError::HeadersInvalidPriority {
                         stream_id: frame.stream_id,
                     });
@@ -1072,6 +1102,7 @@ impl<D: ServerDriver + 'static, W: WriteOwned> ServerContext<D, W> {
                         match ss {
                             StreamState::Open { mut incoming, .. }
                             | StreamState::HalfClosedLocal { mut incoming, .. } => {
+                                self.state.incoming_released += incoming.take_unreleased() as i64;
                                 incoming
                                     .send_error(eyre::eyre!("Received RST_STREAM from peer"))
                                     .await;
@@ -1149,11 +1180,8 @@ impl<D: ServerDriver + 'static, W: WriteOwned> ServerContext<D, W> {
                         }
                     }
 
-                    let frame = Frame::new(
-                        FrameType::Settings(SettingsFlags::Ack.into()),
-                        StreamId::CONNECTION,
-                    );
-                    self.write_frame(frame, PieceList::default()).await?;
+                    self.write_frame(Frame::settings_ack(), PieceList::default())
+                        .await?;
                     debug!("Acknowledged peer settings");
 
                     if maybe_send_data {
@@ -1161,7 +1189,7 @@ impl<D: ServerDriver + 'static, W: WriteOwned> ServerContext<D, W> {
                     }
                 }
             }
-            FrameType::PushPromise => {
+            FrameType::PushPromise(_) => {
                 return Err(H2ConnectionError::ClientSentPushPromise);
             }
             FrameType::Ping(flags) => {
@@ -1270,6 +1298,11 @@ impl<D: ServerDriver + 'static, W: WriteOwned> ServerContext<D, W> {
                     stream_id: frame.stream_id,
                 });
             }
+            FrameType::PriorityUpdate => {
+                // we don't do RFC 9218 prioritization, and it's fine to
+                // ignore priority signals
+                trace!("ignoring PRIORITY_UPDATE frame");
+            }
             FrameType::Unknown(ft) => {
                 trace!(
                     "ignoring unknown frame with type 0x{:x}, flags 0x{:x}",
@@ -1282,13 +1315,51 @@ impl<D: ServerDriver + 'static, W: WriteOwned> ServerContext<D, W> {
         Ok(())
     }
 
+    /// Sends a connection-level WINDOW_UPDATE for the DATA we're done with,
+    /// if there is any, so the peer doesn't stall once it has sent 65,535
+    /// octets over the lifetime of the connection.
+    async fn replenish_connection_window(&mut self) -> Result<(), H2ConnectionError> {
+        let increment = std::mem::take(&mut self.state.incoming_released);
+        if increment == 0 {
+            return Ok(());
+        }
+        self.state.incoming_capacity += increment;
+        debug!(%increment, conn_capacity = %self.state.incoming_capacity, "replenishing connection window");
+
+        self.write_window_update(StreamId::CONNECTION, increment as u32)
+            .await
+    }
+
+    /// Send a WINDOW_UPDATE frame to the peer.
+    async fn write_window_update(
+        &mut self,
+        stream_id: StreamId,
+        increment: u32,
+    ) -> Result<(), H2ConnectionError> {
+        let payload = WindowUpdate {
+            reserved: 0,
+            increment,
+        }
+        .into_piece(&mut self.out_scratch)
+        .map_err(|e| eyre::eyre!(e))?;
+        let frame = Frame::new(FrameType::WindowUpdate, stream_id);
+        self.write_frame(frame, PieceList::single(payload)).await?;
+
+        Ok(())
+    }
+
     /// Send a RST_STREAM frame to the peer.
     async fn rst(
         &mut self,
         stream_id: StreamId,
         e: H2StreamError,
     ) -> Result<(), H2ConnectionError> {
-        self.state.streams.remove(&stream_id);
+        if let Some(mut ss) = self.state.streams.remove(&stream_id) {
+            // whatever DATA the stream got won't be read anymore
+            if let Some(incoming) = ss.incoming_mut() {
+                self.state.incoming_released += incoming.take_unreleased() as i64;
+            }
+        }
 
         let error_code = e.as_known_error_code();
         debug!("Sending rst because: {e} (known error code: {error_code:?})");
@@ -1824,6 +1895,7 @@ impl<D: ServerDriver + 'static, W: WriteOwned> ServerContext<D, W> {
                     Entry::Occupied(mut slot) => match slot.get_mut() {
                         StreamState::Open { incoming, .. } => {
                             incoming.write_trailers(headers).await?;
+                            self.state.incoming_released += incoming.take_unreleased() as i64;
 
                             // set stream state to half closed remote. we do a little
                             // dance to avoid re-inserting.
@@ -13,6 +13,11 @@ use crate::Response;
 use super::body::StreamIncoming;
 use fluke_h2_parse::{FrameType, KnownErrorCode, Settings, SettingsError, StreamId};
 
+/// The connection flow-control windows start at 65,535 octets: unlike stream
+/// windows, they're not affected by SETTINGS_INITIAL_WINDOW_SIZE, only by
+/// WINDOW_UPDATE frames (cf. RFC 9113, Section 6.9.2).
+pub(crate) const INITIAL_CONNECTION_WINDOW_SIZE: i64 = (1 << 16) - 1;
+
 pub(crate) struct ConnState {
     pub(crate) streams: HashMap<StreamId, StreamState>,
     pub(crate) last_stream_id: StreamId,
@@ -32,11 +37,16 @@ pub(crate) struct ConnState {
 
     pub(crate) incoming_capacity: i64,
     pub(crate) outgoing_capacity: i64,
+
+    /// DATA octets we're done with (they were handed over to a request
+    /// body, the stream was reset, or they were padding), which we owe the
+    /// peer a connection-level WINDOW_UPDATE for.
+    pub(crate) incoming_released: i64,
 }
 
 impl Default for ConnState {
     fn default() -> Self {
-        let mut s = Self {
+        Self {
             streams: Default::default(),
             last_stream_id: StreamId(0),
 
@@ -46,13 +56,10 @@ impl Default for ConnState {
             send_data_maybe: Default::default(),
             streams_with_pending_data: Default::default(),
 
-            incoming_capacity: 0,
-            outgoing_capacity: 0,
-        };
-        s.incoming_capacity = s.self_settings.initial_window_size as _;
-        s.outgoing_capacity = s.peer_settings.initial_window_size as _;
-
-        s
+            incoming_capacity: INITIAL_CONNECTION_WINDOW_SIZE,
+            outgoing_capacity: INITIAL_CONNECTION_WINDOW_SIZE,
+            incoming_released: 0,
+        }
     }
 }
 
@@ -143,6 +150,16 @@ impl StreamState {
             _ => None,
         }
     }
+
+    /// Get the inner `StreamIncoming` if the state is `Open` or
+    /// `HalfClosedLocal`.
+    pub(crate) fn incoming_mut(&mut self) -> Option<&mut StreamIncoming> {
+        match self {
+            StreamState::Open { incoming, .. } => Some(incoming),
+            StreamState::HalfClosedLocal { incoming, .. } => Some(incoming),
+            _ => None,
+        }
+    }
 }
 
 pub(crate) struct StreamOutgoing {
@@ -381,7 +398,7 @@ pub(crate) enum H2ConnectionError {
     ReadError(eyre::Report),
 
     #[error("error writing H2 frame: {0:?}")]
-    WriteError(std::io::Error),
+    WriteError(#[source] std::io::Error),
 
     #[error("received rst frame for unknown stream")]
     RstStreamForUnknownStream { stream_id: StreamId },
@@ -413,6 +430,9 @@ pub(crate) enum H2ConnectionError {
     #[error("received frame that would cause the window size to underflow")]
     WindowUnderflow { stream_id: StreamId },
 
+    #[error("received data frame that would cause the connection window size to underflow")]
+    ConnectionWindowUnderflow,
+
     #[error("received initial window size settings update that made the connection window size overflow")]
     StreamWindowSizeOverflowDueToSettings { stream_id: StreamId },
 
@@ -435,6 +455,7 @@ impl H2ConnectionError {
             // flow control errors
             H2ConnectionError::WindowUpdateOverflow => KnownErrorCode::FlowControlError,
             H2ConnectionError::WindowUnderflow { .. } => KnownErrorCode::FlowControlError,
+            H2ConnectionError::ConnectionWindowUnderflow => KnownErrorCode::FlowControlError,
             H2ConnectionError::StreamWindowSizeOverflowDueToSettings { .. } => {
                 KnownErrorCode::FlowControlError
             }
@@ -1,7 +1,8 @@
-use std::rc::Rc;
+use std::{rc::Rc, time::Duration};
 
 use fluke::{Body, BodyChunk, Encoder, ExpectResponseHeaders, Responder, Response, ResponseDone};
-use fluke_buffet::{IntoHalves, PipeRead, PipeWrite, ReadOwned, RollMut, WriteOwned};
+use fluke_buffet::{IntoHalves, Piece, PipeRead, PipeWrite, ReadOwned, RollMut, WriteOwned};
+use fluke_h2_parse::{Frame, FrameType, GoAway, HeadersFlags, KnownErrorCode, Setting, StreamId};
 use http::StatusCode;
 use tracing::Level;
 use tracing_subscriber::{filter::Targets, layer::SubscriberExt, util::SubscriberInitExt};
@@ -100,6 +101,17 @@ impl<W: WriteOwned + 'static, R: ReadOwned + 'static> IntoHalves for TwoHalves<W
 }
 
 pub fn start_server() -> httpwg::Conn<TwoHalves<PipeWrite, PipeRead>> {
+    start_server_with_config(Default::default())
+}
+
+pub fn start_server_with_config(
+    config: httpwg::Config,
+) -> httpwg::Conn<TwoHalves<PipeWrite, PipeRead>> {
+    httpwg::Conn::new(Rc::new(config), start_server_io())
+}
+
+/// Spawns a fluke server on an in-memory pipe, and returns the client's end
+pub fn start_server_io() -> TwoHalves<PipeWrite, PipeRead> {
     let (server_write, client_read) = fluke::buffet::pipe();
     let (client_write, server_read) = fluke::buffet::pipe();
 
@@ -117,11 +129,22 @@ pub fn start_server() -> httpwg::Conn<TwoHalves<PipeWrite, PipeRead>> {
     };
 
     fluke_buffet::spawn(async move {
-        serve_fut.await.unwrap();
+        if let Err(e) = serve_fut.await {
+            // the client hanging up before the server is done writing is
+            // fine, e.g. once it got the GOAWAY it was waiting for
+            let broken_pipe = e.chain().any(|cause| {
+                cause
+                    .downcast_ref::<std::io::Error>()
+                    .is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe)
+            });
+            if !broken_pipe {
+                panic!("server failed: {e:?}");
+            }
+            tracing::debug!("client hung up before the server was done: {e}");
+        }
     });
 
-    let config = Rc::new(httpwg::Config::default());
-    httpwg::Conn::new(config, TwoHalves(client_write, client_read))
+    TwoHalves(client_write, client_read)
 }
 
 #[cfg(test)]
@@ -134,3 +157,385 @@ httpwg_macros::tests! {{
        result.unwrap()
    });
 }}
+
+/// Section 5.1.1: A client that is unable to establish a new stream
+/// identifier can establish a new connection for new streams. Here we check
+/// the last valid stream identifier works, and that a client naively wrapping
+/// around past 2^31-1 (which sets the reserved bit, and lands on stream 1)
+/// gets a connection error.
+#[test]
+fn client_runs_out_of_stream_ids() {
+    crate::setup_tracing_and_error_reporting();
+
+    fluke_buffet::start(async move {
+        let mut conn = crate::start_server_with_config(httpwg::Config {
+            first_stream_id: StreamId::MAX,
+            ..Default::default()
+        });
+        conn.handshake().await.unwrap();
+
+        let stream_id = conn.open_stream().unwrap();
+        assert_eq!(stream_id, StreamId::MAX);
+        conn.send_empty_post_to_root(stream_id).await.unwrap();
+        conn.verify_headers_frame(stream_id).await.unwrap();
+
+        // the harness won't hand out any more stream ids
+        assert!(conn.open_stream().is_err());
+
+        // ...but a client that wraps around would end up sending this
+        let mut frame = Frame::new(
+            FrameType::Headers(HeadersFlags::EndStream | HeadersFlags::EndHeaders),
+            StreamId(1),
+        );
+        frame.reserved = 1;
+        let block_fragment = conn.encode_headers(&conn.common_headers("POST")).unwrap();
+        conn.write_frame(frame, block_fragment).await.unwrap();
+
+        conn.verify_connection_error(httpwg::ErrorC::ProtocolError)
+            .await
+            .unwrap();
+    });
+}
+
+/// Section 5.1.1: A client that is unable to establish a new stream
+/// identifier [...] should send a GOAWAY frame, which the server must not
+/// treat as an error.
+#[test]
+fn client_sends_goaway_after_running_out_of_stream_ids() {
+    crate::setup_tracing_and_error_reporting();
+
+    fluke_buffet::start(async move {
+        let mut conn = crate::start_server_with_config(httpwg::Config {
+            first_stream_id: StreamId::MAX,
+            ..Default::default()
+        });
+        conn.handshake().await.unwrap();
+
+        let stream_id = conn.open_stream().unwrap();
+        conn.send_empty_post_to_root(stream_id).await.unwrap();
+        conn.verify_stream_close(stream_id).await.unwrap();
+        assert!(conn.open_stream().is_err());
+
+        conn.write_frame(
+            FrameType::GoAway.into_frame(StreamId::CONNECTION),
+            GoAway {
+                additional_debug_data: Piece::empty(),
+                error_code: KnownErrorCode::NoError.into(),
+                last_stream_id: StreamId::CONNECTION,
+            },
+        )
+        .await
+        .unwrap();
+
+        // with no streams left on either side, the server may wind down: it
+        // can send a GOAWAY of its own, or close cleanly, but it must not
+        // report an error.
+        match conn.wait_for_frame(httpwg::FrameT::GoAway).await {
+            httpwg::FrameWaitOutcome::Success(_, payload) => {
+                let (_, goaway) = GoAway::parse(payload).unwrap();
+                assert_eq!(
+                    KnownErrorCode::try_from(goaway.error_code),
+                    Ok(KnownErrorCode::NoError)
+                );
+            }
+            httpwg::FrameWaitOutcome::Eof { .. } | httpwg::FrameWaitOutcome::IoError { .. } => {}
+            httpwg::FrameWaitOutcome::Timeout { .. } => {
+                panic!("server neither sent a GOAWAY nor closed the connection")
+            }
+        }
+    });
+}
+
+/// Section 5.1.1: a new stream id lower than one already used is a
+/// connection error of type PROTOCOL_ERROR, and the GOAWAY points at the
+/// highest stream the server processed.
+#[test]
+fn decreasing_stream_id_is_a_connection_error() {
+    crate::setup_tracing_and_error_reporting();
+
+    fluke_buffet::start(async move {
+        let mut conn = crate::start_server();
+        conn.handshake().await.unwrap();
+
+        conn.send_empty_post_to_root(StreamId(5)).await.unwrap();
+        conn.send_empty_post_to_root(StreamId(3)).await.unwrap();
+        assert_eq!(conn.highest_client_stream_id(), Some(StreamId(5)));
+
+        match conn
+            .expect_error_or_close(httpwg::ErrorC::ProtocolError)
+            .await
+            .unwrap()
+        {
+            httpwg::ErrorOutcome::GoAway { last_stream_id, .. } => {
+                assert_eq!(Some(last_stream_id), conn.highest_client_stream_id());
+            }
+            outcome => panic!("expected a GOAWAY, got {outcome:?}"),
+        }
+    });
+}
+
+#[test]
+fn smoke_test_passes() {
+    crate::setup_tracing_and_error_reporting();
+
+    fluke_buffet::start(async move {
+        httpwg::smoke_test(Default::default(), || async {
+            Ok(crate::start_server_io())
+        })
+        .await
+        .unwrap();
+    });
+}
+
+/// The harness should pick up the server's settings during the handshake:
+/// fluke explicitly disables push and advertises a header list size.
+#[test]
+fn conn_reflects_server_settings() {
+    crate::setup_tracing_and_error_reporting();
+
+    fluke_buffet::start(async move {
+        let mut conn = crate::start_server();
+        conn.handshake().await.unwrap();
+
+        assert!(conn.peer_advertised(Setting::EnablePush));
+        assert!(!conn.peer_supports_push());
+        assert!(conn.peer_max_header_list_size().is_some());
+    });
+}
+
+/// With frame recording on, the exact wire bytes of received frames are kept
+#[test]
+fn conn_records_frame_bytes() {
+    crate::setup_tracing_and_error_reporting();
+
+    fluke_buffet::start(async move {
+        let mut conn = crate::start_server_with_config(httpwg::Config {
+            record_frames: true,
+            ..Default::default()
+        });
+        conn.handshake().await.unwrap();
+
+        // the last thing the server sends during the handshake is an empty
+        // SETTINGS frame with the ACK flag
+        assert_eq!(
+            conn.last_frames_bytes(1),
+            vec![vec![0, 0, 0, 0x4, 0x1, 0, 0, 0, 0]]
+        );
+    });
+}
+
+/// The handshake involves exactly two SETTINGS frames from the server: its
+/// own settings, and the acknowledgement of ours.
+#[test]
+fn handshake_frame_counts() {
+    crate::setup_tracing_and_error_reporting();
+
+    fluke_buffet::start(async move {
+        let mut conn = crate::start_server_with_config(httpwg::Config {
+            record_frames: true,
+            ..Default::default()
+        });
+        conn.handshake().await.unwrap();
+
+        let counts = conn.frame_counts();
+        assert_eq!(counts.get(&httpwg::FrameT::Settings), Some(&2));
+        assert_eq!(counts.values().sum::<usize>(), 2, "got {counts:?}");
+    });
+}
+
+#[test]
+fn conn_reads_response_body() {
+    crate::setup_tracing_and_error_reporting();
+
+    fluke_buffet::start(async move {
+        let mut conn = crate::start_server_with_config(httpwg::Config {
+            auto_window_update: true,
+            ..Default::default()
+        });
+        conn.handshake().await.unwrap();
+
+        let stream_id = conn.open_stream().unwrap();
+        conn.send_empty_post_to_root(stream_id).await.unwrap();
+        conn.verify_headers_frame(stream_id).await.unwrap();
+
+        let body = conn.read_body(stream_id, 1024).await.unwrap();
+        assert_eq!(&body[..], b"it's less dire to lose, than to lose oneself");
+    });
+}
+
+#[test]
+fn conn_read_body_enforces_max_len() {
+    crate::setup_tracing_and_error_reporting();
+
+    fluke_buffet::start(async move {
+        let mut conn = crate::start_server();
+        conn.handshake().await.unwrap();
+
+        let stream_id = conn.open_stream().unwrap();
+        conn.send_empty_post_to_root(stream_id).await.unwrap();
+
+        assert!(conn.read_body(stream_id, 4).await.is_err());
+    });
+}
+
+#[test]
+fn conn_checks_response_framing() {
+    crate::setup_tracing_and_error_reporting();
+
+    fluke_buffet::start(async move {
+        let mut conn = crate::start_server();
+        conn.handshake().await.unwrap();
+
+        let stream_id = conn.open_stream().unwrap();
+        conn.send_empty_post_to_root(stream_id).await.unwrap();
+        conn.expect_response_framing(stream_id).await.unwrap();
+    });
+}
+
+#[test]
+fn empty_data_frame_ends_request() {
+    crate::setup_tracing_and_error_reporting();
+
+    fluke_buffet::start(async move {
+        let mut conn = crate::start_server();
+        conn.handshake().await.unwrap();
+
+        let stream_id = conn.open_stream().unwrap();
+        let headers = conn.common_headers("GET");
+        conn.send_request(
+            stream_id,
+            &headers,
+            &[],
+            httpwg::RequestOptions {
+                end_stream_on_headers: false,
+            },
+        )
+        .await
+        .unwrap();
+        conn.expect_response_framing(stream_id).await.unwrap();
+    });
+}
+
+/// Section 6.9: flow-control windows only ever grow through WINDOW_UPDATE
+/// frames, so without them a client would stall after 65,535 octets of
+/// request bodies, over all streams, or on any single one.
+#[test]
+fn replenishes_flow_control_windows() {
+    crate::setup_tracing_and_error_reporting();
+
+    fluke_buffet::start(async move {
+        let mut conn = crate::start_server_with_config(httpwg::Config {
+            buffer_unmatched_frames: true,
+            ..Default::default()
+        });
+        conn.handshake().await.unwrap();
+
+        let max_frame_size = conn.settings.max_frame_size as i64;
+        // more than either window allows without a WINDOW_UPDATE
+        let body_len = 100_000;
+        for _ in 0..2 {
+            let stream_id = conn.open_stream().unwrap();
+            conn.encode_and_write_headers(
+                stream_id,
+                HeadersFlags::EndHeaders,
+                &conn.common_headers("POST"),
+            )
+            .await
+            .unwrap();
+            let mut left = body_len;
+            while left > 0 {
+                if conn.stream_send_window(stream_id) <= 0 {
+                    conn.expect_window_update(Some(stream_id), 1).await.unwrap();
+                    continue;
+                }
+                if conn.send_window() <= 0 {
+                    conn.expect_window_update(None, 1).await.unwrap();
+                    continue;
+                }
+                let len = left
+                    .min(max_frame_size)
+                    .min(conn.send_window())
+                    .min(conn.stream_send_window(stream_id));
+                left -= len;
+                conn.write_data(stream_id, left == 0, httpwg::dummy_bytes(len as usize))
+                    .await
+                    .unwrap();
+            }
+
+            conn.verify_headers_frame(stream_id).await.unwrap();
+        }
+
+        // all of it was handed over: the connection window is whole again
+        conn.wait_until_idle(Duration::from_millis(50))
+            .await
+            .unwrap();
+        assert_eq!(conn.send_window(), (1 << 16) - 1);
+    });
+}
+
+#[test]
+fn gives_padding_back_to_stream_window() {
+    crate::setup_tracing_and_error_reporting();
+
+    fluke_buffet::start(async move {
+        let mut conn = crate::start_server();
+        conn.handshake().await.unwrap();
+
+        let stream_id = conn.open_stream().unwrap();
+        conn.encode_and_write_headers(
+            stream_id,
+            HeadersFlags::EndHeaders,
+            &conn.common_headers("POST"),
+        )
+        .await
+        .unwrap();
+        for _ in 0..4 {
+            conn.send_data_padded(stream_id, &[b'a'; 1000], 200, false)
+                .await
+                .unwrap();
+        }
+
+        // padding included, every octet sent on the stream was given back
+        conn.wait_until_idle(Duration::from_millis(50))
+            .await
+            .unwrap();
+        assert_eq!(conn.stream_send_window(stream_id), (1 << 16) - 1);
+        assert_eq!(conn.send_window(), (1 << 16) - 1);
+
+        conn.write_data(stream_id, true, &b""[..]).await.unwrap();
+        conn.verify_headers_frame(stream_id).await.unwrap();
+    });
+}
+
+#[test]
+fn conn_measures_rtt() {
+    crate::setup_tracing_and_error_reporting();
+
+    fluke_buffet::start(async move {
+        let mut conn = crate::start_server();
+        conn.handshake().await.unwrap();
+
+        let rtts = conn.measure_rtts(3).await.unwrap();
+        assert_eq!(rtts.len(), 3);
+    });
+}
+
+#[test]
+fn conn_waits_until_idle() {
+    crate::setup_tracing_and_error_reporting();
+
+    fluke_buffet::start(async move {
+        let mut conn = crate::start_server();
+        conn.handshake().await.unwrap();
+
+        // leave the whole response unread
+        let stream_id = conn.open_stream().unwrap();
+        conn.send_empty_post_to_root(stream_id).await.unwrap();
+        conn.wait_until_idle(Duration::from_millis(50))
+            .await
+            .unwrap();
+
+        // nothing left over from that response
+        conn.verify_connection_still_alive().await.unwrap();
+    });
+}
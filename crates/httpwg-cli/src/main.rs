@@ -1,26 +1,51 @@
 use std::{
-    collections::HashMap, ffi::OsString, future::Future, net::SocketAddr, pin::Pin, rc::Rc,
-    time::Duration,
+    collections::HashMap, ffi::OsString, fmt, future::Future, net::SocketAddr, path::PathBuf,
+    rc::Rc, time::Duration,
 };
 
 use fluke_buffet::{net::TcpStream, IntoHalves};
-use httpwg::{rfc9113, Config, Conn};
+use httpwg::{rfc9113, Config, Conn, FrameT, FrameWaitOutcome};
+use runner::{
+    run_tests, BoxedFuture, BoxedTest, ConformanceTest, RequirementLevel, RunOptions, ShareableTest,
+};
 use tracing::Level;
 use tracing_subscriber::{filter::Targets, layer::SubscriberExt, util::SubscriberInitExt};
 
+mod runner;
+
 #[derive(Default, Debug)]
 struct Args {
     /// the binary to run tests against (and any args to pass to it)
     server_binary: Vec<String>,
 
-    /// the address/port the binary will listen on
-    server_address: Option<SocketAddr>,
+    /// the address/port (or Unix socket) the binary will listen on
+    server_address: Option<Endpoint>,
 
     /// the timeout for connections (in milliseconds)
     connect_timeout: Option<u64>,
 
     /// which tests to run
     filter: Option<String>,
+
+    /// whether to run all tests on a single connection
+    reuse_connection: bool,
+}
+
+/// Where the server under test listens
+#[derive(Debug, Clone)]
+enum Endpoint {
+    Tcp(SocketAddr),
+    /// a Unix domain socket, given as `unix:<path>` on the command line
+    Unix(PathBuf),
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(addr) => write!(f, "{addr}"),
+            Endpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
 }
 
 pub trait IntoStringResult {
@@ -41,7 +66,11 @@ fn parse_args() -> eyre::Result<Args> {
         match arg {
             lexopt::Arg::Long("address") | lexopt::Arg::Short('a') => {
                 let value = parser.value()?.into_string_result()?;
-                args.server_address = Some(match value.parse() {
+                if let Some(path) = value.strip_prefix("unix:") {
+                    args.server_address = Some(Endpoint::Unix(path.into()));
+                    continue;
+                }
+                args.server_address = Some(Endpoint::Tcp(match value.parse() {
                     Ok(addr) => addr,
                     Err(_) => {
                         use std::net::ToSocketAddrs;
@@ -56,7 +85,7 @@ fn parse_args() -> eyre::Result<Args> {
                                 eyre::eyre!("Failed to parse/resolve address: {}", value)
                             })?
                     }
-                });
+                }));
             }
             lexopt::Arg::Long("connect-timeout") | lexopt::Arg::Short('t') => {
                 args.connect_timeout = Some(
@@ -70,6 +99,9 @@ fn parse_args() -> eyre::Result<Args> {
             lexopt::Arg::Long("filter") | lexopt::Arg::Short('f') => {
                 args.filter = Some(parser.value()?.into_string_result()?);
             }
+            lexopt::Arg::Long("reuse-connection") | lexopt::Arg::Short('r') => {
+                args.reuse_connection = true;
+            }
             lexopt::Arg::Value(value) => {
                 args.server_binary.push(value.into_string_result()?);
             }
@@ -84,9 +116,12 @@ fn print_usage() -> eyre::Result<()> {
         "Usage: httpwg-test-suite [OPTIONS] [-- SERVER [ARGS]]
 
 Options:
-    -a, --address <ADDRESS>    The address/port the server will listen on
+    -a, --address <ADDRESS>    The address/port the server will listen on, or
+                               unix:<PATH> for a Unix domain socket
     -t, --connect-timeout <MS> The timeout for connections in milliseconds
     -f, --filter <FILTER>      Which tests to run
+    -r, --reuse-connection     Run tests on a single connection, skipping those
+                               that need a fresh one
 
 Arguments:
     SERVER                     The server to run tests against
@@ -94,6 +129,7 @@ Arguments:
 
 Examples:
     httpwg-test-suite -a 127.0.0.1:8080 -- ./my_server
+    httpwg-test-suite -a unix:/tmp/my_server.sock -- ./my_server
     httpwg-test-suite -f 'RFC 9113' -- ./my_server --go-fast
 "
     );
@@ -117,8 +153,6 @@ fn main() -> eyre::Result<()> {
 }
 
 async fn async_main(args: Args) -> eyre::Result<()> {
-    let cat = catalog::<fluke_buffet::net::TcpStream>();
-
     let addr = match args.server_address {
         Some(addr) => addr,
         None => {
@@ -173,10 +207,40 @@ async fn async_main(args: Args) -> eyre::Result<()> {
     };
 
     eprintln!("Waiting until server is listening on {addr}");
+    match addr {
+        Endpoint::Tcp(addr) => {
+            run_suite(args.filter, args.reuse_connection, conf, move || {
+                TcpStream::connect(addr)
+            })
+            .await
+        }
+        Endpoint::Unix(path) => {
+            run_suite(args.filter, args.reuse_connection, conf, move || {
+                tokio::net::UnixStream::connect(path.clone())
+            })
+            .await
+        }
+    }
+}
+
+/// Waits for the server to listen, then runs the catalog against it, with
+/// connections from `connect`: any transport works, as long as it
+/// implements [IntoHalves].
+async fn run_suite<IO, F, Fut>(
+    filter: Option<String>,
+    reuse_connection: bool,
+    conf: Rc<Config>,
+    connect: F,
+) -> eyre::Result<()>
+where
+    IO: IntoHalves,
+    F: Fn() -> Fut,
+    Fut: Future<Output = std::io::Result<IO>>,
+{
     let start = std::time::Instant::now();
     let duration = Duration::from_secs(1);
     while start.elapsed() < duration {
-        match tokio::time::timeout(Duration::from_millis(100), TcpStream::connect(addr)).await {
+        match tokio::time::timeout(Duration::from_millis(100), connect()).await {
             Ok(Ok(_)) => break,
             _ => tokio::time::sleep(Duration::from_millis(100)).await,
         }
@@ -185,40 +249,40 @@ async fn async_main(args: Args) -> eyre::Result<()> {
         panic!("Server did not start listening within 3 seconds");
     }
 
-    let local_set = tokio::task::LocalSet::new();
-
+    let cat = catalog::<IO>();
+    let mut tests = Vec::new();
     for (rfc, sections) in cat {
-        for (section, tests) in sections {
-            for (test, boxed_test) in tests {
+        for (section, section_tests) in sections {
+            for (test, boxed_test) in section_tests {
                 let test_name = format!("{rfc} :: {section} :: {test}");
-                if let Some(filter) = &args.filter {
+                if let Some(filter) = &filter {
                     if !test_name.contains(filter) {
                         println!("Skipping test: {}", test_name);
                         continue;
                     }
                 }
-
-                let stream =
-                    tokio::time::timeout(Duration::from_millis(250), TcpStream::connect(addr))
-                        .await
-                        .unwrap()
-                        .unwrap();
-                let conn = Conn::new(conf.clone(), stream);
-                let test = async move {
-                    println!("🔷 Running test: {}", test_name);
-                    boxed_test(conn).await.unwrap();
-                    println!("✅ Test passed: {}", test_name);
-                };
-                local_set.spawn_local(async move {
-                    {
-                        test.await;
-                    }
-                });
+                tests.push((test_name, boxed_test));
             }
         }
     }
 
-    local_set.await;
+    let options = RunOptions {
+        fresh_connection_per_test: !reuse_connection,
+    };
+    let summary = run_tests(tests, &options, || {
+        let conf = conf.clone();
+        let stream = connect();
+        async move {
+            let stream = tokio::time::timeout(Duration::from_millis(250), stream).await??;
+            Ok::<_, eyre::Report>(Conn::new(conf, stream))
+        }
+    })
+    .await?;
+
+    summary.print();
+    if !summary.is_conformant() {
+        std::process::exit(1);
+    }
 
     Ok(())
 }
@@ -263,8 +327,6 @@ fn setup_tracing_and_error_reporting() {
         .with(fmt_layer)
         .init();
 }
-type BoxedTest<IO> = Box<dyn Fn(Conn<IO>) -> Pin<Box<dyn Future<Output = eyre::Result<()>>>>>;
-
 pub fn catalog<IO: IntoHalves>(
 ) -> HashMap<&'static str, HashMap<&'static str, HashMap<&'static str, BoxedTest<IO>>>> {
     let mut rfcs: HashMap<
@@ -275,27 +337,266 @@ pub fn catalog<IO: IntoHalves>(
     {
         let mut sections: HashMap<&'static str, _> = Default::default();
 
+        {
+            use rfc9113::_3_starting_http2 as s;
+            let mut section3: HashMap<&'static str, BoxedTest<IO>> = Default::default();
+            section3.insert(
+                "sends client connection preface",
+                ConformanceTest::boxed(
+                    "3.4",
+                    "The server connection preface must be the first frame the server sends",
+                    RequirementLevel::Must,
+                    |conn: Conn<IO>| Box::pin(s::sends_client_connection_preface(conn)),
+                ),
+            );
+
+            sections.insert("Section 3: Starting HTTP/2", section3);
+        }
+
         {
             use rfc9113::_8_expressing_http_semantics_in_http2 as s;
             let mut section8: HashMap<&'static str, BoxedTest<IO>> = Default::default();
             section8.insert(
                 "client sends push promise frame",
-                Box::new(|conn: Conn<IO>| Box::pin(s::client_sends_push_promise_frame(conn))),
+                ConformanceTest::boxed(
+                    "8.4",
+                    "Servers must treat a PUSH_PROMISE from a client as a connection error",
+                    RequirementLevel::Must,
+                    |conn: Conn<IO>| Box::pin(s::client_sends_push_promise_frame(conn)),
+                ),
             );
             section8.insert(
                 "sends connect with scheme",
-                Box::new(|conn: Conn<IO>| Box::pin(s::sends_connect_with_scheme(conn))),
+                ConformanceTest::boxed(
+                    "8.5",
+                    "A CONNECT request must not include the :scheme pseudo-header",
+                    RequirementLevel::Must,
+                    |conn: Conn<IO>| Box::pin(s::sends_connect_with_scheme(conn)),
+                ),
             );
             section8.insert(
                 "sends connect with path",
-                Box::new(|conn: Conn<IO>| Box::pin(s::sends_connect_with_path(conn))),
+                ConformanceTest::boxed(
+                    "8.5",
+                    "A CONNECT request must not include the :path pseudo-header",
+                    RequirementLevel::Must,
+                    |conn: Conn<IO>| Box::pin(s::sends_connect_with_path(conn)),
+                ),
             );
 
             sections.insert("Section 8: Expressing HTTP Semantics in HTTP/2", section8);
         }
 
+        {
+            use rfc9113::_6_frame_definitions as s;
+            let mut section6: HashMap<&'static str, BoxedTest<IO>> = Default::default();
+            section6.insert(
+                "sends DATA frame with zero stream id",
+                ConformanceTest::boxed(
+                    "6.1",
+                    "Servers must treat a DATA frame on stream 0 as a connection error",
+                    RequirementLevel::Must,
+                    |conn: Conn<IO>| Box::pin(s::sends_data_frame_with_zero_stream_id(conn)),
+                ),
+            );
+            section6.insert(
+                "sends HEADERS frame with zero stream id",
+                ConformanceTest::boxed(
+                    "6.2",
+                    "Servers must treat a HEADERS frame on stream 0 as a connection error",
+                    RequirementLevel::Must,
+                    |conn: Conn<IO>| Box::pin(s::sends_headers_frame_with_zero_stream_id(conn)),
+                ),
+            );
+            section6.insert(
+                "sends RST_STREAM frame with zero stream id",
+                ConformanceTest::boxed(
+                    "6.4",
+                    "Servers must treat a RST_STREAM frame on stream 0 as a connection error",
+                    RequirementLevel::Must,
+                    |conn: Conn<IO>| Box::pin(s::sends_rst_stream_frame_with_zero_stream_id(conn)),
+                ),
+            );
+            section6.insert(
+                "sends CONTINUATION frame with zero stream id",
+                ConformanceTest::boxed(
+                    "6.10",
+                    "Servers must treat a CONTINUATION frame on stream 0 as a connection error",
+                    RequirementLevel::Must,
+                    |conn: Conn<IO>| {
+                        Box::pin(s::sends_continuation_frame_with_zero_stream_id(conn))
+                    },
+                ),
+            );
+            section6.insert(
+                "sends header list exceeding SETTINGS_MAX_HEADER_LIST_SIZE",
+                ConformanceTest::boxed(
+                    "6.5.2",
+                    "Servers should reject a header list larger than their SETTINGS_MAX_HEADER_LIST_SIZE",
+                    RequirementLevel::Should,
+                    |conn: Conn<IO>| {
+                        Box::pin(s::sends_header_list_exceeding_max_header_list_size(conn))
+                    },
+                ),
+            );
+
+            section6.insert(
+                "acknowledges SETTINGS frame",
+                ShareableTest::boxed(
+                    "6.5.3",
+                    "Servers must acknowledge a SETTINGS frame once they've applied it",
+                    RequirementLevel::Must,
+                    acknowledges_settings_frame,
+                ),
+            );
+            section6.insert(
+                "acknowledges PING frame",
+                ShareableTest::boxed(
+                    "6.7",
+                    "Servers must answer a PING frame with a PING ACK with an identical payload",
+                    RequirementLevel::Must,
+                    acknowledges_ping_frame,
+                ),
+            );
+
+            sections.insert("Section 6: Frame Definitions", section6);
+        }
+
+        {
+            use rfc9113::_stream_zero as s;
+            let mut stream_zero: HashMap<&'static str, BoxedTest<IO>> = Default::default();
+            stream_zero.insert(
+                "sends DATA frame on stream 0",
+                ConformanceTest::boxed(
+                    "6.1",
+                    "Servers must treat a DATA frame on stream 0 as a connection error",
+                    RequirementLevel::Must,
+                    |conn: Conn<IO>| Box::pin(s::sends_data_frame(conn)),
+                ),
+            );
+            stream_zero.insert(
+                "sends HEADERS frame on stream 0",
+                ConformanceTest::boxed(
+                    "6.2",
+                    "Servers must treat a HEADERS frame on stream 0 as a connection error",
+                    RequirementLevel::Must,
+                    |conn: Conn<IO>| Box::pin(s::sends_headers_frame(conn)),
+                ),
+            );
+            stream_zero.insert(
+                "sends RST_STREAM frame on stream 0",
+                ConformanceTest::boxed(
+                    "6.4",
+                    "Servers must treat a RST_STREAM frame on stream 0 as a connection error",
+                    RequirementLevel::Must,
+                    |conn: Conn<IO>| Box::pin(s::sends_rst_stream_frame(conn)),
+                ),
+            );
+            stream_zero.insert(
+                "sends PUSH_PROMISE frame on stream 0",
+                ConformanceTest::boxed(
+                    "6.6",
+                    "Servers must treat a PUSH_PROMISE frame on stream 0 as a connection error",
+                    RequirementLevel::Must,
+                    |conn: Conn<IO>| Box::pin(s::sends_push_promise_frame(conn)),
+                ),
+            );
+            stream_zero.insert(
+                "sends CONTINUATION frame on stream 0",
+                ConformanceTest::boxed(
+                    "6.10",
+                    "Servers must treat a CONTINUATION frame on stream 0 as a connection error",
+                    RequirementLevel::Must,
+                    |conn: Conn<IO>| Box::pin(s::sends_continuation_frame(conn)),
+                ),
+            );
+
+            sections.insert("Stream-scoped frames on stream 0", stream_zero);
+        }
+
+        {
+            use rfc9113::_non_zero_stream as s;
+            let mut non_zero_stream: HashMap<&'static str, BoxedTest<IO>> = Default::default();
+            non_zero_stream.insert(
+                "sends SETTINGS frame on a non-zero stream",
+                ConformanceTest::boxed(
+                    "6.5",
+                    "Servers must treat a SETTINGS frame on a non-zero stream as a connection error",
+                    RequirementLevel::Must,
+                    |conn: Conn<IO>| Box::pin(s::sends_settings_frame(conn)),
+                ),
+            );
+            non_zero_stream.insert(
+                "sends PING frame on a non-zero stream",
+                ConformanceTest::boxed(
+                    "6.7",
+                    "Servers must treat a PING frame on a non-zero stream as a connection error",
+                    RequirementLevel::Must,
+                    |conn: Conn<IO>| Box::pin(s::sends_ping_frame(conn)),
+                ),
+            );
+            non_zero_stream.insert(
+                "sends GOAWAY frame on a non-zero stream",
+                ConformanceTest::boxed(
+                    "6.8",
+                    "Servers must treat a GOAWAY frame on a non-zero stream as a connection error",
+                    RequirementLevel::Must,
+                    |conn: Conn<IO>| Box::pin(s::sends_goaway_frame(conn)),
+                ),
+            );
+
+            sections.insert(
+                "Connection-scoped frames on a non-zero stream",
+                non_zero_stream,
+            );
+        }
+
         rfcs.insert("RFC 9113", sections);
     }
 
     rfcs
 }
+
+/// Sends a SETTINGS frame that changes nothing, and expects it to be
+/// acknowledged. Leaves the connection usable, see [ShareableTest].
+fn acknowledges_settings_frame<IO: IntoHalves>(
+    conn: &mut Conn<IO>,
+) -> BoxedFuture<'_, eyre::Result<()>> {
+    Box::pin(async move {
+        conn.write_settings(rfc9113::default_settings()).await?;
+        match conn.wait_for_frame(FrameT::Settings).await {
+            FrameWaitOutcome::Success(frame, _) if frame.is_ack() => Ok(()),
+            FrameWaitOutcome::Success(..) => Err(eyre::eyre!(
+                "expected a SETTINGS ACK, got SETTINGS without the ACK flag"
+            )),
+            _ => Err(eyre::eyre!("server didn't acknowledge our SETTINGS")),
+        }
+    })
+}
+
+/// Sends a PING frame, and expects a PING ACK with the same payload back.
+/// Leaves the connection usable, see [ShareableTest].
+fn acknowledges_ping_frame<IO: IntoHalves>(
+    conn: &mut Conn<IO>,
+) -> BoxedFuture<'_, eyre::Result<()>> {
+    Box::pin(async move {
+        let data = b"httpwg\0\0";
+        conn.write_ping(false, data.to_vec()).await?;
+        match conn.wait_for_frame(FrameT::Ping).await {
+            FrameWaitOutcome::Success(frame, payload) => {
+                if !frame.is_ack() {
+                    return Err(eyre::eyre!("expected a PING ACK, got a PING"));
+                }
+                if &payload[..] != data {
+                    return Err(eyre::eyre!(
+                        "PING ACK payload {:?} doesn't match the PING's {:?}",
+                        &payload[..],
+                        data
+                    ));
+                }
+                Ok(())
+            }
+            _ => Err(eyre::eyre!("server didn't answer our PING")),
+        }
+    })
+}
@@ -0,0 +1,517 @@
+//! Runs tests from the catalog against a server
+
+use std::{cell::RefCell, future::Future, pin::Pin, rc::Rc};
+
+use fluke_buffet::IntoHalves;
+use httpwg::Conn;
+
+pub type BoxedFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+pub type BoxedTest<IO> = Box<dyn Test<IO>>;
+
+/// How binding the requirement a test checks is, after the RFC 2119 keyword
+/// the RFC uses for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RequirementLevel {
+    /// MUST / MUST NOT: failing this is a conformance failure
+    #[default]
+    Must,
+    /// SHOULD / SHOULD NOT: failing this is worth a look, but allowed
+    Should,
+    /// MAY: failing this is purely informational
+    May,
+}
+
+/// Something the runner can run against a server
+pub trait Test<IO: IntoHalves> {
+    /// Runs the test on a connection of its own
+    fn run(&self, conn: Conn<IO>) -> BoxedFuture<'static, eyre::Result<()>>;
+
+    /// The section of the RFC the test covers, e.g. "6.5.3", or an empty
+    /// string if it doesn't map to one. Reports cite it next to the test
+    /// name.
+    fn rfc_section(&self) -> &'static str {
+        ""
+    }
+
+    /// What the test does, in a sentence, or an empty string.
+    fn description(&self) -> &'static str {
+        ""
+    }
+
+    /// The requirement from [Test::rfc_section] the test checks, in a
+    /// sentence (e.g. "Servers must treat a SETTINGS frame on a non-zero
+    /// stream as a connection error"), or an empty string.
+    fn requirement(&self) -> &'static str {
+        ""
+    }
+
+    /// How binding the requirement the test checks is. Failures are only
+    /// reported as hard failures for [RequirementLevel::Must].
+    fn requirement_level(&self) -> RequirementLevel {
+        RequirementLevel::Must
+    }
+
+    /// Whether the test needs a connection of its own. HTTP/2 state is
+    /// connection-scoped, so that's the default: only tests that leave the
+    /// connection usable for whatever comes next should return false.
+    fn requires_fresh_conn(&self) -> bool {
+        true
+    }
+
+    /// Runs the test on a connection shared with other tests, which has
+    /// already gone through the handshake. Only called if
+    /// [Test::requires_fresh_conn] returns false.
+    fn run_shared<'a>(&'a self, _conn: &'a mut Conn<IO>) -> BoxedFuture<'a, eyre::Result<()>> {
+        Box::pin(async { Err(eyre::eyre!("this test needs a connection of its own")) })
+    }
+}
+
+/// A test from the conformance suite. Those take the connection by value
+/// and start with a handshake, so they always need a fresh connection.
+pub struct ConformanceTest<IO: IntoHalves> {
+    rfc_section: &'static str,
+    requirement: &'static str,
+    requirement_level: RequirementLevel,
+    run: Box<dyn Fn(Conn<IO>) -> BoxedFuture<'static, eyre::Result<()>>>,
+}
+
+impl<IO: IntoHalves> ConformanceTest<IO> {
+    pub fn boxed(
+        rfc_section: &'static str,
+        requirement: &'static str,
+        requirement_level: RequirementLevel,
+        run: impl Fn(Conn<IO>) -> BoxedFuture<'static, eyre::Result<()>> + 'static,
+    ) -> BoxedTest<IO> {
+        Box::new(Self {
+            rfc_section,
+            requirement,
+            requirement_level,
+            run: Box::new(run),
+        })
+    }
+}
+
+impl<IO: IntoHalves> Test<IO> for ConformanceTest<IO> {
+    fn run(&self, conn: Conn<IO>) -> BoxedFuture<'static, eyre::Result<()>> {
+        (self.run)(conn)
+    }
+
+    fn rfc_section(&self) -> &'static str {
+        self.rfc_section
+    }
+
+    fn requirement(&self) -> &'static str {
+        self.requirement
+    }
+
+    fn requirement_level(&self) -> RequirementLevel {
+        self.requirement_level
+    }
+}
+
+/// What a [ShareableTest] runs, on a connection that has already gone
+/// through the handshake.
+pub type SharedRun<IO> = for<'a> fn(&'a mut Conn<IO>) -> BoxedFuture<'a, eyre::Result<()>>;
+
+/// A check that leaves the connection usable for whatever comes next, so it
+/// can also run on a connection shared with other tests. On a connection of
+/// its own, it starts with a handshake.
+pub struct ShareableTest<IO: IntoHalves> {
+    rfc_section: &'static str,
+    requirement: &'static str,
+    requirement_level: RequirementLevel,
+    run: SharedRun<IO>,
+}
+
+impl<IO: IntoHalves> ShareableTest<IO> {
+    pub fn boxed(
+        rfc_section: &'static str,
+        requirement: &'static str,
+        requirement_level: RequirementLevel,
+        run: SharedRun<IO>,
+    ) -> BoxedTest<IO> {
+        Box::new(Self {
+            rfc_section,
+            requirement,
+            requirement_level,
+            run,
+        })
+    }
+}
+
+impl<IO: IntoHalves> Test<IO> for ShareableTest<IO> {
+    fn run(&self, mut conn: Conn<IO>) -> BoxedFuture<'static, eyre::Result<()>> {
+        let run = self.run;
+        Box::pin(async move {
+            conn.handshake().await?;
+            run(&mut conn).await
+        })
+    }
+
+    fn rfc_section(&self) -> &'static str {
+        self.rfc_section
+    }
+
+    fn requirement(&self) -> &'static str {
+        self.requirement
+    }
+
+    fn requirement_level(&self) -> RequirementLevel {
+        self.requirement_level
+    }
+
+    fn requires_fresh_conn(&self) -> bool {
+        false
+    }
+
+    fn run_shared<'a>(&'a self, conn: &'a mut Conn<IO>) -> BoxedFuture<'a, eyre::Result<()>> {
+        (self.run)(conn)
+    }
+}
+
+/// Formats a test name along with its description, and the RFC section and
+/// requirement it checks, when it has those.
+fn annotated_name<IO: IntoHalves>(test_name: &str, test: &dyn Test<IO>) -> String {
+    let mut s = test_name.to_string();
+    if !test.rfc_section().is_empty() {
+        s.push_str(&format!(" (§{})", test.rfc_section()));
+    }
+    if !test.description().is_empty() {
+        s.push_str(&format!("\n    {}", test.description()));
+    }
+    if !test.requirement().is_empty() {
+        s.push_str(&format!("\n    requirement: {}", test.requirement()));
+    }
+    s
+}
+
+/// What happened when running a set of tests, see [run_tests]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub passed: usize,
+    /// tests that weren't run, e.g. because they need a fresh connection
+    pub skipped: usize,
+    /// failed tests for MUST requirements: the server isn't conformant
+    pub failed_must: usize,
+    /// failed tests for SHOULD requirements, which are advisory
+    pub failed_should: usize,
+    /// failed tests for MAY requirements, which are informational
+    pub failed_may: usize,
+}
+
+impl Summary {
+    fn record(&mut self, test_name: &str, level: RequirementLevel, res: eyre::Result<()>) {
+        match res {
+            Ok(()) => {
+                println!("✅ Test passed: {}", test_name);
+                self.passed += 1;
+            }
+            Err(e) => {
+                println!("❌ Test failed ({level:?}): {test_name}\n{e:?}");
+                match level {
+                    RequirementLevel::Must => self.failed_must += 1,
+                    RequirementLevel::Should => self.failed_should += 1,
+                    RequirementLevel::May => self.failed_may += 1,
+                }
+            }
+        }
+    }
+
+    /// Whether every MUST requirement was met.
+    pub fn is_conformant(&self) -> bool {
+        self.failed_must == 0
+    }
+
+    pub fn print(&self) {
+        println!(
+            "{} passed, {} skipped, {} failed (MUST), {} failed (SHOULD), {} failed (MAY)",
+            self.passed, self.skipped, self.failed_must, self.failed_should, self.failed_may
+        );
+    }
+}
+
+/// Options for [run_tests]
+pub struct RunOptions {
+    /// Whether each test gets a connection of its own. When false, a single
+    /// connection is shared by all tests, which is faster but less isolated:
+    /// tests that require a fresh connection are skipped.
+    pub fresh_connection_per_test: bool,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            fresh_connection_per_test: true,
+        }
+    }
+}
+
+/// Runs the given tests, getting connections from `connect`. With a fresh
+/// connection per test, tests run concurrently. Otherwise they run one after
+/// the other on a single connection.
+///
+/// Test failures don't stop the run: they're counted in the returned
+/// [Summary], by requirement level. Errors are for failing to connect, and
+/// for asking to share a connection between tests none of which can.
+pub async fn run_tests<IO, F, Fut>(
+    tests: Vec<(String, BoxedTest<IO>)>,
+    options: &RunOptions,
+    connect: F,
+) -> eyre::Result<Summary>
+where
+    IO: IntoHalves,
+    F: Fn() -> Fut,
+    Fut: Future<Output = eyre::Result<Conn<IO>>>,
+{
+    let summary: Rc<RefCell<Summary>> = Default::default();
+
+    if options.fresh_connection_per_test {
+        let local_set = tokio::task::LocalSet::new();
+
+        for (test_name, test) in tests {
+            let conn = connect().await?;
+            let summary = summary.clone();
+            local_set.spawn_local(async move {
+                println!("🔷 Running test: {}", annotated_name(&test_name, &*test));
+                let res = test.run(conn).await;
+                summary
+                    .borrow_mut()
+                    .record(&test_name, test.requirement_level(), res);
+            });
+        }
+
+        local_set.await;
+    } else {
+        if !tests.is_empty() && tests.iter().all(|(_, test)| test.requires_fresh_conn()) {
+            return Err(eyre::eyre!(
+                "none of the {} selected tests can run on a shared connection",
+                tests.len()
+            ));
+        }
+
+        let mut conn = connect().await?;
+        conn.handshake().await?;
+
+        for (test_name, test) in tests {
+            if test.requires_fresh_conn() {
+                println!("Skipping test (needs a fresh connection): {}", test_name);
+                summary.borrow_mut().skipped += 1;
+                continue;
+            }
+
+            conn.reset_test_state();
+            println!("🔷 Running test: {}", annotated_name(&test_name, &*test));
+            let res = test.run_shared(&mut conn).await;
+            summary
+                .borrow_mut()
+                .record(&test_name, test.requirement_level(), res);
+        }
+    }
+
+    let summary = *summary.borrow();
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use fluke_buffet::IntoHalves;
+    use fluke_h2_parse::{Frame, FrameType, PingFlags, StreamId, PREFACE};
+    use httpwg::{rfc9113::default_settings, Config, Conn, DuplexIo, DuplexPeer};
+
+    use super::{
+        annotated_name, run_tests, BoxedFuture, BoxedTest, ConformanceTest, RequirementLevel,
+        RunOptions, ShareableTest, Summary, Test,
+    };
+
+    struct SendsPing;
+
+    impl<IO: IntoHalves> Test<IO> for SendsPing {
+        fn run(&self, mut conn: Conn<IO>) -> BoxedFuture<'static, eyre::Result<()>> {
+            Box::pin(async move { conn.write_ping(false, vec![0u8; 8]).await })
+        }
+
+        fn description(&self) -> &'static str {
+            "Sends a PING frame, without waiting for the ACK"
+        }
+    }
+
+    /// The runner isn't tied to TCP: here the in-memory duplex stands in for
+    /// another transport, like a Unix domain socket.
+    #[test]
+    fn runs_tests_over_any_transport() {
+        fluke_buffet::start(async move {
+            let tests: Vec<(String, BoxedTest<DuplexIo>)> =
+                vec![("sends ping".into(), Box::new(SendsPing))];
+            let summary = run_tests(tests, &RunOptions::default(), || async {
+                let (io, mut peer) = DuplexIo::new();
+                fluke_buffet::spawn(async move { peer.drain().await.unwrap() });
+                Ok(Conn::new(Rc::new(Config::default()), io))
+            })
+            .await
+            .unwrap();
+            assert_eq!(summary.passed, 1);
+            assert!(summary.is_conformant());
+        });
+    }
+
+    /// Reads from the `Conn` until it has written `len` more bytes.
+    async fn recv_exactly(peer: &mut DuplexPeer, len: usize) {
+        let mut received = 0;
+        while received < len {
+            received += peer.recv().await.unwrap().unwrap().len();
+        }
+    }
+
+    #[test]
+    fn runs_tests_on_a_shared_connection() {
+        fluke_buffet::start(async move {
+            let tests: Vec<(String, BoxedTest<DuplexIo>)> = vec![
+                (
+                    "acknowledges PING frame".into(),
+                    ShareableTest::boxed(
+                        "6.7",
+                        "",
+                        RequirementLevel::Must,
+                        crate::acknowledges_ping_frame,
+                    ),
+                ),
+                (
+                    "acknowledges SETTINGS frame".into(),
+                    ShareableTest::boxed(
+                        "6.5.3",
+                        "",
+                        RequirementLevel::Must,
+                        crate::acknowledges_settings_frame,
+                    ),
+                ),
+            ];
+            let options = RunOptions {
+                fresh_connection_per_test: false,
+            };
+            let connects = Rc::new(Cell::new(0));
+            let summary = run_tests(tests, &options, || {
+                connects.set(connects.get() + 1);
+                async {
+                    let (io, mut peer) = DuplexIo::new();
+                    fluke_buffet::spawn(async move {
+                        let settings_len = 9 + default_settings().0.len() * 6;
+                        let settings = Frame::new(
+                            FrameType::Settings(Default::default()),
+                            StreamId::CONNECTION,
+                        );
+
+                        // handshake
+                        recv_exactly(&mut peer, PREFACE.len() + settings_len).await;
+                        peer.send_frame(settings, ()).await.unwrap();
+                        recv_exactly(&mut peer, 9).await;
+                        peer.send_frame(Frame::settings_ack(), ()).await.unwrap();
+
+                        // first test
+                        recv_exactly(&mut peer, 9 + 8).await;
+                        let pong = Frame::new(
+                            FrameType::Ping(PingFlags::Ack.into()),
+                            StreamId::CONNECTION,
+                        );
+                        peer.send_frame(pong, b"httpwg\0\0".to_vec()).await.unwrap();
+
+                        // second test
+                        recv_exactly(&mut peer, settings_len).await;
+                        peer.send_frame(Frame::settings_ack(), ()).await.unwrap();
+
+                        peer.drain().await.unwrap();
+                    });
+                    Ok(Conn::new(Rc::new(Config::default()), io))
+                }
+            })
+            .await
+            .unwrap();
+            assert_eq!(connects.get(), 1);
+            assert_eq!(summary.passed, 2);
+            assert!(summary.is_conformant());
+        });
+    }
+
+    #[test]
+    fn refuses_to_share_a_connection_no_test_can_use() {
+        fluke_buffet::start(async move {
+            let tests: Vec<(String, BoxedTest<DuplexIo>)> =
+                vec![("sends ping".into(), Box::new(SendsPing))];
+            let options = RunOptions {
+                fresh_connection_per_test: false,
+            };
+            let res = run_tests(tests, &options, || async {
+                let (io, _peer) = DuplexIo::new();
+                Ok(Conn::new(Rc::new(Config::default()), io))
+            })
+            .await;
+            assert!(res.is_err());
+        });
+    }
+
+    #[test]
+    fn cites_rfc_section_and_requirement() {
+        let test: BoxedTest<DuplexIo> = ConformanceTest::boxed(
+            "3.4",
+            "The server connection preface must be the first frame the server sends",
+            RequirementLevel::Must,
+            |conn: Conn<DuplexIo>| {
+                Box::pin(httpwg::rfc9113::_3_starting_http2::sends_client_connection_preface(conn))
+            },
+        );
+        assert_eq!(test.rfc_section(), "3.4");
+        assert_eq!(test.requirement_level(), RequirementLevel::Must);
+        assert_eq!(
+            annotated_name("sends client connection preface", &*test),
+            "sends client connection preface (§3.4)\n    requirement: The server connection preface must be the first frame the server sends"
+        );
+    }
+
+    #[test]
+    fn annotates_with_description() {
+        let test: BoxedTest<DuplexIo> = Box::new(SendsPing);
+        assert_eq!(test.requirement(), "");
+        assert_eq!(
+            annotated_name("sends ping", &*test),
+            "sends ping\n    Sends a PING frame, without waiting for the ACK"
+        );
+    }
+
+    #[test]
+    fn counts_failures_by_requirement_level() {
+        let mut summary = Summary::default();
+        summary.record("passes", RequirementLevel::Must, Ok(()));
+        summary.record(
+            "fails a SHOULD",
+            RequirementLevel::Should,
+            Err(eyre::eyre!("no")),
+        );
+        summary.record("fails a MAY", RequirementLevel::May, Err(eyre::eyre!("no")));
+        summary.record(
+            "fails a MAY too",
+            RequirementLevel::May,
+            Err(eyre::eyre!("no")),
+        );
+        assert_eq!(
+            summary,
+            Summary {
+                passed: 1,
+                failed_should: 1,
+                failed_may: 2,
+                ..Default::default()
+            }
+        );
+        // advisory failures don't make a server non-conformant
+        assert!(summary.is_conformant());
+
+        summary.record(
+            "fails a MUST",
+            RequirementLevel::Must,
+            Err(eyre::eyre!("no")),
+        );
+        assert_eq!(summary.failed_must, 1);
+        assert!(!summary.is_conformant());
+    }
+}
@@ -26,6 +26,42 @@ macro_rules! tests {
 mod rfc9113 {
 use ::httpwg::rfc9113 as __suite;
 
+/// Section 10: Security Considerations
+mod _10_security_considerations {
+use super::__suite::_10_security_considerations as __group;
+
+/// An endpoint that doesn't monitor use of these features exposes itself
+/// to a risk of denial of service. Implementations SHOULD track the use of
+/// these features and set limits on their use.
+///
+/// (This is the "Rapid Reset" attack, CVE-2023-44487: streams are opened and
+/// cancelled right away, so they never count against
+/// SETTINGS_MAX_CONCURRENT_STREAMS, but the server may still do work for each
+/// of them. The server passes if it limits the flood, with a GOAWAY carrying
+/// ENHANCE_YOUR_CALM or NO_ERROR, or if it still answers a PING afterwards.
+/// A server that never limits it only passes as long as it keeps up: one
+/// that falls behind fails this test.)
+#[test]
+fn sends_rapid_resets() {
+use __group::sends_rapid_resets as test;
+$body
+}
+
+/// An HTTP/2 connection can demand a greater commitment of resources to
+/// operate than an HTTP/1.1 connection.
+///
+/// (This checks the other direction: a small request shouldn't make the
+/// server send back more than `Config::max_amplification_factor`
+/// times as many bytes, counting frame headers and payloads on both sides,
+/// but not the handshake. A server that does can be used to amplify
+/// traffic.)
+#[test]
+fn small_request_is_not_amplified() {
+use __group::small_request_is_not_amplified as test;
+$body
+}
+}
+
 /// Section 3: Starting HTTP/2
 mod _3_starting_http2 {
 use super::__suite::_3_starting_http2 as __group;
@@ -39,6 +75,17 @@ use __group::sends_client_connection_preface as test;
 $body
 }
 
+/// That is, the connection preface starts with the string
+/// "PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n".
+///
+/// (Nothing says it arrives in a single read: here it's split in two, with
+/// a pause in between.)
+#[test]
+fn sends_client_connection_preface_in_fragments() {
+use __group::sends_client_connection_preface_in_fragments as test;
+$body
+}
+
 /// Clients and servers MUST treat an invalid connection preface as
 /// a connection error (Section 5.4.1) of type PROTOCOL_ERROR.
 #[test]
@@ -46,6 +93,16 @@ fn sends_invalid_connection_preface() {
 use __group::sends_invalid_connection_preface as test;
 $body
 }
+
+/// Clients and servers MUST treat an invalid connection preface as
+/// a connection error (Section 5.4.1) of type PROTOCOL_ERROR.
+///
+/// This one is only a byte away from the real preface.
+#[test]
+fn sends_connection_preface_with_wrong_version() {
+use __group::sends_connection_preface_with_wrong_version as test;
+$body
+}
 }
 
 /// Section 4: HTTP Frames
@@ -76,12 +133,33 @@ use __group::sends_frame_with_reserved_bit_set as test;
 $body
 }
 
+/// The reserved bit MUST be ignored when receiving: a SETTINGS frame that
+/// has it set must still be processed normally, and acknowledged.
+#[test]
+fn sends_settings_frame_with_reserved_bit_set() {
+use __group::sends_settings_frame_with_reserved_bit_set as test;
+$body
+}
+
 #[test]
 fn data_frame_with_max_length() {
 use __group::data_frame_with_max_length as test;
 $body
 }
 
+/// The size of a frame payload is limited by the maximum size that a
+/// receiver advertises in the SETTINGS_MAX_FRAME_SIZE setting.
+///
+/// (Right at the limit is where off-by-one errors hide: a DATA frame of
+/// exactly the SETTINGS_MAX_FRAME_SIZE the server advertised must be
+/// accepted, and the request served with a successful status. The frame
+/// must fit in the flow-control windows too.)
+#[test]
+fn data_frame_with_exact_advertised_max_length() {
+use __group::data_frame_with_exact_advertised_max_length as test;
+$body
+}
+
 /// An endpoint MUST send an error code of FRAME_SIZE_ERROR if a frame
 /// exceeds the size defined in SETTINGS_MAX_FRAME_SIZE, exceeds any
 /// limit defined for the frame type, or is too small to contain mandatory frame
@@ -267,6 +345,17 @@ use __group::closed_sends_continuation_frame as test;
 $body
 }
 
+/// closed:
+/// An endpoint MUST NOT send frames other than PRIORITY on a closed
+/// stream.
+///
+/// (The RST_STREAM is provoked with a malformed request.)
+#[test]
+fn closed_after_server_rst_stream() {
+use __group::closed_after_server_rst_stream as test;
+$body
+}
+
 /// An endpoint that receives an unexpected stream identifier
 /// MUST respond with a connection error (Section 5.4.1) of
 /// type PROTOCOL_ERROR.
@@ -313,6 +402,47 @@ fn unknown_extension_frame_in_header_block() {
 use __group::unknown_extension_frame_in_header_block as test;
 $body
 }
+
+/// Implementations MUST ignore unknown or unsupported values in all
+/// extensible protocol elements. Implementations MUST discard frames
+/// that have unknown or unsupported types.
+///
+/// (Every reserved GREASE type and identifier, plus `Config::grease_seed`.)
+#[test]
+fn sends_grease_frame_and_setting() {
+use __group::sends_grease_frame_and_setting as test;
+$body
+}
+
+/// Implementations MUST discard frames that have unknown or unsupported
+/// types.
+///
+/// (Here between the HEADERS and DATA of a request, which must be served.)
+#[test]
+fn unknown_frame_on_open_stream() {
+use __group::unknown_frame_on_open_stream as test;
+$body
+}
+
+/// Implementations MUST discard frames that have unknown or unsupported
+/// types.
+///
+/// (An RFC 9218 extension frame: the request it's about must be served.)
+#[test]
+fn sends_priority_update_frame() {
+use __group::sends_priority_update_frame as test;
+$body
+}
+
+/// Implementations MUST discard frames that have unknown or unsupported
+/// types.
+///
+/// (RFC 9218, Section 4: out-of-range values MUST be ignored.)
+#[test]
+fn sends_priority_update_frame_with_out_of_range_urgency() {
+use __group::sends_priority_update_frame_with_out_of_range_urgency as test;
+$body
+}
 }
 
 /// Section 6: Frame Definitions
@@ -340,6 +470,16 @@ use __group::sends_data_frame_on_invalid_stream_state as test;
 $body
 }
 
+/// DATA frames MAY also contain padding. Padding can be added to DATA
+/// frames to obscure the size of messages.
+///
+/// (Padding doesn't count towards content-length.)
+#[test]
+fn sends_data_frame_with_padding() {
+use __group::sends_data_frame_with_padding as test;
+$body
+}
+
 /// If the length of the padding is the length of the frame payload
 /// or greater, the recipient MUST treat this as a connection error
 /// (Section 5.4.1) of type PROTOCOL_ERROR.
@@ -430,6 +570,21 @@ use __group::sends_settings_frame_with_ack_and_payload as test;
 $body
 }
 
+/// ACK (0x1):
+/// When set, bit 0 indicates that this frame acknowledges receipt
+/// and application of the peer's SETTINGS frame. When this bit is
+/// set, the payload of the SETTINGS frame MUST be empty. Receipt of
+/// a SETTINGS frame with the ACK flag set and a length field value
+/// other than 0 MUST be treated as a connection error (Section 5.4.1)
+/// of type FRAME_SIZE_ERROR.
+///
+/// (Unlike the 1-byte payload above, this is a well-formed setting.)
+#[test]
+fn sends_settings_frame_with_ack_and_setting_payload() {
+use __group::sends_settings_frame_with_ack_and_setting_payload as test;
+$body
+}
+
 /// SETTINGS frames always apply to a connection, never a single
 /// stream. The stream identifier for a SETTINGS frame MUST be
 /// zero (0x0). If an endpoint receives a SETTINGS frame whose
@@ -499,6 +654,17 @@ use __group::sends_settings_max_frame_size_with_invalid_value_above_max as test;
 $body
 }
 
+/// SETTINGS_MAX_HEADER_LIST_SIZE (0x6):
+/// This advisory setting informs a peer of the maximum field section
+/// size that the sender is prepared to accept, in units of octets.
+///
+/// (cf. Section 10.5.1. Skipped if the server advertises no limit, or 0.)
+#[test]
+fn sends_header_list_exceeding_max_header_list_size() {
+use __group::sends_header_list_exceeding_max_header_list_size as test;
+$body
+}
+
 /// An endpoint that receives a SETTINGS frame with any unknown
 /// or unsupported identifier MUST ignore that setting.
 #[test]
@@ -515,6 +681,29 @@ use __group::sends_multiple_values_of_settings_initial_window_size as test;
 $body
 }
 
+/// The values in the SETTINGS frame MUST be processed in the order
+/// they appear, with no other frame processing between values.
+///
+/// (If the server kept the first value, it would only send 1 byte.)
+#[test]
+fn sends_multiple_values_of_settings_initial_window_size_last_one_wins() {
+use __group::sends_multiple_values_of_settings_initial_window_size_last_one_wins as test;
+$body
+}
+
+/// Most values in SETTINGS benefit from or require an understanding of
+/// when the peer has received and applied the changed parameter values.
+/// In order to provide such synchronization timepoints, the recipient of
+/// a SETTINGS frame in which the ACK flag is not set MUST apply the
+/// updated settings as soon as possible upon receipt.
+///
+/// (The request goes out in the same write as the SETTINGS frame.)
+#[test]
+fn sends_request_right_behind_settings_frame() {
+use __group::sends_request_right_behind_settings_frame as test;
+$body
+}
+
 /// Once all values have been processed, the recipient MUST
 /// immediately emit a SETTINGS frame with the ACK flag set.
 #[test]
@@ -523,6 +712,26 @@ use __group::sends_settings_frame_without_ack_flag as test;
 $body
 }
 
+/// Once all values have been processed, the recipient MUST
+/// immediately emit a SETTINGS frame with the ACK flag set.
+///
+/// (Our initial SETTINGS frame must be acknowledged exactly once.)
+#[test]
+fn acknowledges_settings_frame_exactly_once() {
+use __group::acknowledges_settings_frame_exactly_once as test;
+$body
+}
+
+/// Once all values have been processed, the recipient MUST
+/// immediately emit a SETTINGS frame with the ACK flag set.
+///
+/// (A SETTINGS frame without any parameter must be acknowledged too.)
+#[test]
+fn sends_empty_settings_frame() {
+use __group::sends_empty_settings_frame as test;
+$body
+}
+
 /// Receivers of a PING frame that does not include an ACK flag MUST
 /// send a PING frame with the ACK flag set in response, with an
 /// identical payload.
@@ -570,6 +779,17 @@ use __group::sends_goaway_frame_with_non_zero_stream_id as test;
 $body
 }
 
+/// Once the GOAWAY is sent, the sender will ignore frames sent on
+/// streams initiated by the receiver if the stream has an identifier
+/// higher than the included last stream identifier.
+///
+/// (Refusing it with REFUSED_STREAM, or hanging up, passes too.)
+#[test]
+fn sends_request_above_goaway_last_stream_id() {
+use __group::sends_request_above_goaway_last_stream_id as test;
+$body
+}
+
 /// A receiver MUST treat the receipt of a WINDOW_UPDATE frame with
 /// a flow-control window increment of 0 as a stream error
 /// (Section 5.4.2) of type PROTOCOL_ERROR; errors on the connection
@@ -610,6 +830,17 @@ use __group::sends_settings_frame_to_set_initial_window_size_to_1_and_sends_head
 $body
 }
 
+/// The sender MUST NOT send a flow-controlled frame with a length
+/// that exceeds the space available in either of the flow-control
+/// windows advertised by the receiver.
+///
+/// (Skipped if the server keeps replenishing the window as it's filled.)
+#[test]
+fn sends_data_frames_exceeding_connection_flow_control_window() {
+use __group::sends_data_frames_exceeding_connection_flow_control_window as test;
+$body
+}
+
 /// A sender MUST NOT allow a flow-control window to exceed 2^31-1
 /// octets. If a sender receives a WINDOW_UPDATE that causes a
 /// flow-control window to exceed this maximum, it MUST terminate
@@ -687,6 +918,17 @@ use __group::sends_continuation_frame_followed_by_non_continuation_frame as test
 $body
 }
 
+/// END_HEADERS (0x4):
+/// If the END_HEADERS bit is not set, this frame MUST be followed
+/// by another CONTINUATION frame. A receiver MUST treat the receipt
+/// of any other type of frame or a frame on a different stream as
+/// a connection error (Section 5.4.1) of type PROTOCOL_ERROR.
+#[test]
+fn sends_headers_frame_followed_by_frame_on_another_stream() {
+use __group::sends_headers_frame_followed_by_frame_on_another_stream as test;
+$body
+}
+
 /// CONTINUATION frames MUST be associated with a stream. If a
 /// CONTINUATION frame is received whose stream identifier field is
 /// 0x0, the recipient MUST respond with a connection error
@@ -1022,6 +1264,17 @@ use __group::sends_headers_frame_with_te_not_trailers as test;
 $body
 }
 
+/// The only exception to this is the TE header field, which MAY be present in
+/// an HTTP/2 request; when it is, it MUST NOT contain any value other than
+/// "trailers".
+///
+/// (A list that merely includes "trailers" is malformed too.)
+#[test]
+fn sends_headers_frame_with_te_trailers_and_gzip() {
+use __group::sends_headers_frame_with_te_trailers_and_gzip as test;
+$body
+}
+
 /// [...] pseudo-header fields defined for responses MUST NOT appear in requests
 /// [...] Endpoints MUST treat a request or response that contains undefined or
 /// invalid pseudo-header fields as malformed (Section 8.1.1).
@@ -1116,6 +1369,16 @@ use __group::client_sends_push_promise_frame as test;
 $body
 }
 
+/// A client can use the SETTINGS_MAX_CONCURRENT_STREAMS setting to limit the
+/// number of responses that can be concurrently pushed by a server.
+///
+/// Servers that don't push pass trivially.
+#[test]
+fn server_respects_max_concurrent_pushes() {
+use __group::server_respects_max_concurrent_pushes as test;
+$body
+}
+
 /// The CONNECT method (Section 9.3.6 of [HTTP]) is used to convert an HTTP
 /// connection into a tunnel to a remote host. CONNECT is primarily used with
 /// HTTP proxies to establish a TLS session with an origin server for the
@@ -1157,6 +1420,105 @@ use __group::sends_headers_frame_with_pseudo_headers_after_regular_headers as te
 $body
 }
 }
+
+/// Connection-scoped frames on a non-zero stream
+///
+/// SETTINGS, PING and GOAWAY frames apply to the connection as a whole:
+/// receiving one on a stream other than 0x0 is a connection error of type
+/// PROTOCOL_ERROR. This is the mirror of the stream-zero suite.
+mod _non_zero_stream {
+use super::__suite::_non_zero_stream as __group;
+
+/// SETTINGS frames always apply to a connection, never a single stream.
+/// The stream identifier for a SETTINGS frame MUST be zero (0x0). If an
+/// endpoint receives a SETTINGS frame whose stream identifier field is
+/// anything other than 0x0, the endpoint MUST respond with a connection
+/// error (Section 5.4.1) of type PROTOCOL_ERROR. (Section 6.5)
+#[test]
+fn sends_settings_frame() {
+use __group::sends_settings_frame as test;
+$body
+}
+
+/// PING frames are not associated with any individual stream. If a PING
+/// frame is received with a Stream Identifier field value other than 0x0,
+/// the recipient MUST respond with a connection error (Section 5.4.1) of
+/// type PROTOCOL_ERROR. (Section 6.7)
+#[test]
+fn sends_ping_frame() {
+use __group::sends_ping_frame as test;
+$body
+}
+
+/// The GOAWAY frame applies to the connection, not a specific stream. An
+/// endpoint MUST treat a GOAWAY frame with a stream identifier other than
+/// 0x0 as a connection error (Section 5.4.1) of type PROTOCOL_ERROR.
+/// (Section 6.8)
+#[test]
+fn sends_goaway_frame() {
+use __group::sends_goaway_frame as test;
+$body
+}
+}
+
+/// Stream-scoped frames on stream 0
+///
+/// DATA, HEADERS, RST_STREAM, PUSH_PROMISE and CONTINUATION frames are
+/// always associated with a stream: receiving one on stream 0x0 is a
+/// connection error of type PROTOCOL_ERROR. Section 6 states the rule once
+/// per frame type, this checks it for each of them the same way.
+mod _stream_zero {
+use super::__suite::_stream_zero as __group;
+
+/// DATA frames MUST be associated with a stream. If a DATA frame is
+/// received whose stream identifier field is 0x0, the recipient MUST
+/// respond with a connection error (Section 5.4.1) of type
+/// PROTOCOL_ERROR. (Section 6.1)
+#[test]
+fn sends_data_frame() {
+use __group::sends_data_frame as test;
+$body
+}
+
+/// If a HEADERS frame is received whose stream identifier field is 0x0,
+/// the recipient MUST respond with a connection error (Section 5.4.1) of
+/// type PROTOCOL_ERROR. (Section 6.2)
+#[test]
+fn sends_headers_frame() {
+use __group::sends_headers_frame as test;
+$body
+}
+
+/// If a RST_STREAM frame is received with a stream identifier of 0x0,
+/// the recipient MUST treat this as a connection error (Section 5.4.1)
+/// of type PROTOCOL_ERROR. (Section 6.4)
+#[test]
+fn sends_rst_stream_frame() {
+use __group::sends_rst_stream_frame as test;
+$body
+}
+
+/// If the stream identifier field specifies the value 0x0, a recipient
+/// MUST respond with a connection error (Section 5.4.1) of type
+/// PROTOCOL_ERROR. (Section 6.6)
+///
+/// (Clients can't push either, so a server has two reasons to reject this.)
+#[test]
+fn sends_push_promise_frame() {
+use __group::sends_push_promise_frame as test;
+$body
+}
+
+/// CONTINUATION frames MUST be associated with a stream. If a
+/// CONTINUATION frame is received whose stream identifier field is 0x0,
+/// the recipient MUST respond with a connection error (Section 5.4.1) of
+/// type PROTOCOL_ERROR. (Section 6.10)
+#[test]
+fn sends_continuation_frame() {
+use __group::sends_continuation_frame as test;
+$body
+}
+}
 }
 }
 }
@@ -0,0 +1,112 @@
+//! An in-memory transport, to test the harness itself against a scripted
+//! peer rather than a real server.
+
+use fluke_buffet::{pipe, IntoHalves, Piece, PipeRead, PipeWrite, ReadOwned, RollMut, WriteOwned};
+use fluke_h2_parse::{Frame, IntoPiece};
+
+/// The client end of an in-memory connection, to pass to [crate::Conn::new].
+/// The other end is a [DuplexPeer].
+pub struct DuplexIo {
+    r: PipeRead,
+    w: PipeWrite,
+}
+
+impl DuplexIo {
+    /// Creates a connected pair: the transport for a `Conn`, and the peer
+    /// the test drives.
+    pub fn new() -> (Self, DuplexPeer) {
+        let (client_w, peer_r) = pipe();
+        let (peer_w, client_r) = pipe();
+        (
+            Self {
+                r: client_r,
+                w: client_w,
+            },
+            DuplexPeer {
+                r: peer_r,
+                w: peer_w,
+                scratch: RollMut::alloc().unwrap(),
+            },
+        )
+    }
+}
+
+impl IntoHalves for DuplexIo {
+    type Read = PipeRead;
+    type Write = PipeWrite;
+
+    fn into_halves(self) -> (Self::Read, Self::Write) {
+        (self.r, self.w)
+    }
+}
+
+/// The scripted end of a [DuplexIo]: whatever it sends, the `Conn` reads.
+pub struct DuplexPeer {
+    r: PipeRead,
+    w: PipeWrite,
+    scratch: RollMut,
+}
+
+impl DuplexPeer {
+    /// Sends raw bytes. Each call is delivered to the `Conn` as a single
+    /// read (or several, if its read buffer is smaller), so a script controls
+    /// exactly how the byte stream is chunked. Empty buffers are ignored.
+    pub async fn send(&mut self, buf: impl Into<Piece>) -> eyre::Result<()> {
+        let buf = buf.into();
+        if buf.is_empty() {
+            return Ok(());
+        }
+        self.w.write_all_owned(buf).await?;
+        Ok(())
+    }
+
+    /// Sends raw bytes in chunks of `chunk_size` bytes (the last one may be
+    /// shorter), each delivered to the `Conn` as a separate read.
+    pub async fn send_chunked(
+        &mut self,
+        buf: impl Into<Piece>,
+        chunk_size: usize,
+    ) -> eyre::Result<()> {
+        assert!(chunk_size > 0, "chunk_size must be non-zero");
+        let buf = buf.into();
+        for chunk in buf.chunks(chunk_size) {
+            self.send(chunk.to_vec()).await?;
+        }
+        Ok(())
+    }
+
+    /// Encodes a frame and sends it, header and payload in a single write.
+    pub async fn send_frame(&mut self, frame: Frame, payload: impl IntoPiece) -> eyre::Result<()> {
+        let payload = payload.into_piece(&mut self.scratch)?;
+        let frame = frame.with_len(payload.len().try_into().unwrap());
+        let header = frame.into_piece(&mut self.scratch)?;
+
+        let mut buf = header.to_vec();
+        buf.extend_from_slice(&payload[..]);
+        self.send(buf).await
+    }
+
+    /// Reads the next chunk of bytes the `Conn` wrote, or `None` once it
+    /// hung up.
+    pub async fn recv(&mut self) -> eyre::Result<Option<Vec<u8>>> {
+        let (res, mut buf) = self.r.read_owned(vec![0u8; 16384]).await;
+        let n = res?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buf.truncate(n);
+        Ok(Some(buf))
+    }
+
+    /// Reads and discards everything the `Conn` writes, until it hangs up.
+    pub async fn drain(&mut self) -> eyre::Result<()> {
+        while self.recv().await?.is_some() {}
+        Ok(())
+    }
+
+    /// Simulates a connection reset: the `Conn` gets an I/O error on its
+    /// next read.
+    pub async fn reset(self) {
+        self.w.reset().await
+    }
+}
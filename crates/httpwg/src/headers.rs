@@ -0,0 +1,237 @@
+//! Utilities to compare header lists
+
+use std::collections::BTreeMap;
+
+use eyre::eyre;
+
+use crate::Headers;
+
+/// Pseudo-header fields defined for requests (Section 8.3.1), which must not
+/// appear in responses.
+const REQUEST_PSEUDO_HEADERS: &[&[u8]] = &[b":method", b":scheme", b":authority", b":path"];
+
+/// Pseudo-header fields defined for responses (Section 8.3.2), which must not
+/// appear in requests.
+const RESPONSE_PSEUDO_HEADERS: &[&[u8]] = &[b":status"];
+
+/// Checks that a request doesn't carry response pseudo-header fields
+/// (Section 8.3). Requests that do are malformed.
+pub fn check_request_pseudo_headers(headers: &Headers) -> eyre::Result<()> {
+    check_pseudo_headers(headers, RESPONSE_PSEUDO_HEADERS, "request")
+}
+
+/// Checks that a response doesn't carry request pseudo-header fields
+/// (Section 8.3). Responses that do are malformed.
+pub fn check_response_pseudo_headers(headers: &Headers) -> eyre::Result<()> {
+    check_pseudo_headers(headers, REQUEST_PSEUDO_HEADERS, "response")
+}
+
+/// Connection-specific header fields, which HTTP/2 doesn't use (Section
+/// 8.2.2). `te` is handled separately.
+const CONNECTION_SPECIFIC_HEADERS: &[&[u8]] = &[
+    b"connection",
+    b"keep-alive",
+    b"proxy-connection",
+    b"transfer-encoding",
+    b"upgrade",
+];
+
+/// Checks that a header list doesn't carry connection-specific header
+/// fields (Section 8.2.2). The one exception is `te`, which is allowed if
+/// its value is exactly "trailers": "trailers, gzip" isn't.
+pub fn check_connection_specific_headers(headers: &Headers) -> eyre::Result<()> {
+    for (k, v) in headers.iter() {
+        let name = k.to_ascii_lowercase();
+        if CONNECTION_SPECIFIC_HEADERS.contains(&&name[..]) {
+            return Err(eyre!(
+                "malformed message: it carries the connection-specific {} header field",
+                String::from_utf8_lossy(&name)
+            ));
+        }
+        if name == b"te" && &v[..] != b"trailers" {
+            return Err(eyre!(
+                "malformed message: te may only be \"trailers\", got {:?}",
+                String::from_utf8_lossy(&v[..])
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn check_pseudo_headers(headers: &Headers, forbidden: &[&[u8]], kind: &str) -> eyre::Result<()> {
+    for (k, _) in headers.iter() {
+        if forbidden.contains(&&k[..]) {
+            return Err(eyre!(
+                "malformed {kind}: it carries the {} pseudo-header field",
+                String::from_utf8_lossy(&k[..])
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// A header list, normalized for comparison: field names lowercased, values
+/// of repeated fields joined, pseudo-headers kept apart from regular fields.
+#[derive(Default, PartialEq, Eq)]
+struct Normalized {
+    pseudo: BTreeMap<String, String>,
+    regular: BTreeMap<String, String>,
+}
+
+impl Normalized {
+    fn new(headers: &Headers) -> Self {
+        let mut normalized = Self::default();
+        for (k, v) in headers.iter() {
+            let name = String::from_utf8_lossy(&k[..]).to_ascii_lowercase();
+            let value = String::from_utf8_lossy(&v[..]).into_owned();
+
+            let (map, separator) = if name.starts_with(':') {
+                (&mut normalized.pseudo, ", ")
+            } else if name == "cookie" {
+                // cf. RFC 9113, Section 8.2.3
+                (&mut normalized.regular, "; ")
+            } else {
+                (&mut normalized.regular, ", ")
+            };
+            map.entry(name)
+                .and_modify(|joined| {
+                    joined.push_str(separator);
+                    joined.push_str(&value);
+                })
+                .or_insert(value);
+        }
+        normalized
+    }
+}
+
+/// Checks that two header lists carry the same fields, the way HTTP/2
+/// peers would see them:
+///
+///   * field names are compared case-insensitively (they're lowercase on
+///     the wire),
+///   * pseudo-headers and regular fields are compared separately, and the
+///     order of fields doesn't matter,
+///   * repeated fields are joined into a single value, in order (so the
+///     order of values within a field does matter), and cookies are joined
+///     with "; ".
+///
+/// On mismatch, the error lists the fields that differ, `-` for `expected`
+/// and `+` for `actual`.
+pub fn assert_equivalent(expected: &Headers, actual: &Headers) -> eyre::Result<()> {
+    let expected = Normalized::new(expected);
+    let actual = Normalized::new(actual);
+    if expected == actual {
+        return Ok(());
+    }
+
+    let mut diff = String::new();
+    for (expected, actual) in [
+        (&expected.pseudo, &actual.pseudo),
+        (&expected.regular, &actual.regular),
+    ] {
+        let mut names: Vec<&String> = expected.keys().chain(actual.keys()).collect();
+        names.sort();
+        names.dedup();
+
+        for name in names {
+            let (e, a) = (expected.get(name), actual.get(name));
+            if e == a {
+                continue;
+            }
+            if let Some(e) = e {
+                diff.push_str(&format!("\n- {name}: {e}"));
+            }
+            if let Some(a) = a {
+                diff.push_str(&format!("\n+ {name}: {a}"));
+            }
+        }
+    }
+
+    Err(eyre!("headers differ (- expected, + actual):{diff}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        assert_equivalent, check_connection_specific_headers, check_request_pseudo_headers,
+        check_response_pseudo_headers,
+    };
+    use crate::Headers;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> Headers {
+        let mut headers = Headers::default();
+        for &(k, v) in pairs {
+            headers.append(k.as_bytes(), v.as_bytes());
+        }
+        headers
+    }
+
+    #[test]
+    fn equivalent_headers() {
+        let expected = headers(&[
+            (":status", "200"),
+            ("Content-Type", "text/plain"),
+            ("vary", "accept"),
+            ("vary", "accept-encoding"),
+            ("cookie", "a=1"),
+            ("cookie", "b=2"),
+        ]);
+        let actual = headers(&[
+            ("cookie", "a=1; b=2"),
+            ("vary", "accept, accept-encoding"),
+            ("content-type", "text/plain"),
+            (":status", "200"),
+        ]);
+        assert_equivalent(&expected, &actual).unwrap();
+    }
+
+    #[test]
+    fn different_headers() {
+        let expected = headers(&[(":status", "200"), ("x-a", "1"), ("x-b", "2")]);
+        let actual = headers(&[(":status", "204"), ("x-a", "1"), ("x-c", "3")]);
+
+        let msg = assert_equivalent(&expected, &actual)
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("- :status: 200\n+ :status: 204"), "{msg}");
+        assert!(msg.contains("- x-b: 2"), "{msg}");
+        assert!(msg.contains("+ x-c: 3"), "{msg}");
+        assert!(!msg.contains("x-a"), "{msg}");
+    }
+
+    #[test]
+    fn direction_specific_pseudo_headers() {
+        let request = headers(&[(":method", "GET"), (":path", "/"), ("x-a", "1")]);
+        let response = headers(&[(":status", "200"), ("x-a", "1")]);
+
+        check_request_pseudo_headers(&request).unwrap();
+        check_response_pseudo_headers(&response).unwrap();
+
+        let msg = check_request_pseudo_headers(&response)
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains(":status"), "{msg}");
+        let msg = check_response_pseudo_headers(&request)
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains(":method"), "{msg}");
+    }
+
+    #[test]
+    fn connection_specific_headers() {
+        check_connection_specific_headers(&headers(&[(":method", "GET"), ("te", "trailers")]))
+            .unwrap();
+
+        for (k, v) in [
+            ("te", "gzip"),
+            ("te", "trailers, gzip"),
+            ("Connection", "keep-alive"),
+            ("upgrade", "h2c"),
+        ] {
+            let msg = check_connection_specific_headers(&headers(&[(k, v)]))
+                .unwrap_err()
+                .to_string();
+            assert!(msg.contains("malformed"), "{msg}");
+        }
+    }
+}
@@ -1,22 +1,42 @@
 use eyre::eyre;
 use rfc9113::DEFAULT_FRAME_SIZE;
-use std::{collections::VecDeque, rc::Rc, time::Duration};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet, VecDeque},
+    future::Future,
+    rc::Rc,
+    time::Duration,
+};
 
 use enumflags2::{bitflags, BitFlags};
 use fluke_buffet::{IntoHalves, Piece, PieceList, Roll, RollMut, WriteOwned};
 use fluke_h2_parse::{
     enumflags2,
     nom::{self, Finish},
-    ContinuationFlags, DataFlags, ErrorCode, Frame, FrameType, GoAway, HeadersFlags, IntoPiece,
-    KnownErrorCode, PingFlags, PrioritySpec, RstStream, SettingPairs, Settings, SettingsFlags,
-    StreamId, WindowUpdate, PREFACE,
+    ContinuationFlags, DataFlags, EncodedFrameType, ErrorCode, Frame, FrameType, GoAway,
+    HeadersFlags, IntoPiece, KnownErrorCode, PingFlags, PrioritySpec, PriorityUpdate,
+    ProtocolViolation, RstStream, Setting, SettingPairs, Settings, SettingsFlags, StreamId,
+    WindowUpdate, PREFACE,
 };
 use tokio::time::Instant;
 use tracing::{debug, trace};
 
 use crate::rfc9113::default_settings;
 
+pub mod headers;
 pub mod rfc9113;
+pub mod transcript;
+use transcript::{Direction, Transcript};
+
+mod duplex;
+pub use duplex::*;
+
+mod replay;
+pub use replay::*;
+
+mod streams;
+pub use streams::StreamState;
+use streams::StreamStates;
 
 #[derive(Default)]
 pub struct Headers {
@@ -95,15 +115,207 @@ pub struct Conn<IO: IntoHalves> {
     hpack_dec: fluke_hpack::Decoder<'static>,
     /// the peer's settings
     pub settings: Settings,
+    /// which settings the peer explicitly sent us during the handshake (one bit
+    /// per setting identifier)
+    advertised_settings: u8,
+    /// how many bytes of DATA we may still send on the connection, as far as
+    /// we know: decremented by `write_data`, and replenished by the
+    /// WINDOW_UPDATE frames the helpers that look for them come across.
+    send_window: i64,
+    /// the same, for each stream we sent DATA on or got a WINDOW_UPDATE
+    /// for. Other streams still have the peer's SETTINGS_INITIAL_WINDOW_SIZE.
+    stream_send_windows: HashMap<StreamId, i64>,
+    /// the stream ids `open_stream` hands out
+    stream_ids: StreamIdAllocator,
+    /// the state of streams, as far as the frames sent with
+    /// [Conn::write_frame] and read by the receive loop go
+    streams: Rc<RefCell<StreamStates>>,
+    /// how many PINGs `measure_rtt` sent, to give each a payload of its own
+    pings_sent: u64,
+
+    /// frames received from the peer, filled by the receive loop if
+    /// [Config::record_frames] is set
+    frame_log: Rc<RefCell<Vec<RecordedFrame>>>,
+
+    /// frames sent and received, in order, if [Config::record_frames] is
+    /// set
+    transcript: Rc<RefCell<Transcript>>,
+
+    /// the SETTINGS_MAX_FRAME_SIZE we last advertised, shared with the
+    /// receive loop, which rejects larger frames
+    local_max_frame_size: Rc<Cell<u32>>,
+
+    /// called by the receive loop for every frame, see
+    /// [Conn::with_frame_observer]
+    frame_observer: Rc<RefCell<Option<FrameObserver>>>,
+
+    /// frames `wait_for_frame` skipped over, if
+    /// [Config::buffer_unmatched_frames] is set, in arrival order
+    unmatched_frames: VecDeque<(Frame, Roll, Instant)>,
+
+    /// when the frame `wait_for_frame` last returned was received
+    last_received_at: Option<Instant>,
+
+    /// how many bytes of frames (headers and payloads) the receive loop read
+    /// so far, see [Conn::received_byte_count]
+    received_bytes: Rc<Cell<u64>>,
+
+    /// how many bytes we wrote so far, see [Conn::sent_byte_count]
+    sent_bytes: u64,
 
     // this field exists for the `Drop` impl
     #[allow(dead_code)]
     cancel_tx: tokio::sync::oneshot::Sender<()>,
 }
 
+/// A callback the receive loop invokes for every frame, see
+/// [Conn::with_frame_observer].
+pub type FrameObserver = Box<dyn FnMut(&Frame, &Roll)>;
+
+/// Hands out the stream ids an endpoint initiates, in order: odd ones for
+/// a client (1, 3, 5, and so on), even ones for a server (2, 4, 6, and so
+/// on), stopping after [StreamId::MAX]. Allocating from a single place
+/// avoids reusing an id by accident, or picking one of the wrong parity
+/// (cf. RFC 9113, Section 5.1.1).
+#[derive(Clone, Debug)]
+pub struct StreamIdAllocator {
+    next: u32,
+}
+
+impl StreamIdAllocator {
+    /// Client-initiated stream ids, starting at 1
+    pub fn client() -> Self {
+        Self::client_starting_at(StreamId(1))
+    }
+
+    /// Client-initiated stream ids, starting at `first`, which must be odd.
+    pub fn client_starting_at(first: StreamId) -> Self {
+        assert!(
+            !first.is_server_initiated(),
+            "client stream ids are odd, got {first}"
+        );
+        Self { next: first.0 }
+    }
+
+    /// Server-initiated stream ids, starting at 2
+    pub fn server() -> Self {
+        Self { next: 2 }
+    }
+
+    /// Allocates the next stream id, or errors out once they've all been
+    /// handed out.
+    pub fn allocate(&mut self) -> Result<StreamId, StreamIdExhausted> {
+        if self.next > StreamId::MAX.0 {
+            return Err(StreamIdExhausted {
+                server: self.next % 2 == 0,
+            });
+        }
+        let stream_id = StreamId(self.next);
+        // can't overflow: MAX is 2^31-1
+        self.next += 2;
+        Ok(stream_id)
+    }
+}
+
+/// Client-initiated stream ids, in order: 1, 3, 5, and so on, stopping
+/// after [StreamId::MAX].
+#[deprecated(note = "use `StreamIdAllocator::client` instead")]
+#[derive(Clone, Debug)]
+pub struct ClientStreamIds {
+    inner: StreamIdAllocator,
+}
+
+#[allow(deprecated)]
+impl ClientStreamIds {
+    pub fn new() -> Self {
+        Self {
+            inner: StreamIdAllocator::client(),
+        }
+    }
+
+    /// Starts at `first`, which must be an odd stream id.
+    pub fn starting_at(first: StreamId) -> Self {
+        Self {
+            inner: StreamIdAllocator::client_starting_at(first),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl Default for ClientStreamIds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[allow(deprecated)]
+impl Iterator for ClientStreamIds {
+    type Item = StreamId;
+
+    fn next(&mut self) -> Option<StreamId> {
+        self.inner.allocate().ok()
+    }
+}
+
+/// Returned by [StreamIdAllocator::allocate] once every stream id up to
+/// [StreamId::MAX] has been handed out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamIdExhausted {
+    server: bool,
+}
+
+impl std::fmt::Display for StreamIdExhausted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let role = if self.server { "server" } else { "client" };
+        write!(f, "ran out of {role} stream ids (max is {})", StreamId::MAX)
+    }
+}
+
+impl std::error::Error for StreamIdExhausted {}
+
+/// A frame received from the peer, see [Config::record_frames]
+#[derive(Clone)]
+pub struct RecordedFrame {
+    pub frame: Frame,
+    /// the frame header and payload, exactly as they were read off the wire
+    pub bytes: Vec<u8>,
+}
+
+/// What was exchanged during [Conn::handshake_capture]
+#[derive(Debug)]
+pub struct HandshakeRecord {
+    /// the parameters of the server's first SETTINGS frame, in the order
+    /// it sent them
+    pub settings: Vec<(Setting, u32)>,
+    /// how long after we sent our SETTINGS the server acknowledged them, or
+    /// `None` if it didn't within [Config::timeout]
+    pub settings_ack_after: Option<Duration>,
+    /// what we sent: the connection preface, our SETTINGS frame, and our
+    /// acknowledgement of the server's
+    pub sent: Vec<u8>,
+    /// what the server sent: its SETTINGS frame, and its acknowledgement of
+    /// ours. Frames are re-encoded after parsing, so flags that aren't
+    /// defined for SETTINGS don't show up here.
+    pub received: Vec<u8>,
+}
+
 pub enum Ev {
-    Frame { frame: Frame, payload: Roll },
-    IoError { error: std::io::Error },
+    Frame {
+        frame: Frame,
+        payload: Roll,
+        /// when the receive loop finished reading the frame
+        received_at: Instant,
+    },
+    /// The peer sent a SETTINGS frame after the one from the handshake.
+    /// `Conn` applies the changes to its view of the peer's settings and
+    /// acknowledges them while waiting for frames.
+    SettingsChanged {
+        frame: Frame,
+        changes: Vec<(Setting, u32)>,
+    },
+    IoError {
+        error: std::io::Error,
+    },
 }
 
 pub enum FrameWaitOutcome {
@@ -124,29 +336,50 @@ pub enum FrameWaitOutcome {
     },
 }
 
+/// How a peer reported an error, see [Conn::expect_error_or_close]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorOutcome {
+    /// a GOAWAY frame with one of the expected codes: a connection error
+    GoAway {
+        error: ErrorC,
+        last_stream_id: StreamId,
+    },
+    /// a RST_STREAM frame with one of the expected codes: a stream error
+    RstStream { error: ErrorC, stream_id: StreamId },
+    /// the peer hung up (or reset the connection) without sending either
+    Closed,
+}
+
 impl FrameWaitOutcome {
     pub fn unwrap(self) -> (Frame, Roll) {
+        self.into_result().unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Like [FrameWaitOutcome::unwrap], but returns an error instead of
+    /// panicking
+    pub fn into_result(self) -> eyre::Result<(Frame, Roll)> {
         match self {
-            FrameWaitOutcome::Success(frame, payload) => (frame, payload),
+            FrameWaitOutcome::Success(frame, payload) => Ok((frame, payload)),
             FrameWaitOutcome::Timeout {
                 wanted,
                 last_frame,
                 waited,
-            } => {
-                panic!(
-                    "Wanted ({wanted:?}), timed out after {waited:?}. Last frame: {last_frame:?}"
-                );
-            }
-            FrameWaitOutcome::Eof { wanted, last_frame } => {
-                panic!("Wanted ({wanted:?}), peer hung up. Last frame: {last_frame:?}");
-            }
+            } => Err(eyre!(
+                "Wanted ({}), timed out after {waited:?}. Last frame: {last_frame:?}",
+                FrameT::describe(wanted)
+            )),
+            FrameWaitOutcome::Eof { wanted, last_frame } => Err(eyre!(
+                "Wanted ({}), peer hung up. Last frame: {last_frame:?}",
+                FrameT::describe(wanted)
+            )),
             FrameWaitOutcome::IoError {
                 wanted,
                 last_frame,
                 error,
-            } => {
-                panic!("Wanted ({wanted:?}), got I/O error {error}. Last frame: {last_frame:?}")
-            }
+            } => Err(eyre!(
+                "Wanted ({}), got I/O error {error}. Last frame: {last_frame:?}",
+                FrameT::describe(wanted)
+            )),
         }
     }
 }
@@ -155,7 +388,7 @@ impl FrameWaitOutcome {
 /// Useful to expect a certain frame type
 #[bitflags]
 #[repr(u16)]
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum FrameT {
     Data,
     Headers,
@@ -167,9 +400,44 @@ pub enum FrameT {
     GoAway,
     WindowUpdate,
     Continuation,
+    PriorityUpdate,
     Unknown,
 }
 
+impl FrameT {
+    /// The frame type's name as written in RFC 9113 ("DATA", "HEADERS",
+    /// etc.). See [FrameType::name], which also names unknown frame types
+    /// after their type byte.
+    pub fn name(&self) -> &'static str {
+        match self {
+            FrameT::Data => "DATA",
+            FrameT::Headers => "HEADERS",
+            FrameT::Priority => "PRIORITY",
+            FrameT::RstStream => "RST_STREAM",
+            FrameT::Settings => "SETTINGS",
+            FrameT::PushPromise => "PUSH_PROMISE",
+            FrameT::Ping => "PING",
+            FrameT::GoAway => "GOAWAY",
+            FrameT::WindowUpdate => "WINDOW_UPDATE",
+            FrameT::Continuation => "CONTINUATION",
+            FrameT::PriorityUpdate => "PRIORITY_UPDATE",
+            FrameT::Unknown => "UNKNOWN",
+        }
+    }
+
+    /// Formats a set of frame types for humans, e.g. "DATA | HEADERS".
+    pub fn describe(types: BitFlags<FrameT>) -> String {
+        let names: Vec<&str> = types.iter().map(|t| t.name()).collect();
+        names.join(" | ")
+    }
+}
+
+impl std::fmt::Display for FrameT {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
 impl From<FrameType> for FrameT {
     fn from(value: FrameType) -> Self {
         match value {
@@ -178,11 +446,12 @@ impl From<FrameType> for FrameT {
             FrameType::Priority => Self::Priority,
             FrameType::RstStream => Self::RstStream,
             FrameType::Settings(_) => Self::Settings,
-            FrameType::PushPromise => Self::PushPromise,
+            FrameType::PushPromise(_) => Self::PushPromise,
             FrameType::Ping(_) => Self::Ping,
             FrameType::GoAway => Self::GoAway,
             FrameType::WindowUpdate => Self::WindowUpdate,
             FrameType::Continuation(_) => Self::Continuation,
+            FrameType::PriorityUpdate => Self::PriorityUpdate,
             FrameType::Unknown(_) => Self::Unknown,
         }
     }
@@ -236,15 +505,45 @@ impl From<KnownErrorCode> for ErrorC {
     }
 }
 
+impl Conn<ReplayIo> {
+    /// A connection that reads back `chunks`, then EOF, and discards
+    /// whatever is written to it: see [ReplayIo::chunked] for how chunks are
+    /// delivered. Handy to test the receive loop and the parsers
+    /// deterministically, with no peer and no network.
+    pub fn replay<C: Into<Piece>>(config: Rc<Config>, chunks: impl IntoIterator<Item = C>) -> Self {
+        Self::new(config, ReplayIo::chunked(chunks))
+    }
+}
+
 impl<IO: IntoHalves> Conn<IO> {
     pub fn new(config: Rc<Config>, io: IO) -> Self {
+        let first_stream_id = config.first_stream_id;
+        assert!(
+            !first_stream_id.is_server_initiated() && first_stream_id <= StreamId::MAX,
+            "first_stream_id must be an odd, valid stream id, got {first_stream_id}"
+        );
+
         let (mut r, w) = io.into_halves();
 
         let (ev_tx, ev_rx) = tokio::sync::mpsc::channel::<Ev>(1);
         let mut eof = false;
+        // the first SETTINGS frame from the peer is handled by `handshake`
+        let mut saw_peer_settings = false;
+        let frame_log: Rc<RefCell<Vec<RecordedFrame>>> = Default::default();
+        let transcript: Rc<RefCell<Transcript>> = Default::default();
+        let local_max_frame_size = Rc::new(Cell::new(DEFAULT_FRAME_SIZE));
+        let frame_observer: Rc<RefCell<Option<FrameObserver>>> = Default::default();
+        let streams: Rc<RefCell<StreamStates>> = Default::default();
+        let received_bytes: Rc<Cell<u64>> = Default::default();
 
         let recv_fut = {
             let config = config.clone();
+            let frame_log = frame_log.clone();
+            let transcript = transcript.clone();
+            let streams = streams.clone();
+            let local_max_frame_size = local_max_frame_size.clone();
+            let frame_observer = frame_observer.clone();
+            let received_bytes = received_bytes.clone();
             async move {
                 let mut res_buf = RollMut::alloc()?;
                 'read: loop {
@@ -252,18 +551,59 @@ impl<IO: IntoHalves> Conn<IO> {
 
                     match Frame::parse(res_buf.filled()) {
                         Ok((rest, frame)) => {
+                            // `frame` only has the flags defined for its type
+                            let encoded = EncodedFrameType {
+                                ty: res_buf[3],
+                                flags: res_buf[4],
+                            };
+                            // copied out rather than kept as a `Roll`, so that
+                            // recording doesn't hold on to buffers from the pool
+                            let header_bytes = config
+                                .record_frames
+                                .then(|| res_buf[..res_buf.len() - rest.len()].to_vec());
                             res_buf.keep(rest);
                             debug!("< {frame:?}");
 
+                            // don't read (and allocate room for) a payload
+                            // larger than we said we'd accept
+                            let max_frame_size = local_max_frame_size.get();
+                            if frame.len > max_frame_size {
+                                let error = std::io::Error::new(
+                                    std::io::ErrorKind::InvalidData,
+                                    format!(
+                                        "peer sent a frame of {} bytes ({frame:?}), larger than our SETTINGS_MAX_FRAME_SIZE ({max_frame_size})",
+                                        frame.len
+                                    ),
+                                );
+                                _ = ev_tx.send(Ev::IoError { error }).await;
+                                break 'read;
+                            }
+
+                            if config.strict_flags && FrameType::decode_strict(encoded).is_none() {
+                                let error = std::io::Error::new(
+                                    std::io::ErrorKind::InvalidData,
+                                    format!(
+                                        "peer sent a frame with undefined flags ({frame:?}, flags {:#04x})",
+                                        encoded.flags
+                                    ),
+                                );
+                                _ = ev_tx.send(Ev::IoError { error }).await;
+                                break 'read;
+                            }
+
                             // read frame payload
                             let frame_len = frame.len as usize;
                             trace!(?frame_len, "reserving memory");
                             res_buf.reserve_at_least(frame_len)?;
 
-                            let deadline = Instant::now() + config.timeout;
+                            let mut deadline = Instant::now() + config.timeout;
                             trace!(?frame_len, ?deadline, "reading");
 
                             while res_buf.len() < frame_len {
+                                if let Some(pacing) = config.read_pacing {
+                                    tokio::time::sleep(pacing).await;
+                                    deadline += pacing;
+                                }
                                 let res;
                                 (res, res_buf) = match tokio::time::timeout_at(
                                     deadline,
@@ -279,7 +619,15 @@ impl<IO: IntoHalves> Conn<IO> {
                                         break 'read;
                                     }
                                 };
-                                let n = res?;
+                                let n = match res {
+                                    Ok(n) => n,
+                                    Err(error) => {
+                                        // e.g. a connection reset: that's for
+                                        // the test to look at
+                                        _ = ev_tx.send(Ev::IoError { error }).await;
+                                        break 'read;
+                                    }
+                                };
                                 trace!(%n, len = %res_buf.len(), "read bytes (reading frame payload)");
 
                                 if n == 0 {
@@ -300,7 +648,43 @@ impl<IO: IntoHalves> Conn<IO> {
                             assert_eq!(payload.len(), frame_len);
 
                             trace!(%frame_len, "got frame payload");
-                            if ev_tx.send(Ev::Frame { frame, payload }).await.is_err() {
+                            let received_at = Instant::now();
+                            // frame headers are always 9 bytes
+                            received_bytes.set(received_bytes.get() + 9 + frame_len as u64);
+                            if let Some(mut bytes) = header_bytes {
+                                bytes.extend_from_slice(&payload[..]);
+                                frame_log.borrow_mut().push(RecordedFrame { frame, bytes });
+                                transcript.borrow_mut().push(Direction::Received, frame);
+                            }
+                            streams.borrow_mut().on_frame_received(&frame);
+                            if let Some(observer) = frame_observer.borrow_mut().as_mut() {
+                                observer(&frame, &payload);
+                            }
+                            let is_peer_settings = matches!(
+                                frame.frame_type,
+                                FrameType::Settings(flags) if !flags.contains(SettingsFlags::Ack)
+                            );
+                            let ev = if is_peer_settings
+                                && saw_peer_settings
+                                && payload.len() % 6 == 0
+                            {
+                                let mut changes = Vec::new();
+                                Settings::parse(&payload[..], |k, v| {
+                                    changes.push((k, v));
+                                    Ok::<_, std::convert::Infallible>(())
+                                })
+                                .unwrap();
+                                Ev::SettingsChanged { frame, changes }
+                            } else {
+                                saw_peer_settings |= is_peer_settings;
+                                Ev::Frame {
+                                    frame,
+                                    payload,
+                                    received_at,
+                                }
+                            };
+
+                            if ev_tx.send(ev).await.is_err() {
                                 // I guess we stopped consuming frames, sure.
                                 break 'read;
                             }
@@ -320,6 +704,9 @@ impl<IO: IntoHalves> Conn<IO> {
 
                             trace!("reserving");
                             res_buf.reserve()?;
+                            if let Some(pacing) = config.read_pacing {
+                                tokio::time::sleep(pacing).await;
+                            }
                             let res;
                             trace!("re-filling buffer");
                             let deadline = Instant::now() + config.timeout;
@@ -335,7 +722,13 @@ impl<IO: IntoHalves> Conn<IO> {
                                     break 'read;
                                 }
                             };
-                            let n = res?;
+                            let n = match res {
+                                Ok(n) => n,
+                                Err(error) => {
+                                    _ = ev_tx.send(Ev::IoError { error }).await;
+                                    break 'read;
+                                }
+                            };
                             if n == 0 {
                                 debug!("reached EOF");
                                 eof = true;
@@ -373,6 +766,11 @@ impl<IO: IntoHalves> Conn<IO> {
             }
         });
 
+        let mut scratch = RollMut::alloc().unwrap();
+        if let Some(scratch_size) = config.scratch_size {
+            scratch.reserve_at_least(scratch_size).unwrap();
+        }
+
         let mut settings: Settings = Default::default();
         for (code, value) in default_settings().0 {
             settings.apply(*code, *value).unwrap();
@@ -380,7 +778,7 @@ impl<IO: IntoHalves> Conn<IO> {
 
         Self {
             w,
-            scratch: RollMut::alloc().unwrap(),
+            scratch,
             ev_rx,
             config,
             hpack_enc: Default::default(),
@@ -390,6 +788,22 @@ impl<IO: IntoHalves> Conn<IO> {
                 max_frame_size: DEFAULT_FRAME_SIZE,
                 ..Default::default()
             },
+            advertised_settings: 0,
+            // the connection flow-control window is not affected by
+            // SETTINGS_INITIAL_WINDOW_SIZE, it always starts at 65535
+            send_window: (1 << 16) - 1,
+            stream_send_windows: Default::default(),
+            stream_ids: StreamIdAllocator::client_starting_at(first_stream_id),
+            streams,
+            pings_sent: 0,
+            frame_log,
+            transcript,
+            local_max_frame_size,
+            frame_observer,
+            unmatched_frames: Default::default(),
+            last_received_at: None,
+            received_bytes,
+            sent_bytes: 0,
             cancel_tx,
         }
     }
@@ -399,667 +813,4293 @@ impl<IO: IntoHalves> Conn<IO> {
         let frame = frame.with_len(payload.len().try_into().unwrap());
 
         let header = frame.into_piece(&mut self.scratch)?;
+        let len = header.len() + payload.len();
         self.w
             .writev_all_owned(PieceList::single(header).followed_by(payload))
             .await?;
+        self.sent_bytes += len as u64;
+        self.on_frame_sent(frame);
         Ok(())
     }
 
-    pub async fn write_priority(
-        &mut self,
-        stream_id: StreamId,
-        priority_spec: PrioritySpec,
-    ) -> eyre::Result<()> {
-        self.write_frame(FrameType::Priority.into_frame(stream_id), priority_spec)
-            .await
+    fn on_frame_sent(&mut self, frame: Frame) {
+        self.streams.borrow_mut().on_frame_sent(&frame);
+        if self.config.record_frames {
+            self.transcript.borrow_mut().push(Direction::Sent, frame);
+        }
     }
 
-    /// Send a PING frame and wait for the peer to acknowledge it.
-    pub async fn verify_connection_still_alive(&mut self) -> eyre::Result<()> {
-        let payload = b"pingpong";
-        self.write_ping(false, &payload[..]).await?;
-        self.verify_ping_frame_with_ack(payload).await?;
+    /// Half-closes the connection at the transport level: everything written
+    /// so far is flushed, then the write half is shut down (e.g. a TCP FIN).
+    /// Frames from the peer can still be read afterwards, but nothing more
+    /// can be written.
+    ///
+    /// Unlike sending a GOAWAY frame, this says nothing at the HTTP/2 level:
+    /// it's for testing how a server deals with a peer that stops sending
+    /// without closing the connection gracefully.
+    pub async fn shutdown_write(&mut self) -> eyre::Result<()> {
+        self.w.shutdown().await?;
         Ok(())
     }
 
-    pub async fn write_ping(&mut self, ack: bool, payload: impl IntoPiece) -> eyre::Result<()> {
-        self.write_frame(
-            FrameType::Ping(if ack {
-                PingFlags::Ack.into()
-            } else {
-                Default::default()
-            })
-            .into_frame(StreamId::CONNECTION),
-            payload,
-        )
-        .await
+    /// Sets a callback the receive loop invokes for every frame it reads, as
+    /// it reads it, before the frame is sent on [Conn::ev_rx]: useful for
+    /// live logging, metrics, or checks that span the whole connection.
+    /// Replaces any previously set observer.
+    ///
+    /// The callback runs on the receive loop, which can't read anything else
+    /// until it returns, so it should be quick.
+    pub fn with_frame_observer(&mut self, f: impl FnMut(&Frame, &Roll) + 'static) {
+        *self.frame_observer.borrow_mut() = Some(Box::new(f));
     }
 
-    pub async fn write_and_ack_settings(
-        &mut self,
-        settings: impl Into<SettingPairs<'_>>,
-    ) -> eyre::Result<()> {
-        self.write_settings(settings).await?;
-        self.verify_settings_frame_with_ack().await?;
-        Ok(())
+    /// Returns how many bytes of DATA the connection flow-control window
+    /// still has room for. Can be negative if we sent more than we were
+    /// allowed to.
+    pub fn send_window(&self) -> i64 {
+        self.send_window
     }
 
-    pub async fn write_settings(
-        &mut self,
-        settings: impl Into<SettingPairs<'_>>,
-    ) -> eyre::Result<()> {
-        self.write_frame(
-            FrameType::Settings(Default::default()).into_frame(StreamId::CONNECTION),
-            settings.into(),
-        )
-        .await
+    /// Returns how many bytes of DATA the flow-control window of `stream_id`
+    /// still has room for, see [Conn::send_window]. Follows changes to the
+    /// peer's SETTINGS_INITIAL_WINDOW_SIZE (cf. RFC 9113, Section 6.9.2).
+    pub fn stream_send_window(&self, stream_id: StreamId) -> i64 {
+        self.stream_send_windows
+            .get(&stream_id)
+            .copied()
+            .unwrap_or(self.settings.initial_window_size as i64)
     }
 
-    /// Waits for a certain kind of frame
-    pub async fn wait_for_frame(&mut self, types: impl Into<BitFlags<FrameT>>) -> FrameWaitOutcome {
-        let deadline = Instant::now() + self.config.timeout;
-        self.wait_for_frame_with_deadline(types, deadline).await
+    /// Takes `len` bytes of DATA off the connection window and the window
+    /// of `stream_id`.
+    fn consume_send_windows(&mut self, stream_id: StreamId, len: usize) {
+        let initial = self.settings.initial_window_size as i64;
+        self.send_window -= len as i64;
+        *self.stream_send_windows.entry(stream_id).or_insert(initial) -= len as i64;
     }
 
-    /// Waits for a certain kind of frame with a specified deadline
-    pub async fn wait_for_frame_with_deadline(
+    /// Credits a WINDOW_UPDATE the peer sent to the window it's for.
+    fn apply_window_update(&mut self, stream_id: StreamId, increment: u32) {
+        if stream_id == StreamId::CONNECTION {
+            self.send_window += increment as i64;
+        } else {
+            let initial = self.settings.initial_window_size as i64;
+            *self.stream_send_windows.entry(stream_id).or_insert(initial) += increment as i64;
+        }
+    }
+
+    /// Applies settings the peer changed mid-connection. Invalid values, and
+    /// a new SETTINGS_INITIAL_WINDOW_SIZE that makes a stream window exceed
+    /// 2^31-1, are connection errors, which we report with a GOAWAY (cf. RFC
+    /// 9113, Section 6.5.2 and 6.9.2).
+    async fn apply_settings_change(
         &mut self,
-        types: impl Into<BitFlags<FrameT>>,
-        deadline: Instant,
-    ) -> FrameWaitOutcome {
-        let types = types.into();
-        let mut last_frame: Option<Frame> = None;
+        changes: Vec<(Setting, u32)>,
+    ) -> Result<(), std::io::Error> {
+        let old_initial = self.settings.initial_window_size as i64;
+        let mut result = changes
+            .into_iter()
+            .try_for_each(|(k, v)| self.settings.apply(k, v))
+            .map_err(ProtocolViolation::from)
+            .map_err(|e| (e.error_code(), e.to_string()));
 
-        loop {
-            match tokio::time::timeout_at(deadline, self.ev_rx.recv()).await {
-                Err(_) => {
-                    return FrameWaitOutcome::Timeout {
-                        wanted: types,
-                        last_frame,
-                        waited: self.config.timeout,
-                    };
+        if result.is_ok() {
+            // the difference applies to every stream window, even if it
+            // makes it negative
+            let delta = self.settings.initial_window_size as i64 - old_initial;
+            for (stream_id, window) in self.stream_send_windows.iter_mut() {
+                *window += delta;
+                if *window > Settings::MAX_INITIAL_WINDOW_SIZE as i64 {
+                    result = Err((
+                        KnownErrorCode::FlowControlError,
+                        format!("new SETTINGS_INITIAL_WINDOW_SIZE makes the window of stream {stream_id} exceed 2^31-1"),
+                    ));
                 }
-                Ok(maybe_ev) => match maybe_ev {
-                    None => {
-                        return FrameWaitOutcome::Eof {
-                            wanted: types,
-                            last_frame,
-                        }
-                    }
-                    Some(ev) => match ev {
-                        Ev::Frame { frame, payload } => {
-                            if types.contains(FrameT::from(frame.frame_type)) {
-                                return FrameWaitOutcome::Success(frame, payload);
-                            } else {
-                                last_frame = Some(frame)
-                            }
-                        }
-                        Ev::IoError { error } => {
-                            return FrameWaitOutcome::IoError {
-                                wanted: types,
-                                last_frame,
-                                error,
-                            }
-                        }
-                    },
-                },
             }
         }
-    }
 
-    /// Waits for a PING frame with Ack flag and the specified payload.
-    /// It will NOT ignore other PING frames, if the first frame it
-    /// receives doesn't have the expected payload, it will return an error.
-    pub async fn verify_ping_frame_with_ack(&mut self, payload: &[u8]) -> eyre::Result<()> {
-        let (frame, received_payload) = self.wait_for_frame(FrameT::Ping).await.unwrap();
-        assert!(frame.is_ack(), "expected PING frame to have ACK flag");
+        match result {
+            Ok(()) => self
+                .write_frame(Frame::settings_ack(), ())
+                .await
+                .map_err(|e| std::io::Error::other(e.to_string())),
+            Err((error_code, reason)) => {
+                debug!(?error_code, %reason, "peer sent invalid settings");
+                let goaway = GoAway {
+                    additional_debug_data: Piece::empty(),
+                    error_code: error_code.into(),
+                    last_stream_id: StreamId::CONNECTION,
+                };
+                _ = self
+                    .write_frame(FrameType::GoAway.into_frame(StreamId::CONNECTION), goaway)
+                    .await;
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("peer sent invalid settings: {reason}"),
+                ))
+            }
+        }
+    }
 
-        assert_eq!(received_payload, payload, "unexpected PING payload");
+    /// How many streams are open or half-closed, which is what counts
+    /// towards the peer's SETTINGS_MAX_CONCURRENT_STREAMS (cf. RFC 9113,
+    /// Section 5.1.2). Streams open when a HEADERS frame is sent or
+    /// received on them, and close once both sides sent END_STREAM, or
+    /// either sent RST_STREAM.
+    ///
+    /// Only frames sent with [Conn::write_frame] (and the methods built on
+    /// it) are accounted for, not raw bytes sent with [Conn::send]. Frames
+    /// from the peer count as soon as they're read, even if no test looked
+    /// at them yet.
+    pub fn open_stream_count(&self) -> usize {
+        self.streams.borrow().open_count()
+    }
 
-        Ok(())
+    /// How many bytes of frames the receive loop read from the peer so far,
+    /// frame headers and payloads included, whether or not a test looked at
+    /// those frames yet. Frames that fail to read (e.g. because they're
+    /// larger than our SETTINGS_MAX_FRAME_SIZE) aren't counted.
+    pub fn received_byte_count(&self) -> u64 {
+        self.received_bytes.get()
     }
 
-    pub async fn handshake(&mut self) -> eyre::Result<()> {
-        // perform an HTTP/2 handshake as a client
-        self.w.write_all_owned(PREFACE).await?;
+    /// How many bytes we wrote to the peer so far: frames, and raw bytes sent
+    /// with [Conn::send] (like the connection preface).
+    pub fn sent_byte_count(&self) -> u64 {
+        self.sent_bytes
+    }
 
-        self.write_settings(default_settings()).await?;
+    /// The state of `stream_id`, tracked the same way as for
+    /// [Conn::open_stream_count], or `None` if it's still idle.
+    pub fn stream_state(&self, stream_id: StreamId) -> Option<StreamState> {
+        self.streams.borrow().get(stream_id)
+    }
 
-        let (frame, payload) = self.wait_for_frame(FrameT::Settings).await.unwrap();
-        assert!(
-            !frame.is_ack(),
-            "server should send their settings first thing (no ack)"
-        );
+    /// The highest client-initiated stream id a HEADERS frame went out (or
+    /// came in) on so far, `None` before the first request. RFC 9113,
+    /// Section 5.1.1 requires new streams to have a higher id: a test that
+    /// goes back down can check the server's GOAWAY against this.
+    pub fn highest_client_stream_id(&self) -> Option<StreamId> {
+        self.streams.borrow().highest_client_stream_id()
+    }
 
-        Settings::parse(&payload[..], |k, v| self.settings.apply(k, v))?;
+    /// The size of our HPACK encoder's dynamic table, in octets (cf. RFC
+    /// 7541, Section 4.1). Once the peer has decoded every header block we
+    /// sent, its decoder's table should be the same size: if it isn't, the
+    /// two are out of sync, and COMPRESSION_ERROR is around the corner.
+    pub fn hpack_encoder_table_size(&self) -> usize {
+        self.hpack_enc.table_size()
+    }
 
-        self.write_frame(
-            Frame::new(
-                FrameType::Settings(SettingsFlags::Ack.into()),
-                StreamId::CONNECTION,
-            ),
-            (),
-        )
-        .await?;
+    /// The size of our HPACK decoder's dynamic table, in octets, after the
+    /// header blocks decoded so far
+    pub fn hpack_decoder_table_size(&self) -> usize {
+        self.hpack_dec.table_size()
+    }
 
-        // and wait until the server acknowledges our settings
-        let (frame, _payload) = self.wait_for_frame(FrameT::Settings).await.unwrap();
-        assert!(frame.is_ack(), "server should acknowledge our settings");
+    /// Allocates the next client stream id, starting at
+    /// [Config::first_stream_id]. Nothing is sent: the stream only opens
+    /// once a HEADERS frame is written on it.
+    ///
+    /// Errors out once every client stream id up to 2^31-1 has been handed
+    /// out.
+    pub fn open_stream(&mut self) -> eyre::Result<StreamId> {
+        Ok(self.stream_ids.allocate()?)
+    }
 
-        Ok(())
+    /// Opens the next client stream (see [Conn::open_stream]) by sending
+    /// `headers` on it, in a single HEADERS frame, with END_STREAM if
+    /// `end_stream` is set. Returns the stream id used.
+    pub async fn open_next_stream(
+        &mut self,
+        headers: &Headers,
+        end_stream: bool,
+    ) -> eyre::Result<StreamId> {
+        let stream_id = self.open_stream()?;
+        let mut flags: BitFlags<HeadersFlags> = HeadersFlags::EndHeaders.into();
+        if end_stream {
+            flags |= HeadersFlags::EndStream;
+        }
+        self.encode_and_write_headers(stream_id, flags, headers)
+            .await?;
+        Ok(stream_id)
     }
 
-    pub async fn send(&mut self, buf: impl Into<Piece>) -> eyre::Result<()> {
-        self.w.write_all_owned(buf.into()).await?;
+    /// Opens `count` streams and resets each one right away, without reading
+    /// anything in between, like the "Rapid Reset" attack (CVE-2023-44487).
+    /// The HEADERS frames don't carry END_STREAM, so each stream is still
+    /// open (waiting for a request body) when its RST_STREAM (CANCEL) comes
+    /// in.
+    ///
+    /// Errors out if a write fails, e.g. because the peer hung up in the
+    /// middle of it.
+    pub async fn send_rapid_resets(&mut self, count: usize) -> eyre::Result<()> {
+        let headers = self.common_headers("POST");
+        for _ in 0..count {
+            let stream_id = self.open_next_stream(&headers, false).await?;
+            self.write_rst_stream(stream_id, KnownErrorCode::Cancel)
+                .await?;
+        }
         Ok(())
     }
 
-    async fn verify_connection_error(
-        &mut self,
-        codes: impl Into<BitFlags<ErrorC>>,
-    ) -> eyre::Result<()> {
-        let codes = codes.into();
+    /// Returns the wire bytes (header and payload) of the last `n` frames
+    /// received from the peer, oldest first. Useful for snapshot testing a
+    /// server's exact encoding.
+    ///
+    /// Panics unless [Config::record_frames] is set.
+    pub fn last_frames_bytes(&self, n: usize) -> Vec<Vec<u8>> {
+        assert!(
+            self.config.record_frames,
+            "last_frames_bytes requires Config::record_frames"
+        );
+        let log = self.frame_log.borrow();
+        log[log.len().saturating_sub(n)..]
+            .iter()
+            .map(|rf| rf.bytes.clone())
+            .collect()
+    }
 
-        match self.wait_for_frame(FrameT::GoAway).await {
-            FrameWaitOutcome::Success(_frame, payload) => {
-                let (_, goaway) = GoAway::parse(payload).finish().unwrap();
-                let error_c: ErrorC = KnownErrorCode::try_from(goaway.error_code)
-                    .map_err(|_| eyre::eyre!(
-                        "Expected GOAWAY with one of {codes:?}, but got unknown error code {} (0x{:x})",
-                        goaway.error_code.as_repr(), goaway.error_code.as_repr()
-                    ))?
-                    .into();
+    /// Tallies the frames received from the peer so far, by type. Handy to
+    /// assert on how many SETTINGS frames the handshake involved, or to spot
+    /// unexpected bursts of frames.
+    ///
+    /// Panics unless [Config::record_frames] is set.
+    pub fn frame_counts(&self) -> HashMap<FrameT, usize> {
+        assert!(
+            self.config.record_frames,
+            "frame_counts requires Config::record_frames"
+        );
+        let mut counts = HashMap::new();
+        for rf in self.frame_log.borrow().iter() {
+            *counts.entry(FrameT::from(rf.frame.frame_type)).or_default() += 1;
+        }
+        counts
+    }
 
-                if codes.contains(error_c) {
-                    // that's what we expected!
-                    return Ok(());
+    /// Every frame sent and received so far, in order, to compare against a
+    /// golden copy with [Transcript::assert_matches].
+    ///
+    /// Only frames sent with [Conn::write_frame] (and the methods built on
+    /// it) are included, not raw bytes sent with [Conn::send], like the
+    /// connection preface.
+    ///
+    /// Panics unless [Config::record_frames] is set.
+    pub fn transcript(&self) -> Transcript {
+        assert!(
+            self.config.record_frames,
+            "transcript requires Config::record_frames"
+        );
+        self.transcript.borrow().clone()
+    }
+
+    /// Forgets the state the harness keeps for the benefit of a single test,
+    /// like recorded frames, when a connection is reused across tests.
+    ///
+    /// Stream ids are not reset: a stream id can only be used once per
+    /// connection, so `open_stream` keeps counting up.
+    pub fn reset_test_state(&mut self) {
+        self.frame_log.borrow_mut().clear();
+        self.transcript.borrow_mut().clear();
+        self.unmatched_frames.clear();
+    }
+
+    pub async fn write_priority(
+        &mut self,
+        stream_id: StreamId,
+        priority_spec: PrioritySpec,
+    ) -> eyre::Result<()> {
+        self.write_frame(FrameType::Priority.into_frame(stream_id), priority_spec)
+            .await
+    }
+
+    /// Sends a PRIORITY_UPDATE frame (RFC 9218) on stream 0, updating the
+    /// priority of `prioritized_stream_id` to `field_value`, e.g. `u=1, i`.
+    pub async fn send_priority_update(
+        &mut self,
+        prioritized_stream_id: StreamId,
+        field_value: &[u8],
+    ) -> eyre::Result<()> {
+        let field_value = self.scratch.put_to_roll(field_value.len(), |slice| {
+            slice.copy_from_slice(field_value);
+            Ok(())
+        })?;
+        self.write_frame(
+            FrameType::PriorityUpdate.into_frame(StreamId::CONNECTION),
+            PriorityUpdate {
+                prioritized_stream_id,
+                field_value,
+            },
+        )
+        .await
+    }
+
+    /// Sends a frame of type `ty` with the given flags and payload, as is:
+    /// meant for frame types the parser doesn't know about (cf. RFC 9113,
+    /// Section 5.5), which peers must ignore. Nothing stops `ty` from being
+    /// a known type, for sending frames with flags undefined for it.
+    pub async fn send_unknown_frame(
+        &mut self,
+        ty: u8,
+        flags: u8,
+        stream_id: StreamId,
+        payload: &[u8],
+    ) -> eyre::Result<()> {
+        self.write_frame(
+            FrameType::Unknown(EncodedFrameType { ty, flags }).into_frame(stream_id),
+            payload.to_vec(),
+        )
+        .await
+    }
+
+    /// Reads frames until the peer has sent nothing for `idle_for`, so no
+    /// late frame is left over to confuse whatever comes next (e.g. the next
+    /// test on a reused connection). Settings changes are applied and
+    /// acknowledged, WINDOW_UPDATE frames replenish the send windows,
+    /// everything else is discarded.
+    ///
+    /// Errors out if the connection closes in the meantime.
+    pub async fn wait_until_idle(&mut self, idle_for: Duration) -> eyre::Result<()> {
+        loop {
+            let deadline = Instant::now() + idle_for;
+            match self
+                .wait_for_frame_with_deadline(BitFlags::all(), deadline)
+                .await
+            {
+                FrameWaitOutcome::Success(frame, payload) => {
+                    trace!(?frame, "discarding frame while waiting for the connection to go idle");
+                    if matches!(frame.frame_type, FrameType::WindowUpdate) {
+                        if let Ok((_, update)) = WindowUpdate::parse(payload) {
+                            self.apply_window_update(frame.stream_id, update.increment);
+                        }
+                    }
+                }
+                FrameWaitOutcome::Timeout { .. } => return Ok(()),
+                FrameWaitOutcome::Eof { last_frame, .. } => {
+                    return Err(eyre!(
+                        "Peer hung up while we waited for the connection to go idle, last frame: ({last_frame:?})"
+                    ))
+                }
+                FrameWaitOutcome::IoError { error, .. } => {
+                    return Err(eyre!(
+                        "I/O error while waiting for the connection to go idle: {error}"
+                    ))
                 }
-                Err(eyre::eyre!(
-                    "Expected GOAWAY with one of {codes:?}, but got {error_c:?}"
-                ))
-            }
-            FrameWaitOutcome::Timeout { last_frame, .. } => Err(eyre!(
-                "Timed out while waiting for connection error, last frame: ({last_frame:?})"
-            )),
-            FrameWaitOutcome::Eof { .. } => {
-                // that's fine
-                Ok(())
-            }
-            FrameWaitOutcome::IoError { .. } => {
-                // TODO: that's fine if it's a connection reset, we should probably check
-                Ok(())
             }
         }
     }
 
-    pub async fn verify_connection_close(&mut self) -> eyre::Result<()> {
-        match self.wait_for_frame(FrameT::GoAway).await {
-            FrameWaitOutcome::Success(_frame, _payload) => {
-                // that's what we expected!
-                Ok(())
-            }
-            FrameWaitOutcome::Timeout { last_frame, .. } => Err(eyre!(
-                "Timed out while waiting for connection close, last frame: ({last_frame:?})"
-            )),
-            FrameWaitOutcome::Eof { .. } => {
-                // that's fine
-                Ok(())
-            }
-            FrameWaitOutcome::IoError { .. } => {
-                // TODO: that's fine if it's a connection reset, we should probably check
-                Ok(())
+    /// Reads frames for `within`, and errors out if any of them is on
+    /// `stream_id`, other than PRIORITY, which is allowed on closed streams
+    /// (cf. RFC 9113, Section 5.1). Frames on other streams are discarded.
+    ///
+    /// The peer hanging up in the meantime is fine: it sent nothing more on
+    /// the stream.
+    pub async fn verify_no_frame_on_stream(
+        &mut self,
+        stream_id: StreamId,
+        within: Duration,
+    ) -> eyre::Result<()> {
+        let deadline = Instant::now() + within;
+        loop {
+            match self
+                .wait_for_frame_with_deadline(BitFlags::all(), deadline)
+                .await
+            {
+                FrameWaitOutcome::Success(frame, _payload) => {
+                    if frame.stream_id == stream_id
+                        && !matches!(frame.frame_type, FrameType::Priority)
+                    {
+                        return Err(eyre!(
+                            "expected no more frames on stream {stream_id}, got {frame:?}"
+                        ));
+                    }
+                }
+                FrameWaitOutcome::Timeout { .. } | FrameWaitOutcome::Eof { .. } => return Ok(()),
+                FrameWaitOutcome::IoError { error, .. } => {
+                    return Err(eyre!(
+                        "I/O error while checking for frames on stream {stream_id}: {error}"
+                    ))
+                }
             }
         }
     }
 
-    pub async fn verify_stream_close(&mut self, stream_id: StreamId) -> eyre::Result<()> {
-        let mut global_last_frame: Option<Frame> = None;
-        let deadline = Instant::now() + self.config.timeout;
-
+    /// Reads frames for `within`, and errors out if the peer responds on
+    /// `stream_id` with HEADERS, DATA or PUSH_PROMISE frames, or resets it
+    /// with anything but REFUSED_STREAM: for streams the peer should not
+    /// have processed at all, e.g. ones above the last stream identifier of
+    /// its GOAWAY (cf. RFC 9113, Section 6.8). Frames on other streams are
+    /// discarded.
+    ///
+    /// The peer hanging up in the meantime is fine.
+    pub async fn verify_no_response_on_stream(
+        &mut self,
+        stream_id: StreamId,
+        within: Duration,
+    ) -> eyre::Result<()> {
+        let deadline = Instant::now() + within;
         loop {
             match self
                 .wait_for_frame_with_deadline(
-                    FrameT::Data | FrameT::Headers | FrameT::RstStream,
+                    FrameT::Headers | FrameT::Data | FrameT::PushPromise | FrameT::RstStream,
                     deadline,
                 )
                 .await
             {
-                FrameWaitOutcome::Success(frame, payload) => match frame.frame_type {
-                    FrameType::Data(flags) => {
-                        if flags.contains(DataFlags::EndStream) {
-                            assert_eq!(frame.stream_id, stream_id, "unexpected stream ID");
-                            return Ok(());
-                        } else {
-                            global_last_frame = Some(frame);
-                        }
+                FrameWaitOutcome::Success(frame, payload) => {
+                    if frame.stream_id != stream_id {
+                        continue;
                     }
-                    FrameType::Headers(flags) => {
-                        if flags.contains(HeadersFlags::EndStream) {
-                            assert_eq!(frame.stream_id, stream_id, "unexpected stream ID");
+                    if let FrameType::RstStream = frame.frame_type {
+                        let (_, rst_stream) = RstStream::parse(payload).finish().unwrap();
+                        if matches!(
+                            KnownErrorCode::try_from(rst_stream.error_code),
+                            Ok(KnownErrorCode::RefusedStream)
+                        ) {
                             return Ok(());
-                        } else {
-                            global_last_frame = Some(frame);
                         }
+                        return Err(eyre!(
+                            "expected stream {stream_id} to be refused, but it was reset with {:?}",
+                            rst_stream.error_code
+                        ));
                     }
-                    FrameType::RstStream => {
-                        let (_rest, rst_stream) = RstStream::parse(payload).finish().unwrap();
-                        let error_code =
-                            KnownErrorCode::try_from(rst_stream.error_code).map_err(|_| {
-                                eyre::eyre!("expected NO_ERROR code, but got unknown error code")
-                            })?;
-                        assert_eq!(
-                            error_code,
-                            KnownErrorCode::NoError,
-                            "expected RST_STREAM frame with NO_ERROR code"
-                        );
-                        assert_eq!(frame.stream_id, stream_id, "unexpected stream ID");
-                        return Ok(());
-                    }
-                    _ => panic!("unexpected frame type"),
-                },
-                FrameWaitOutcome::Timeout { last_frame, .. } => {
                     return Err(eyre!(
-                        "Timed out while waiting for stream close frame, last frame: ({:?})",
-                        last_frame.or(global_last_frame)
+                        "expected no response on stream {stream_id}, got {frame:?}"
                     ));
                 }
-                FrameWaitOutcome::Eof { .. } => {
-                    // that's fine
-                    return Ok(());
-                }
-                FrameWaitOutcome::IoError { .. } => {
-                    // TODO: that's fine if it's a connection reset, we should probably check
-                    return Ok(());
-                }
+                FrameWaitOutcome::Timeout { .. }
+                | FrameWaitOutcome::Eof { .. }
+                | FrameWaitOutcome::IoError { .. } => return Ok(()),
             }
         }
     }
 
-    /// verify_headers_frame verifies whether a HEADERS frame with specified
-    /// stream ID was received.
-    pub async fn verify_headers_frame(&mut self, stream_id: StreamId) -> eyre::Result<()> {
-        let (frame, _payload) = self.wait_for_frame(FrameT::Headers).await.unwrap();
-        assert_eq!(frame.stream_id, stream_id, "unexpected stream ID");
-        Ok(())
+    /// Waits for a GOAWAY frame from the peer and returns it, or `None` if
+    /// the peer hung up without sending one. Other frames are discarded.
+    pub async fn wait_for_goaway(&mut self) -> eyre::Result<Option<GoAway>> {
+        match self.wait_for_frame(FrameT::GoAway).await {
+            FrameWaitOutcome::Success(_frame, payload) => {
+                let (_, goaway) = GoAway::parse(payload)
+                    .finish()
+                    .map_err(|err| eyre!("peer sent a malformed GOAWAY: {err:?}"))?;
+                Ok(Some(goaway))
+            }
+            FrameWaitOutcome::Timeout { last_frame, .. } => Err(eyre!(
+                "Timed out while waiting for GOAWAY, last frame: ({last_frame:?})"
+            )),
+            FrameWaitOutcome::Eof { .. } | FrameWaitOutcome::IoError { .. } => Ok(None),
+        }
     }
 
-    pub async fn verify_stream_error(
-        &mut self,
-        codes: impl Into<BitFlags<ErrorC>>,
-    ) -> eyre::Result<()> {
-        let codes = codes.into();
-
-        tracing::debug!("waiting for frame GoAaway | RstStream");
-        let frame_wait_outcome = self
-            .wait_for_frame(FrameT::GoAway | FrameT::RstStream)
-            .await;
-        tracing::debug!("waiting for frame GoAaway | RstStream.. got an outcome");
-
-        match frame_wait_outcome {
-            FrameWaitOutcome::Success(frame, payload) => {
-                match frame.frame_type {
-                    FrameType::GoAway => {
-                        let (_, goaway) = GoAway::parse(payload).finish().unwrap();
-                        let error_c: ErrorC = KnownErrorCode::try_from(goaway.error_code)
-                            .map_err(|_| eyre::eyre!(
-                                "Expected GOAWAY or RSTSTREAM with one of {codes:?}, but got unknown error code {} (0x{:x})",
-                                goaway.error_code.as_repr(), goaway.error_code.as_repr()
-                            ))?
-                            .into();
-
-                        if codes.contains(error_c) {
-                            // that's what we expected!
-                            Ok(())
-                        } else {
-                            Err(eyre::eyre!(
-                                "Expected GOAWAY or RSTSTREAM with one of {codes:?}, but got {error_c:?}"
-                            ))
-                        }
+    /// Reads frames for `window`, and returns how many of them were SETTINGS
+    /// frames with the ACK flag. SETTINGS frames carrying parameters aren't
+    /// counted, other frames are discarded.
+    ///
+    /// Errors out if the connection closes in the meantime.
+    pub async fn count_settings_acks(&mut self, window: Duration) -> eyre::Result<usize> {
+        let deadline = Instant::now() + window;
+        let mut acks = 0;
+        loop {
+            match self
+                .wait_for_frame_with_deadline(FrameT::Settings, deadline)
+                .await
+            {
+                FrameWaitOutcome::Success(frame, _payload) => {
+                    if frame.is_ack() {
+                        acks += 1;
                     }
-                    FrameType::RstStream => {
-                        tracing::debug!("waiting for frame GoAaway | RstStream.. got RstStream");
-
-                        let (_, rststream) = RstStream::parse(payload).finish().unwrap();
-                        let error_code = KnownErrorCode::try_from(rststream.error_code)
-                            .map_err(|_| eyre::eyre!(
-                                "Expected GOAWAY or RSTSTREAM with one of {codes:?}, but got unknown error code {} (0x{:x})",
-                                rststream.error_code.as_repr(), rststream.error_code.as_repr()
-                            ))?;
+                }
+                FrameWaitOutcome::Timeout { .. } => return Ok(acks),
+                FrameWaitOutcome::Eof { last_frame, .. } => {
+                    return Err(eyre!(
+                        "Peer hung up while we were counting SETTINGS ACKs, last frame: ({last_frame:?})"
+                    ))
+                }
+                FrameWaitOutcome::IoError { error, .. } => {
+                    return Err(eyre!(
+                        "I/O error while counting SETTINGS ACKs: {error}"
+                    ))
+                }
+            }
+        }
+    }
 
-                        let error_c: ErrorC = error_code.into();
+    /// Waits for a WINDOW_UPDATE frame on `stream_id`, or on the connection
+    /// if it's `None`, and returns its increment, which must be at least
+    /// `min_increment`. WINDOW_UPDATE frames for other streams are skipped.
+    ///
+    /// Increments replenish [Conn::send_window] or
+    /// [Conn::stream_send_window], skipped ones included.
+    pub async fn expect_window_update(
+        &mut self,
+        stream_id: Option<StreamId>,
+        min_increment: u32,
+    ) -> eyre::Result<u32> {
+        let stream_id = stream_id.unwrap_or(StreamId::CONNECTION);
 
-                        if codes.contains(error_c) {
-                            // that's what we expected!
-                            Ok(())
-                        } else {
-                            Err(eyre::eyre!(
-                                "Expected GOAWAY or RSTSTREAM with one of {codes:?}, but got {error_c:?}"
-                            ))
-                        }
-                    }
-                    _ => unreachable!(),
+        loop {
+            let (frame, payload) = match self.wait_for_frame(FrameT::WindowUpdate).await {
+                FrameWaitOutcome::Success(frame, payload) => (frame, payload),
+                FrameWaitOutcome::Timeout { last_frame, .. } => {
+                    return Err(eyre!(
+                        "Timed out waiting for a WINDOW_UPDATE on stream {stream_id}, last frame: ({last_frame:?})"
+                    ))
                 }
+                FrameWaitOutcome::Eof { .. } => {
+                    return Err(eyre!(
+                        "Peer hung up while we were waiting for a WINDOW_UPDATE on stream {stream_id}"
+                    ))
+                }
+                FrameWaitOutcome::IoError { error, .. } => {
+                    return Err(eyre!(
+                        "I/O error while waiting for a WINDOW_UPDATE on stream {stream_id}: {error}"
+                    ))
+                }
+            };
+            let (_, update) = WindowUpdate::parse(payload)
+                .finish()
+                .map_err(|err| eyre!("malformed WINDOW_UPDATE frame: {err:?}"))?;
+            self.apply_window_update(frame.stream_id, update.increment);
+            if frame.stream_id != stream_id {
+                continue;
             }
-            FrameWaitOutcome::Timeout { last_frame, .. } => Err(eyre!(
-                "Timed out while waiting for stream error, last frame: ({last_frame:?})"
-            )),
-            FrameWaitOutcome::Eof { .. } => {
-                // that's fine
-                Ok(())
-            }
-            FrameWaitOutcome::IoError { .. } => {
-                // TODO: that's fine if it's a connection reset, we should probably check
-                Ok(())
+            if update.increment < min_increment {
+                return Err(eyre!(
+                    "WINDOW_UPDATE on stream {stream_id} has an increment of {}, expected at least {min_increment}",
+                    update.increment
+                ));
             }
+            return Ok(update.increment);
         }
     }
 
-    fn common_headers(&self, method: &'static str) -> Headers {
-        let (scheme, default_port) = if self.config.tls {
-            ("https", self.config.port == 443)
-        } else {
-            ("http", self.config.port == 80)
-        };
-
-        let authority = if default_port {
-            self.config.host.clone()
-        } else {
-            format!("{}:{}", self.config.host, self.config.port)
-        };
+    /// Send a PING frame and wait for the peer to acknowledge it.
+    pub async fn verify_connection_still_alive(&mut self) -> eyre::Result<()> {
+        let payload = b"pingpong";
+        self.write_ping(false, &payload[..]).await?;
+        self.verify_ping_frame_with_ack(payload).await?;
+        Ok(())
+    }
 
-        let mut headers = Headers::default();
-        headers.append(":method", method);
-        headers.append(":scheme", scheme);
-        headers.append(":path", self.config.path.clone().into_bytes());
-        headers.append(":authority", authority.into_bytes());
-        headers
+    /// Measures the round-trip time to the peer: sends a PING with a payload
+    /// of its own, and times how long the matching ACK takes to come back.
+    /// ACKs for other PINGs (e.g. late ones) are skipped.
+    pub async fn measure_rtt(&mut self) -> eyre::Result<Duration> {
+        self.pings_sent += 1;
+        let payload = self.pings_sent.to_be_bytes();
+
+        let start = Instant::now();
+        self.write_ping(false, payload.to_vec()).await?;
+        loop {
+            let (frame, received) = match self.wait_for_frame(FrameT::Ping).await {
+                FrameWaitOutcome::Success(frame, payload) => (frame, payload),
+                FrameWaitOutcome::Timeout { last_frame, .. } => {
+                    return Err(eyre!(
+                        "Timed out waiting for our PING to be acknowledged, last frame: ({last_frame:?})"
+                    ))
+                }
+                FrameWaitOutcome::Eof { .. } => {
+                    return Err(eyre!(
+                        "Peer hung up before acknowledging our PING"
+                    ))
+                }
+                FrameWaitOutcome::IoError { error, .. } => {
+                    return Err(eyre!(
+                        "I/O error while waiting for our PING to be acknowledged: {error}"
+                    ))
+                }
+            };
+            if frame.is_ack() && received[..] == payload[..] {
+                return Ok(start.elapsed());
+            }
+            trace!(?frame, "skipping PING while measuring the round-trip time");
+        }
     }
 
-    pub fn encode_headers(&mut self, headers: &Headers) -> eyre::Result<Piece> {
-        // wasteful, but we're doing tests so shrug.
-        let mut fragment = Vec::new();
-        for (k, v) in headers.iter() {
-            self.hpack_enc
-                .encode_header_into((k.as_ref(), v.as_ref()), &mut fragment)?;
+    /// Measures the round-trip time to the peer `samples` times, see
+    /// [Conn::measure_rtt].
+    ///
+    /// PINGs are sent sequentially, with a single one outstanding at a time,
+    /// so that samples don't skew each other.
+    pub async fn measure_rtts(&mut self, samples: usize) -> eyre::Result<Vec<Duration>> {
+        let mut rtts = Vec::with_capacity(samples);
+        for _ in 0..samples {
+            rtts.push(self.measure_rtt().await?);
         }
-        Ok(fragment.into())
+        Ok(rtts)
     }
 
-    /// Note: The buffer should represent the entire block that should be
-    /// decoded. For example, in HTTP/2, all continuation frames need to be
-    /// concatenated to a single buffer before passing them to the decoder.
-    pub fn decode_headers(&mut self, fragment: Piece) -> eyre::Result<Headers> {
-        // note: this allocates a lot but, but again, we're doing tests so shrug.
-        let mut headers = Headers::default();
-        let res = self
-            .hpack_dec
-            .decode(&fragment[..])
-            .map_err(|e| eyre::eyre!("hpack decoder error: {e:?}"))?;
-        for (k, v) in res {
-            headers.append(k, v);
+    pub async fn write_ping(&mut self, ack: bool, payload: impl IntoPiece) -> eyre::Result<()> {
+        self.write_frame(
+            FrameType::Ping(if ack {
+                PingFlags::Ack.into()
+            } else {
+                Default::default()
+            })
+            .into_frame(StreamId::CONNECTION),
+            payload,
+        )
+        .await
+    }
+
+    pub async fn write_and_ack_settings(
+        &mut self,
+        settings: impl Into<SettingPairs<'_>>,
+    ) -> eyre::Result<()> {
+        self.write_settings(settings).await?;
+        self.verify_settings_frame_with_ack().await?;
+        Ok(())
+    }
+
+    pub async fn write_settings(
+        &mut self,
+        settings: impl Into<SettingPairs<'_>>,
+    ) -> eyre::Result<()> {
+        let settings = settings.into();
+        self.note_local_settings(&settings);
+
+        self.write_frame(
+            FrameType::Settings(Default::default()).into_frame(StreamId::CONNECTION),
+            settings,
+        )
+        .await
+    }
+
+    /// Sends a SETTINGS frame and, right behind it in the same write, the
+    /// `followups` frames (their length is filled in from their payload),
+    /// without waiting for the peer to acknowledge the settings. The peer
+    /// must apply them before processing any frame that follows (cf. RFC
+    /// 9113, Section 6.5.3). Waiting for the ACK is left to the caller.
+    pub async fn send_settings_then(
+        &mut self,
+        settings: impl Into<SettingPairs<'_>>,
+        followups: Vec<(Frame, Piece)>,
+    ) -> eyre::Result<()> {
+        let settings = settings.into();
+        self.note_local_settings(&settings);
+
+        let settings_frame =
+            FrameType::Settings(Default::default()).into_frame(StreamId::CONNECTION);
+        let settings_payload = settings.into_piece(&mut self.scratch)?;
+
+        let mut list = PieceList::default();
+        let mut frames = Vec::with_capacity(1 + followups.len());
+        for (frame, payload) in std::iter::once((settings_frame, settings_payload)).chain(followups)
+        {
+            let frame = frame.with_len(payload.len().try_into().unwrap());
+            list.push_back(frame.into_piece(&mut self.scratch)?);
+            list.push_back(payload);
+            frames.push(frame);
         }
 
-        tracing::trace!("decoded {} headers:", headers.len());
-        for (k, v) in headers.iter() {
-            tracing::trace!(
-                "    {:?} => {:?}",
-                String::from_utf8_lossy(&k[..]),
-                String::from_utf8_lossy(&v[..])
-            );
+        let len = list.len();
+        self.w.writev_all_owned(list).await?;
+        self.sent_bytes += len as u64;
+        for frame in frames {
+            self.on_frame_sent(frame);
         }
+        Ok(())
+    }
 
-        Ok(headers)
+    /// Keeps track of the settings we're about to advertise, for the receive
+    /// loop to enforce.
+    fn note_local_settings(&self, settings: &SettingPairs<'_>) {
+        for &(k, v) in settings.0 {
+            // invalid values are for tests that expect the peer to reject them
+            if matches!(k, Setting::MaxFrameSize)
+                && Settings::MAX_FRAME_SIZE_ALLOWED_RANGE.contains(&v)
+            {
+                self.local_max_frame_size.set(v);
+            }
+        }
+    }
+
+    /// Waits for a certain kind of frame
+    ///
+    /// If [Config::auto_ack_ping] is set, PING frames without the ACK flag
+    /// are answered and skipped, even if `types` contains [FrameT::Ping].
+    ///
+    /// Frames of other types are discarded, unless
+    /// [Config::buffer_unmatched_frames] is set: then they're queued, and
+    /// later calls look at the queue before reading more frames. Frames are
+    /// always returned in the order they arrived, among those of the
+    /// requested types: a queued frame is returned before any matching
+    /// frame that arrived after it.
+    pub async fn wait_for_frame(&mut self, types: impl Into<BitFlags<FrameT>>) -> FrameWaitOutcome {
+        let deadline = Instant::now() + self.config.timeout;
+        self.wait_for_frame_with_deadline(types, deadline).await
+    }
+
+    /// Like [Conn::wait_for_frame], but returns transport errors (e.g. a
+    /// connection reset) instead of panicking on them, for tests where that's
+    /// the expected outcome. Still panics on timeouts and EOF.
+    pub async fn wait_for_frame_or_error(
+        &mut self,
+        types: impl Into<BitFlags<FrameT>>,
+    ) -> Result<(Frame, Roll), std::io::Error> {
+        match self.wait_for_frame(types).await {
+            FrameWaitOutcome::IoError { error, .. } => Err(error),
+            outcome => Ok(outcome.unwrap()),
+        }
+    }
+
+    /// Like [Conn::wait_for_frame], but also returns when the frame was
+    /// received: when the receive loop finished reading it, not when the
+    /// test got to look at it. Handy to measure gaps between frames.
+    pub async fn wait_for_frame_timed(
+        &mut self,
+        types: impl Into<BitFlags<FrameT>>,
+    ) -> eyre::Result<(Frame, Roll, Instant)> {
+        match self.wait_for_frame(types).await {
+            FrameWaitOutcome::Success(frame, payload) => {
+                let received_at = self
+                    .last_received_at
+                    .expect("frames are timestamped as they're received");
+                Ok((frame, payload, received_at))
+            }
+            FrameWaitOutcome::Timeout {
+                wanted,
+                last_frame,
+                waited,
+            } => Err(eyre!(
+                "Wanted ({}), timed out after {waited:?}. Last frame: {last_frame:?}",
+                FrameT::describe(wanted)
+            )),
+            FrameWaitOutcome::Eof { wanted, last_frame } => Err(eyre!(
+                "Wanted ({}), peer hung up. Last frame: {last_frame:?}",
+                FrameT::describe(wanted)
+            )),
+            FrameWaitOutcome::IoError {
+                wanted,
+                last_frame,
+                error,
+            } => Err(eyre!(
+                "Wanted ({}), got I/O error {error}. Last frame: {last_frame:?}",
+                FrameT::describe(wanted)
+            )),
+        }
+    }
+
+    /// Puts a frame returned by [Conn::wait_for_frame] back, so the next
+    /// call that wants its type returns it again, e.g. to hand a HEADERS
+    /// frame over to [Conn::read_header_block] after looking at it.
+    pub(crate) fn put_back_frame(&mut self, frame: Frame, payload: Roll) {
+        let received_at = self.last_received_at.unwrap_or_else(Instant::now);
+        self.unmatched_frames
+            .push_front((frame, payload, received_at));
+    }
+
+    /// Waits for the next frame, of any type. See [Conn::wait_for_frame].
+    pub async fn next_frame(&mut self) -> FrameWaitOutcome {
+        self.wait_for_frame(BitFlags::all()).await
+    }
+
+    /// Waits for a certain kind of frame with a specified deadline
+    pub async fn wait_for_frame_with_deadline(
+        &mut self,
+        types: impl Into<BitFlags<FrameT>>,
+        deadline: Instant,
+    ) -> FrameWaitOutcome {
+        let types = types.into();
+        let mut last_frame: Option<Frame> = None;
+
+        if let Some(index) = self
+            .unmatched_frames
+            .iter()
+            .position(|(frame, _, _)| types.contains(FrameT::from(frame.frame_type)))
+        {
+            let (frame, payload, received_at) = self.unmatched_frames.remove(index).unwrap();
+            self.last_received_at = Some(received_at);
+            return FrameWaitOutcome::Success(frame, payload);
+        }
+
+        loop {
+            match tokio::time::timeout_at(deadline, self.ev_rx.recv()).await {
+                Err(_) => {
+                    return FrameWaitOutcome::Timeout {
+                        wanted: types,
+                        last_frame,
+                        waited: self.config.timeout,
+                    };
+                }
+                Ok(maybe_ev) => match maybe_ev {
+                    None => {
+                        return FrameWaitOutcome::Eof {
+                            wanted: types,
+                            last_frame,
+                        }
+                    }
+                    Some(ev) => match ev {
+                        Ev::Frame { frame, payload, .. }
+                            if self.config.auto_ack_ping
+                                && matches!(frame.frame_type, FrameType::Ping(_))
+                                && !frame.is_ack() =>
+                        {
+                            debug!("auto-acknowledging PING from peer");
+                            if let Err(e) = self.write_ping(true, payload).await {
+                                return FrameWaitOutcome::IoError {
+                                    wanted: types,
+                                    last_frame: Some(frame),
+                                    error: std::io::Error::other(e.to_string()),
+                                };
+                            }
+                        }
+                        Ev::Frame {
+                            frame,
+                            payload,
+                            received_at,
+                        } => {
+                            if types.contains(FrameT::from(frame.frame_type)) {
+                                self.last_received_at = Some(received_at);
+                                return FrameWaitOutcome::Success(frame, payload);
+                            } else {
+                                if self.config.buffer_unmatched_frames {
+                                    self.unmatched_frames
+                                        .push_back((frame, payload, received_at));
+                                }
+                                last_frame = Some(frame)
+                            }
+                        }
+                        Ev::SettingsChanged { frame, changes } => {
+                            debug!(?changes, "peer changed its settings mid-connection");
+                            last_frame = Some(frame);
+
+                            if let Err(error) = self.apply_settings_change(changes).await {
+                                return FrameWaitOutcome::IoError {
+                                    wanted: types,
+                                    last_frame,
+                                    error,
+                                };
+                            }
+                        }
+                        Ev::IoError { error } => {
+                            return FrameWaitOutcome::IoError {
+                                wanted: types,
+                                last_frame,
+                                error,
+                            }
+                        }
+                    },
+                },
+            }
+        }
+    }
+
+    /// Waits for a PING frame with Ack flag and the specified payload.
+    /// It will NOT ignore other PING frames, if the first frame it
+    /// receives doesn't have the expected payload, it will return an error.
+    ///
+    /// With [Config::auto_ack_ping], PING frames initiated by the peer
+    /// (keepalives) are acknowledged and skipped, so only PING ACKs are
+    /// considered here.
+    pub async fn verify_ping_frame_with_ack(&mut self, payload: &[u8]) -> eyre::Result<()> {
+        let (frame, received_payload) = self.wait_for_frame(FrameT::Ping).await.unwrap();
+        assert!(frame.is_ack(), "expected PING frame to have ACK flag");
+
+        assert_eq!(received_payload, payload, "unexpected PING payload");
+
+        Ok(())
+    }
+
+    pub async fn handshake(&mut self) -> eyre::Result<()> {
+        let record = self.handshake_capture().await?;
+        if record.settings_ack_after.is_none() {
+            return Err(eyre!(
+                "server didn't acknowledge our SETTINGS within {:?}",
+                self.config.timeout
+            ));
+        }
+        Ok(())
+    }
+
+    /// Performs the handshake like [Conn::handshake], but keeps what was
+    /// exchanged, for tests that look at the specifics: the server's exact
+    /// SETTINGS, how long it took to acknowledge ours, the bytes on the wire.
+    ///
+    /// The server not acknowledging our SETTINGS in time isn't an error
+    /// here, see [HandshakeRecord::settings_ack_after].
+    pub async fn handshake_capture(&mut self) -> eyre::Result<HandshakeRecord> {
+        // perform an HTTP/2 handshake as a client
+        let payload = default_settings().into_piece(&mut self.scratch)?;
+        let header = FrameType::Settings(Default::default())
+            .into_frame(StreamId::CONNECTION)
+            .with_len(payload.len().try_into().unwrap())
+            .into_piece(&mut self.scratch)?;
+
+        let mut sent = PREFACE.to_vec();
+        sent.extend_from_slice(&header[..]);
+        sent.extend_from_slice(&payload[..]);
+
+        if self.config.coalesce_handshake {
+            self.note_local_settings(&default_settings());
+            self.send(sent.clone()).await?;
+        } else {
+            self.send_preface(PREFACE).await?;
+            self.write_settings(default_settings()).await?;
+        }
+        let settings_sent_at = Instant::now();
+
+        let (frame, payload) = self
+            .wait_for_frame(FrameT::Settings | FrameT::GoAway)
+            .await
+            .unwrap();
+        if matches!(frame.frame_type, FrameType::GoAway) {
+            let (_, goaway) = GoAway::parse(payload).finish().map_err(|err| {
+                eyre!("server sent a malformed GOAWAY before its SETTINGS: {err:?}")
+            })?;
+            return Err(eyre!(
+                "server sent GOAWAY before its SETTINGS, with error code {:?} (last stream id {})",
+                goaway.error_code,
+                goaway.last_stream_id
+            ));
+        }
+        assert!(
+            !frame.is_ack(),
+            "server should send their settings first thing (no ack)"
+        );
+
+        let mut received = frame.into_piece(&mut self.scratch)?.to_vec();
+        received.extend_from_slice(&payload[..]);
+
+        let mut settings = Vec::new();
+        let outcome = Settings::parse(&payload[..], |k, v| {
+            settings.push((k, v));
+            self.advertised_settings |= 1 << k.repr();
+            self.settings.apply(k, v)
+        })?;
+        if outcome.saw_duplicates {
+            debug!("server SETTINGS repeat some identifiers, the last value wins");
+        }
+
+        let ack = Frame::settings_ack();
+        sent.extend_from_slice(&ack.into_piece(&mut self.scratch)?[..]);
+        self.write_frame(ack, ()).await?;
+
+        // and wait until the server acknowledges our settings. Other frames
+        // may come first, or in the same read (e.g. a connection-level
+        // WINDOW_UPDATE): they're taken into account, and kept for later if
+        // `buffer_unmatched_frames` is set. Those are only put back once the
+        // ACK is in: `wait_for_frame` would hand them right back otherwise.
+        let deadline = Instant::now() + self.config.timeout;
+        let mut skipped = Vec::new();
+        let settings_ack_after = loop {
+            match self
+                .wait_for_frame_with_deadline(BitFlags::all(), deadline)
+                .await
+            {
+                FrameWaitOutcome::Success(frame, payload) => {
+                    if matches!(frame.frame_type, FrameType::Settings(_)) {
+                        assert!(frame.is_ack(), "server should acknowledge our settings");
+                        // cf. RFC 9113, Section 6.5: that's a FRAME_SIZE_ERROR
+                        if !payload.is_empty() {
+                            return Err(eyre!(
+                                "server sent a SETTINGS ACK with a {}-byte payload, it must be empty",
+                                payload.len()
+                            ));
+                        }
+                        received.extend_from_slice(&frame.into_piece(&mut self.scratch)?[..]);
+                        break Some(settings_sent_at.elapsed());
+                    }
+
+                    debug!(
+                        ?frame,
+                        "got a frame before the server acknowledged our settings"
+                    );
+                    if self.config.buffer_unmatched_frames {
+                        // whoever picks a WINDOW_UPDATE up later credits it
+                        let received_at = self.last_received_at.unwrap_or_else(Instant::now);
+                        skipped.push((frame, payload, received_at));
+                    } else if matches!(frame.frame_type, FrameType::WindowUpdate) {
+                        if let Ok((_, update)) = WindowUpdate::parse(payload) {
+                            self.apply_window_update(frame.stream_id, update.increment);
+                        }
+                    }
+                }
+                FrameWaitOutcome::Timeout { .. } => break None,
+                FrameWaitOutcome::Eof { .. } => {
+                    return Err(eyre!("server hung up before acknowledging our SETTINGS"))
+                }
+                FrameWaitOutcome::IoError { error, .. } => {
+                    return Err(eyre!(
+                    "I/O error while waiting for the server to acknowledge our SETTINGS: {error}"
+                ))
+                }
+            }
+        };
+        self.unmatched_frames.extend(skipped);
+
+        Ok(HandshakeRecord {
+            settings,
+            settings_ack_after,
+            sent,
+            received,
+        })
+    }
+
+    /// Returns true if the peer explicitly sent the given setting in its
+    /// initial SETTINGS frame. Only meaningful after [Conn::handshake].
+    pub fn peer_advertised(&self, setting: Setting) -> bool {
+        self.advertised_settings & (1 << setting.repr()) != 0
+    }
+
+    /// Returns true if the peer is willing to receive PUSH_PROMISE frames.
+    /// Servers never are: they either omit SETTINGS_ENABLE_PUSH or set it
+    /// to 0.
+    pub fn peer_supports_push(&self) -> bool {
+        self.settings.enable_push
+    }
+
+    /// Returns the peer's SETTINGS_MAX_HEADER_LIST_SIZE, if it advertised
+    /// one (the initial value is unlimited).
+    pub fn peer_max_header_list_size(&self) -> Option<u32> {
+        self.peer_advertised(Setting::MaxHeaderListSize)
+            .then_some(self.settings.max_header_list_size)
+    }
+
+    /// Returns the peer's SETTINGS_MAX_CONCURRENT_STREAMS, if it advertised
+    /// one (the initial value is unlimited).
+    pub fn peer_max_concurrent_streams(&self) -> Option<u32> {
+        if self.peer_advertised(Setting::MaxConcurrentStreams) {
+            self.settings.max_concurrent_streams
+        } else {
+            None
+        }
     }
 
-    pub async fn send_empty_post_to_root(&mut self, stream_id: StreamId) -> eyre::Result<()> {
-        self.encode_and_write_headers(
-            stream_id,
-            HeadersFlags::EndStream | HeadersFlags::EndHeaders,
-            &self.common_headers("POST"),
-        )
-        .await
+    /// Sends a client connection preface, normally [PREFACE], but tests can
+    /// pass something slightly wrong to check that the server rejects it.
+    /// Only the preface is sent: the SETTINGS frame that must follow it is a
+    /// separate step, see [Conn::write_settings].
+    pub async fn send_preface(&mut self, bytes: &[u8]) -> eyre::Result<()> {
+        self.send(bytes.to_vec()).await
+    }
+
+    /// Sends the client connection preface in two writes: the first
+    /// `split_at` bytes, then the rest after waiting for `gap`, to check
+    /// that the server doesn't expect it to arrive all at once.
+    pub async fn send_preface_fragmented(
+        &mut self,
+        split_at: usize,
+        gap: Duration,
+    ) -> eyre::Result<()> {
+        assert!(
+            (1..PREFACE.len()).contains(&split_at),
+            "split_at must be within the preface, got {split_at}"
+        );
+
+        let (head, tail) = PREFACE.split_at(split_at);
+        self.send(head.to_vec()).await?;
+        tokio::time::sleep(gap).await;
+        self.send(tail.to_vec()).await
+    }
+
+    pub async fn send(&mut self, buf: impl Into<Piece>) -> eyre::Result<()> {
+        let buf = buf.into();
+        let len = buf.len();
+        self.w.write_all_owned(buf).await?;
+        self.sent_bytes += len as u64;
+        Ok(())
+    }
+
+    /// Waits for a GOAWAY frame with one of the given error codes, which
+    /// must be on stream 0 (see Section 6.8). The peer closing the
+    /// connection is also accepted.
+    pub async fn verify_connection_error(
+        &mut self,
+        codes: impl Into<BitFlags<ErrorC>>,
+    ) -> eyre::Result<()> {
+        let codes = codes.into();
+
+        match self.wait_for_frame(FrameT::GoAway).await {
+            FrameWaitOutcome::Success(frame, payload) => {
+                if frame.stream_id != StreamId::CONNECTION {
+                    return Err(eyre!(
+                        "Expected GOAWAY on stream 0, but got it on stream {}",
+                        frame.stream_id
+                    ));
+                }
+
+                let (_, goaway) = GoAway::parse(payload).finish().unwrap();
+                let error_c: ErrorC = KnownErrorCode::try_from(goaway.error_code)
+                    .map_err(|_| eyre::eyre!(
+                        "Expected GOAWAY with one of {codes:?}, but got unknown error code {} (0x{:x})",
+                        goaway.error_code.as_repr(), goaway.error_code.as_repr()
+                    ))?
+                    .into();
+
+                if codes.contains(error_c) {
+                    // that's what we expected!
+                    return Ok(());
+                }
+                Err(eyre::eyre!(
+                    "Expected GOAWAY with one of {codes:?}, but got {error_c:?}"
+                ))
+            }
+            FrameWaitOutcome::Timeout { last_frame, .. } => Err(eyre!(
+                "Timed out while waiting for connection error, last frame: ({last_frame:?})"
+            )),
+            FrameWaitOutcome::Eof { .. } => {
+                // that's fine
+                Ok(())
+            }
+            FrameWaitOutcome::IoError { .. } => {
+                // TODO: that's fine if it's a connection reset, we should probably check
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn verify_connection_close(&mut self) -> eyre::Result<()> {
+        match self.wait_for_frame(FrameT::GoAway).await {
+            FrameWaitOutcome::Success(_frame, _payload) => {
+                // that's what we expected!
+                Ok(())
+            }
+            FrameWaitOutcome::Timeout { last_frame, .. } => Err(eyre!(
+                "Timed out while waiting for connection close, last frame: ({last_frame:?})"
+            )),
+            FrameWaitOutcome::Eof { .. } => {
+                // that's fine
+                Ok(())
+            }
+            FrameWaitOutcome::IoError { .. } => {
+                // TODO: that's fine if it's a connection reset, we should probably check
+                Ok(())
+            }
+        }
+    }
+
+    /// Waits for the peer to close the connection without sending anything
+    /// else: EOF and connection resets are fine, any frame is an error. This
+    /// is the counterpart to [Conn::verify_connection_error], for servers that
+    /// just drop the connection.
+    pub async fn expect_connection_closed(&mut self) -> eyre::Result<()> {
+        match self.next_frame().await {
+            FrameWaitOutcome::Success(frame, _payload) => Err(eyre!(
+                "Expected the connection to be closed, but got {frame:?}"
+            )),
+            FrameWaitOutcome::Timeout { last_frame, .. } => Err(eyre!(
+                "Timed out while waiting for the connection to be closed, last frame: ({last_frame:?})"
+            )),
+            FrameWaitOutcome::Eof { .. } => Ok(()),
+            FrameWaitOutcome::IoError { error, .. } => match error.kind() {
+                std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::BrokenPipe => Ok(()),
+                _ => Err(eyre!(
+                    "I/O error while waiting for the connection to be closed: {error}"
+                )),
+            },
+        }
+    }
+
+    /// Waits for the peer to report an error with one of `codes`, whichever
+    /// way it picked: a GOAWAY or RST_STREAM frame, or closing the
+    /// connection (EOF, or a reset), which RFC 9113 allows in many places.
+    /// It's the union of [Conn::verify_stream_error] and
+    /// [Conn::expect_connection_closed], and tells which one happened.
+    ///
+    /// Other frames are skipped. A GOAWAY or RST_STREAM frame with another
+    /// error code is an error, and so is the peer not doing anything.
+    pub async fn expect_error_or_close(
+        &mut self,
+        codes: impl Into<BitFlags<ErrorC>>,
+    ) -> eyre::Result<ErrorOutcome> {
+        let codes = codes.into();
+
+        match self
+            .wait_for_frame(FrameT::GoAway | FrameT::RstStream)
+            .await
+        {
+            FrameWaitOutcome::Success(frame, payload) => {
+                // the last stream id is only there for GOAWAY
+                let (error_code, last_stream_id) = match frame.frame_type {
+                    FrameType::GoAway => {
+                        let (_, goaway) = GoAway::parse(payload)
+                            .finish()
+                            .map_err(|err| eyre!("peer sent a malformed GOAWAY: {err:?}"))?;
+                        (goaway.error_code, Some(goaway.last_stream_id))
+                    }
+                    _ => {
+                        let (_, rst_stream) = RstStream::parse(payload)
+                            .finish()
+                            .map_err(|err| eyre!("peer sent a malformed RST_STREAM: {err:?}"))?;
+                        (rst_stream.error_code, None)
+                    }
+                };
+                let error_c: ErrorC = KnownErrorCode::try_from(error_code)
+                    .map_err(|_| {
+                        eyre!(
+                            "Expected one of {codes:?}, but got {} with unknown error code {} (0x{:x})",
+                            FrameT::from(frame.frame_type),
+                            error_code.as_repr(),
+                            error_code.as_repr()
+                        )
+                    })?
+                    .into();
+                if !codes.contains(error_c) {
+                    return Err(eyre!(
+                        "Expected one of {codes:?}, but got {} with {error_c:?}",
+                        FrameT::from(frame.frame_type)
+                    ));
+                }
+                Ok(match last_stream_id {
+                    Some(last_stream_id) => ErrorOutcome::GoAway {
+                        error: error_c,
+                        last_stream_id,
+                    },
+                    None => ErrorOutcome::RstStream {
+                        error: error_c,
+                        stream_id: frame.stream_id,
+                    },
+                })
+            }
+            FrameWaitOutcome::Timeout { last_frame, .. } => Err(eyre!(
+                "Timed out while waiting for an error or the connection to be closed, last frame: ({last_frame:?})"
+            )),
+            FrameWaitOutcome::Eof { .. } => Ok(ErrorOutcome::Closed),
+            FrameWaitOutcome::IoError { error, .. } => match error.kind() {
+                std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::BrokenPipe => Ok(ErrorOutcome::Closed),
+                _ => Err(eyre!(
+                    "I/O error while waiting for an error or the connection to be closed: {error}"
+                )),
+            },
+        }
+    }
+
+    /// Checks that the peer coped with abusive traffic (e.g. after
+    /// [Conn::send_rapid_resets]): either it limited it, by sending a GOAWAY
+    /// with ENHANCE_YOUR_CALM or NO_ERROR and/or hanging up, or it still
+    /// acknowledges a PING in time. Frames for the streams involved are
+    /// skipped over.
+    pub async fn verify_flood_handled(&mut self) -> eyre::Result<()> {
+        // if the peer hung up already, writing fails: that's fine, the
+        // closed connection is what we'll see next
+        if let Err(e) = self.write_ping(false, dummy_bytes(8)).await {
+            debug!("couldn't send PING after the flood: {e}");
+        }
+
+        loop {
+            match self.next_frame().await {
+                FrameWaitOutcome::Success(frame, payload) => match frame.frame_type {
+                    FrameType::GoAway => {
+                        let (_, goaway) = GoAway::parse(payload).finish().unwrap();
+                        let error_c = KnownErrorCode::try_from(goaway.error_code)
+                            .ok()
+                            .map(ErrorC::from);
+                        return match error_c {
+                            Some(ErrorC::EnhanceYourCalm | ErrorC::NoError) => Ok(()),
+                            _ => Err(eyre!(
+                                "Expected the flood to be handled, or limited with a GOAWAY with ENHANCE_YOUR_CALM or NO_ERROR, but got GOAWAY with error code {:#x}",
+                                goaway.error_code.as_repr()
+                            )),
+                        };
+                    }
+                    FrameType::Ping(flags) if flags.contains(PingFlags::Ack) => return Ok(()),
+                    _ => {
+                        trace!(?frame, "skipping frame while waiting for the flood to be handled");
+                    }
+                },
+                FrameWaitOutcome::Timeout { last_frame, .. } => {
+                    return Err(eyre!(
+                        "Timed out waiting for the flood to be handled: the peer neither limited it nor acknowledged our PING, last frame: ({last_frame:?})"
+                    ))
+                }
+                FrameWaitOutcome::Eof { .. } => return Ok(()),
+                FrameWaitOutcome::IoError { error, .. } => match error.kind() {
+                    std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::BrokenPipe => return Ok(()),
+                    _ => {
+                        return Err(eyre!(
+                            "I/O error while waiting for the flood to be handled: {error}"
+                        ))
+                    }
+                },
+            }
+        }
+    }
+
+    pub async fn verify_stream_close(&mut self, stream_id: StreamId) -> eyre::Result<()> {
+        let mut global_last_frame: Option<Frame> = None;
+        let deadline = Instant::now() + self.config.timeout;
+
+        loop {
+            match self
+                .wait_for_frame_with_deadline(
+                    FrameT::Data | FrameT::Headers | FrameT::RstStream,
+                    deadline,
+                )
+                .await
+            {
+                FrameWaitOutcome::Success(frame, payload) => match frame.frame_type {
+                    FrameType::Data(flags) => {
+                        if flags.contains(DataFlags::EndStream) {
+                            assert_eq!(frame.stream_id, stream_id, "unexpected stream ID");
+                            return Ok(());
+                        } else {
+                            global_last_frame = Some(frame);
+                        }
+                    }
+                    FrameType::Headers(flags) => {
+                        if flags.contains(HeadersFlags::EndStream) {
+                            assert_eq!(frame.stream_id, stream_id, "unexpected stream ID");
+                            return Ok(());
+                        } else {
+                            global_last_frame = Some(frame);
+                        }
+                    }
+                    FrameType::RstStream => {
+                        let (_rest, rst_stream) = RstStream::parse(payload).finish().unwrap();
+                        let error_code =
+                            KnownErrorCode::try_from(rst_stream.error_code).map_err(|_| {
+                                eyre::eyre!("expected NO_ERROR code, but got unknown error code")
+                            })?;
+                        assert_eq!(
+                            error_code,
+                            KnownErrorCode::NoError,
+                            "expected RST_STREAM frame with NO_ERROR code"
+                        );
+                        assert_eq!(frame.stream_id, stream_id, "unexpected stream ID");
+                        return Ok(());
+                    }
+                    _ => panic!("unexpected frame type"),
+                },
+                FrameWaitOutcome::Timeout { last_frame, .. } => {
+                    return Err(eyre!(
+                        "Timed out while waiting for stream close frame, last frame: ({:?})",
+                        last_frame.or(global_last_frame)
+                    ));
+                }
+                FrameWaitOutcome::Eof { .. } => {
+                    // that's fine
+                    return Ok(());
+                }
+                FrameWaitOutcome::IoError { .. } => {
+                    // TODO: that's fine if it's a connection reset, we should probably check
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// verify_headers_frame verifies whether a HEADERS frame with specified
+    /// stream ID was received.
+    pub async fn verify_headers_frame(&mut self, stream_id: StreamId) -> eyre::Result<()> {
+        let (frame, _payload) = self.wait_for_frame(FrameT::Headers).await.unwrap();
+        assert_eq!(frame.stream_id, stream_id, "unexpected stream ID");
+        Ok(())
+    }
+
+    pub async fn verify_stream_error(
+        &mut self,
+        codes: impl Into<BitFlags<ErrorC>>,
+    ) -> eyre::Result<()> {
+        let codes = codes.into();
+
+        tracing::debug!("waiting for frame GoAaway | RstStream");
+        let frame_wait_outcome = self
+            .wait_for_frame(FrameT::GoAway | FrameT::RstStream)
+            .await;
+        tracing::debug!("waiting for frame GoAaway | RstStream.. got an outcome");
+
+        match frame_wait_outcome {
+            FrameWaitOutcome::Success(frame, payload) => {
+                match frame.frame_type {
+                    FrameType::GoAway => {
+                        let (_, goaway) = GoAway::parse(payload).finish().unwrap();
+                        let error_c: ErrorC = KnownErrorCode::try_from(goaway.error_code)
+                            .map_err(|_| eyre::eyre!(
+                                "Expected GOAWAY or RSTSTREAM with one of {codes:?}, but got unknown error code {} (0x{:x})",
+                                goaway.error_code.as_repr(), goaway.error_code.as_repr()
+                            ))?
+                            .into();
+
+                        if codes.contains(error_c) {
+                            // that's what we expected!
+                            Ok(())
+                        } else {
+                            Err(eyre::eyre!(
+                                "Expected GOAWAY or RSTSTREAM with one of {codes:?}, but got {error_c:?}"
+                            ))
+                        }
+                    }
+                    FrameType::RstStream => {
+                        tracing::debug!("waiting for frame GoAaway | RstStream.. got RstStream");
+
+                        let (_, rststream) = RstStream::parse(payload).finish().unwrap();
+                        let error_code = KnownErrorCode::try_from(rststream.error_code)
+                            .map_err(|_| eyre::eyre!(
+                                "Expected GOAWAY or RSTSTREAM with one of {codes:?}, but got unknown error code {} (0x{:x})",
+                                rststream.error_code.as_repr(), rststream.error_code.as_repr()
+                            ))?;
+
+                        let error_c: ErrorC = error_code.into();
+
+                        if codes.contains(error_c) {
+                            // that's what we expected!
+                            Ok(())
+                        } else {
+                            Err(eyre::eyre!(
+                                "Expected GOAWAY or RSTSTREAM with one of {codes:?}, but got {error_c:?}"
+                            ))
+                        }
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            FrameWaitOutcome::Timeout { last_frame, .. } => Err(eyre!(
+                "Timed out while waiting for stream error, last frame: ({last_frame:?})"
+            )),
+            FrameWaitOutcome::Eof { .. } => {
+                // that's fine
+                Ok(())
+            }
+            FrameWaitOutcome::IoError { .. } => {
+                // TODO: that's fine if it's a connection reset, we should probably check
+                Ok(())
+            }
+        }
+    }
+
+    pub fn common_headers(&self, method: &'static str) -> Headers {
+        let (scheme, default_port) = if self.config.tls {
+            ("https", self.config.port == 443)
+        } else {
+            ("http", self.config.port == 80)
+        };
+
+        let authority = if default_port {
+            self.config.host.clone()
+        } else {
+            format!("{}:{}", self.config.host, self.config.port)
+        };
+
+        let mut headers = Headers::default();
+        headers.append(":method", method);
+        headers.append(":scheme", scheme);
+        headers.append(":path", self.config.path.clone().into_bytes());
+        headers.append(":authority", authority.into_bytes());
+        headers
+    }
+
+    pub fn encode_headers(&mut self, headers: &Headers) -> eyre::Result<Piece> {
+        // wasteful, but we're doing tests so shrug.
+        let mut fragment = Vec::new();
+        for (k, v) in headers.iter() {
+            self.hpack_enc
+                .encode_header_into((k.as_ref(), v.as_ref()), &mut fragment)?;
+        }
+        Ok(fragment.into())
+    }
+
+    /// Sends a response on `stream_id`, acting as a (minimal) server: a
+    /// HEADERS frame with `:status` followed by `headers`, then `body` as DATA
+    /// frames no larger than the peer's SETTINGS_MAX_FRAME_SIZE. The last
+    /// frame carries END_STREAM.
+    pub async fn send_response(
+        &mut self,
+        stream_id: StreamId,
+        status: u16,
+        headers: &Headers,
+        body: &[u8],
+    ) -> eyre::Result<()> {
+        let mut block = Headers::default();
+        block.append(":status", status.to_string().into_bytes());
+        for (k, v) in headers.iter() {
+            block.append(k.clone(), v.clone());
+        }
+
+        if body.is_empty() {
+            return self
+                .encode_and_write_headers(
+                    stream_id,
+                    HeadersFlags::EndHeaders | HeadersFlags::EndStream,
+                    &block,
+                )
+                .await;
+        }
+
+        self.encode_and_write_headers(stream_id, HeadersFlags::EndHeaders, &block)
+            .await?;
+        let mut chunks = body
+            .chunks(self.settings.max_frame_size as usize)
+            .peekable();
+        while let Some(chunk) = chunks.next() {
+            let end_stream = chunks.peek().is_none();
+            self.write_data(stream_id, end_stream, chunk.to_vec())
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Sends a complete request on the next stream from `open_stream`, with
+    /// the given malformation (see [MalformedRequest]), and returns its stream
+    /// id. The server should answer with a stream error of type
+    /// PROTOCOL_ERROR (Section 8.1.1).
+    ///
+    /// The header block is built by hand, out of literal fields that don't
+    /// touch the HPACK dynamic table, so nothing gets normalized on the way
+    /// and the connection's HPACK state is unaffected.
+    pub async fn send_malformed_request(
+        &mut self,
+        variant: MalformedRequest,
+    ) -> eyre::Result<StreamId> {
+        let mut fields: Vec<(Piece, Piece)> = self.common_headers("GET").iter().cloned().collect();
+        match variant {
+            MalformedRequest::UppercaseFieldName => {
+                fields.push(("X-Uppercase".into(), "oh no".into()));
+            }
+            MalformedRequest::PseudoHeaderAfterRegular => {
+                let path = fields.iter().position(|(k, _)| *k == ":path").unwrap();
+                let path = fields.remove(path);
+                fields.push(("x-regular".into(), "1".into()));
+                fields.push(path);
+            }
+            MalformedRequest::DuplicateMethod => {
+                fields.insert(1, (":method".into(), "GET".into()));
+            }
+            MalformedRequest::MissingScheme => {
+                fields.retain(|(k, _)| *k != ":scheme");
+            }
+            MalformedRequest::ConnectionSpecificHeader => {
+                fields.push(("connection".into(), "keep-alive".into()));
+            }
+            MalformedRequest::TeNotTrailers => {
+                fields.push(("te".into(), "gzip".into()));
+            }
+        }
+
+        let mut block_fragment = Vec::new();
+        for (k, v) in &fields {
+            // literal header field without indexing, new name (RFC 7541
+            // Section 6.2.2)
+            block_fragment.push(0x00);
+            encode_hpack_string(&k[..], &mut block_fragment);
+            encode_hpack_string(&v[..], &mut block_fragment);
+        }
+
+        let stream_id = self.open_stream()?;
+        self.write_headers(
+            stream_id,
+            HeadersFlags::EndHeaders | HeadersFlags::EndStream,
+            Piece::from(block_fragment),
+        )
+        .await?;
+        Ok(stream_id)
+    }
+
+    /// Note: The buffer should represent the entire block that should be
+    /// decoded. For example, in HTTP/2, all continuation frames need to be
+    /// concatenated to a single buffer before passing them to the decoder.
+    pub fn decode_headers(&mut self, fragment: Piece) -> eyre::Result<Headers> {
+        // note: this allocates a lot but, but again, we're doing tests so shrug.
+        let mut headers = Headers::default();
+        let res = self
+            .hpack_dec
+            .decode(&fragment[..])
+            .map_err(|e| eyre::eyre!("hpack decoder error: {e:?}"))?;
+        for (k, v) in res {
+            headers.append(k, v);
+        }
+
+        tracing::trace!("decoded {} headers:", headers.len());
+        for (k, v) in headers.iter() {
+            tracing::trace!(
+                "    {:?} => {:?}",
+                String::from_utf8_lossy(&k[..]),
+                String::from_utf8_lossy(&v[..])
+            );
+        }
+
+        Ok(headers)
+    }
+
+    /// Sends a request on `stream_id`: a HEADERS frame, then `body` in a
+    /// single DATA frame with END_STREAM, if it's not empty. For bodiless
+    /// requests, [RequestOptions::end_stream_on_headers] controls which frame
+    /// closes the stream.
+    ///
+    /// Refuses headers that carry response pseudo-header fields like
+    /// `:status`, or connection-specific fields (`te` is fine as long as
+    /// it's exactly "trailers"): to send a malformed request on purpose, use
+    /// [Conn::encode_and_write_headers].
+    pub async fn send_request(
+        &mut self,
+        stream_id: StreamId,
+        headers: &Headers,
+        body: &[u8],
+        options: RequestOptions,
+    ) -> eyre::Result<()> {
+        headers::check_request_pseudo_headers(headers)?;
+        headers::check_connection_specific_headers(headers)?;
+
+        if body.is_empty() && options.end_stream_on_headers {
+            return self
+                .encode_and_write_headers(
+                    stream_id,
+                    HeadersFlags::EndHeaders | HeadersFlags::EndStream,
+                    headers,
+                )
+                .await;
+        }
+
+        self.encode_and_write_headers(stream_id, HeadersFlags::EndHeaders, headers)
+            .await?;
+        self.write_data(stream_id, true, body.to_vec()).await
+    }
+
+    pub async fn send_empty_post_to_root(&mut self, stream_id: StreamId) -> eyre::Result<()> {
+        self.encode_and_write_headers(
+            stream_id,
+            HeadersFlags::EndStream | HeadersFlags::EndHeaders,
+            &self.common_headers("POST"),
+        )
+        .await
+    }
+
+    pub async fn encode_and_write_headers(
+        &mut self,
+        stream_id: StreamId,
+        flags: impl Into<BitFlags<HeadersFlags>>,
+        headers: &Headers,
+    ) -> eyre::Result<()> {
+        let flags = flags.into();
+        let block_fragment = self.encode_headers(headers)?;
+        self.write_headers(stream_id, flags, block_fragment).await?;
+        Ok(())
+    }
+
+    pub async fn write_headers(
+        &mut self,
+        stream_id: StreamId,
+        flags: impl Into<BitFlags<HeadersFlags>>,
+        block_fragment: Piece,
+    ) -> eyre::Result<()> {
+        let flags = flags.into();
+        let frame = Frame::new(FrameType::Headers(flags), stream_id);
+        self.write_frame(frame, block_fragment).await?;
+        Ok(())
+    }
+
+    pub async fn write_headers_with_priority(
+        &mut self,
+        stream_id: StreamId,
+        flags: impl Into<BitFlags<HeadersFlags>>,
+        priority_spec: PrioritySpec,
+        block_fragment: Piece,
+    ) -> eyre::Result<()> {
+        let flags = flags.into() | HeadersFlags::Priority;
+        let frame = Frame::new(FrameType::Headers(flags), stream_id);
+
+        let payload = block_fragment.into_piece(&mut self.scratch)?;
+        let frame = frame.with_len(payload.len().try_into().unwrap());
+
+        let priority_spec_piece = priority_spec.into_piece(&mut self.scratch)?;
+
+        let header = frame.into_piece(&mut self.scratch)?;
+        let len = header.len() + priority_spec_piece.len() + payload.len();
+        self.w
+            .writev_all_owned(
+                PieceList::single(header)
+                    .followed_by(priority_spec_piece)
+                    .followed_by(payload),
+            )
+            .await?;
+        self.sent_bytes += len as u64;
+        self.on_frame_sent(frame);
+
+        Ok(())
+    }
+
+    pub async fn write_continuation(
+        &mut self,
+        stream_id: StreamId,
+        flags: impl Into<BitFlags<ContinuationFlags>>,
+        block_fragment: Piece,
+    ) -> eyre::Result<()> {
+        let flags = flags.into();
+        let frame = Frame::new(FrameType::Continuation(flags), stream_id);
+        self.write_frame(frame, block_fragment).await?;
+        Ok(())
+    }
+
+    /// Sends a header block on `stream_id` as a HEADERS frame followed by as
+    /// many CONTINUATION frames as needed to stay within the peer's
+    /// SETTINGS_MAX_FRAME_SIZE. The last frame carries END_HEADERS.
+    pub async fn write_headers_in_fragments(
+        &mut self,
+        stream_id: StreamId,
+        end_stream: bool,
+        block: Piece,
+    ) -> eyre::Result<()> {
+        let max_frame_size = self.settings.max_frame_size as usize;
+        let first_len = block.len().min(max_frame_size);
+        let (mut fragment, mut rest) = block.split_at(first_len);
+
+        let mut flags: BitFlags<HeadersFlags> = Default::default();
+        if end_stream {
+            flags |= HeadersFlags::EndStream;
+        }
+        if rest.is_empty() {
+            flags |= HeadersFlags::EndHeaders;
+        }
+        self.write_headers(stream_id, flags, fragment).await?;
+
+        while !rest.is_empty() {
+            let len = rest.len().min(max_frame_size);
+            (fragment, rest) = rest.split_at(len);
+            let flags = if rest.is_empty() {
+                ContinuationFlags::EndHeaders.into()
+            } else {
+                BitFlags::empty()
+            };
+            self.write_continuation(stream_id, flags, fragment).await?;
+        }
+        Ok(())
+    }
+
+    /// Starts a request on `stream_id` with a HEADERS frame that doesn't have
+    /// END_HEADERS, then sends a PRIORITY frame on `other_stream_id` instead
+    /// of the CONTINUATION frame the peer expects (see Section 6.10). The
+    /// peer should answer with a connection error of type PROTOCOL_ERROR.
+    ///
+    /// PRIORITY frames are legal in any stream state, so the interleaving is
+    /// the only thing wrong with them.
+    pub async fn send_headers_then_interleave(
+        &mut self,
+        stream_id: StreamId,
+        other_stream_id: StreamId,
+    ) -> eyre::Result<()> {
+        assert_ne!(
+            stream_id, other_stream_id,
+            "the interleaved frame must be on a different stream"
+        );
+
+        let block_fragment = self.encode_headers(&self.common_headers("POST"))?;
+        self.write_headers(stream_id, HeadersFlags::EndStream, block_fragment)
+            .await?;
+
+        self.write_priority(
+            other_stream_id,
+            PrioritySpec {
+                stream_dependency: StreamId::CONNECTION,
+                exclusive: false,
+                weight: 16,
+            },
+        )
+        .await
+    }
+
+    pub async fn write_data(
+        &mut self,
+        stream_id: StreamId,
+        end_stream: bool,
+        data: impl Into<Piece>,
+    ) -> eyre::Result<()> {
+        let frame = Frame::new(
+            FrameType::Data(if end_stream {
+                DataFlags::EndStream.into()
+            } else {
+                Default::default()
+            }),
+            stream_id,
+        );
+        let data = data.into();
+        self.consume_send_windows(stream_id, data.len());
+        self.write_frame(frame, data).await?;
+        Ok(())
+    }
+
+    /// Writes a DATA frame with the PADDED flag: a Pad Length octet, `data`,
+    /// then `pad_len` octets of zeroes. The padding counts against flow
+    /// control, like the rest of the payload.
+    pub async fn send_data_padded(
+        &mut self,
+        stream_id: StreamId,
+        data: &[u8],
+        pad_len: u8,
+        end_stream: bool,
+    ) -> eyre::Result<()> {
+        self.send_data_padded_unchecked(stream_id, data, pad_len, pad_len as usize, end_stream)
+            .await
+    }
+
+    /// Like [Conn::send_data_padded], but the Pad Length octet says
+    /// `pad_len` while `padding` octets of zeroes actually follow `data`.
+    /// With a `pad_len` larger than what follows it, that's the malformed
+    /// frame RFC 9113, Section 6.1 requires a connection error for.
+    pub async fn send_data_padded_unchecked(
+        &mut self,
+        stream_id: StreamId,
+        data: &[u8],
+        pad_len: u8,
+        padding: usize,
+        end_stream: bool,
+    ) -> eyre::Result<()> {
+        let mut flags: BitFlags<DataFlags> = DataFlags::Padded.into();
+        if end_stream {
+            flags |= DataFlags::EndStream;
+        }
+
+        let mut payload = Vec::with_capacity(1 + data.len() + padding);
+        payload.push(pad_len);
+        payload.extend_from_slice(data);
+        payload.resize(payload.len() + padding, 0);
+
+        self.consume_send_windows(stream_id, payload.len());
+        self.write_frame(FrameType::Data(flags).into_frame(stream_id), payload)
+            .await
+    }
+
+    /// Collects the body of the response on `stream_id`, until a frame with
+    /// the END_STREAM flag (DATA, or trailers) or until `max_len` bytes were
+    /// read. Padding is stripped. Errors out if the body is larger than
+    /// `max_len`, or if the stream or connection gets closed before then.
+    ///
+    /// Header blocks, on any stream, are decoded along the way to keep the
+    /// HPACK decoder in sync with the peer's encoder.
+    ///
+    /// With [Config::auto_window_update], consumed DATA is given back to the
+    /// peer with WINDOW_UPDATE frames, so bodies larger than the initial
+    /// window can be read.
+    ///
+    /// Frames for other streams are skipped.
+    pub async fn read_body(
+        &mut self,
+        stream_id: StreamId,
+        max_len: usize,
+    ) -> eyre::Result<Vec<u8>> {
+        let mut body = Vec::new();
+
+        loop {
+            let (frame, payload) = match self
+                .wait_for_frame(
+                    FrameT::Data
+                        | FrameT::Headers
+                        | FrameT::Continuation
+                        | FrameT::RstStream
+                        | FrameT::GoAway,
+                )
+                .await
+            {
+                FrameWaitOutcome::Success(frame, payload) => (frame, payload),
+                FrameWaitOutcome::Timeout { last_frame, .. } => {
+                    return Err(eyre!(
+                        "Timed out reading the body of stream {stream_id}, last frame: ({last_frame:?})"
+                    ))
+                }
+                FrameWaitOutcome::Eof { .. } => {
+                    return Err(eyre!(
+                        "Peer hung up while we were reading the body of stream {stream_id}"
+                    ))
+                }
+                FrameWaitOutcome::IoError { error, .. } => {
+                    return Err(eyre!(
+                        "I/O error while reading the body of stream {stream_id}: {error}"
+                    ))
+                }
+            };
+
+            if let FrameType::GoAway = frame.frame_type {
+                return Err(eyre!(
+                    "Peer sent GOAWAY while we were reading the body of stream {stream_id}"
+                ));
+            }
+            if let FrameType::Headers(_) | FrameType::Continuation(_) = frame.frame_type {
+                // either the response headers (keep going), or trailers. A
+                // CONTINUATION can't come first, that errors out.
+                let block_stream_id = frame.stream_id;
+                let (_, end_stream) = self
+                    .read_header_block_from(block_stream_id, Some((frame, payload)))
+                    .await?;
+                if block_stream_id == stream_id && end_stream {
+                    return Ok(body);
+                }
+                continue;
+            }
+            if frame.stream_id != stream_id {
+                continue;
+            }
+
+            match frame.frame_type {
+                FrameType::Data(flags) => {
+                    let data = if flags.contains(DataFlags::Padded) {
+                        let pad_len = *payload
+                            .first()
+                            .ok_or_else(|| eyre!("padded DATA frame without a pad length"))?
+                            as usize;
+                        if pad_len >= payload.len() {
+                            return Err(eyre!(
+                                "DATA frame has {pad_len} bytes of padding but is only {} bytes long",
+                                payload.len()
+                            ));
+                        }
+                        &payload[1..payload.len() - pad_len]
+                    } else {
+                        &payload[..]
+                    };
+
+                    if body.len() + data.len() > max_len {
+                        return Err(eyre!(
+                            "Body of stream {stream_id} is larger than {max_len} bytes"
+                        ));
+                    }
+                    body.extend_from_slice(data);
+
+                    // the whole frame counts against flow control, padding included
+                    if self.config.auto_window_update && frame.len > 0 {
+                        self.write_window_update(StreamId::CONNECTION, frame.len)
+                            .await?;
+                        if !flags.contains(DataFlags::EndStream) {
+                            self.write_window_update(stream_id, frame.len).await?;
+                        }
+                    }
+
+                    if flags.contains(DataFlags::EndStream) || body.len() == max_len {
+                        return Ok(body);
+                    }
+                }
+                FrameType::RstStream => {
+                    return Err(eyre!(
+                        "Stream {stream_id} was reset while we were reading its body"
+                    ));
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    /// Reads frames until the response on `stream_id`, and any response the
+    /// server pushed alongside it, is complete, and fails if more than `max`
+    /// pushed streams were ever open at the same time (see Section 8.4).
+    ///
+    /// Promised streams only count once the server sends HEADERS on them:
+    /// streams in the "reserved" state don't count towards the limit, so
+    /// servers may promise more than `max` and start them later.
+    ///
+    /// Returns how many streams the server pushed, which is zero for servers
+    /// that don't push at all.
+    pub async fn verify_max_concurrent_pushes(
+        &mut self,
+        stream_id: StreamId,
+        max: u32,
+    ) -> eyre::Result<usize> {
+        let mut open_pushes: HashSet<StreamId> = HashSet::new();
+        let mut pushes = 0;
+        let mut response_done = false;
+
+        while !response_done || !open_pushes.is_empty() {
+            let frame = match self
+                .wait_for_frame(
+                    FrameT::Data | FrameT::Headers | FrameT::RstStream | FrameT::GoAway,
+                )
+                .await
+            {
+                FrameWaitOutcome::Success(frame, _payload) => frame,
+                FrameWaitOutcome::Timeout { last_frame, .. } => {
+                    return Err(eyre!(
+                        "Timed out waiting for the response on stream {stream_id} and its pushes, last frame: ({last_frame:?})"
+                    ))
+                }
+                FrameWaitOutcome::Eof { .. } => {
+                    return Err(eyre!(
+                        "Peer hung up before the response on stream {stream_id} and its pushes were complete"
+                    ))
+                }
+                FrameWaitOutcome::IoError { error, .. } => {
+                    return Err(eyre!(
+                        "I/O error while waiting for the response on stream {stream_id}: {error}"
+                    ))
+                }
+            };
+
+            let ended = match frame.frame_type {
+                FrameType::GoAway => {
+                    return Err(eyre!(
+                        "Peer sent GOAWAY while we were waiting for the response on stream {stream_id}"
+                    ));
+                }
+                FrameType::Data(flags) => flags.contains(DataFlags::EndStream),
+                FrameType::Headers(flags) => {
+                    // server-initiated streams have even ids
+                    if frame.stream_id.0 % 2 == 0 && open_pushes.insert(frame.stream_id) {
+                        pushes += 1;
+                        if open_pushes.len() > max as usize {
+                            return Err(eyre!(
+                                "Server has {} pushed streams open at once, but we only allowed {max}",
+                                open_pushes.len()
+                            ));
+                        }
+                    }
+                    flags.contains(HeadersFlags::EndStream)
+                }
+                FrameType::RstStream => true,
+                _ => unreachable!(),
+            };
+
+            if ended {
+                if frame.stream_id == stream_id {
+                    response_done = true;
+                } else {
+                    open_pushes.remove(&frame.stream_id);
+                }
+            }
+        }
+
+        Ok(pushes)
+    }
+
+    /// Reads the response on `stream_id` and checks that its frames come in
+    /// a legal order (see Section 8.1): a header section (HEADERS, then
+    /// CONTINUATION frames until END_HEADERS), zero or more DATA frames, then
+    /// optionally a trailer section, which must carry END_STREAM. Interim
+    /// (1xx) responses may come before the final header section.
+    ///
+    /// Header blocks are decoded, so the HPACK state stays in sync.
+    ///
+    /// DATA before the final header section makes the response malformed
+    /// (Section 8.1.1): like a client would, we reset the stream with
+    /// PROTOCOL_ERROR before erroring out.
+    pub async fn expect_response_framing(&mut self, stream_id: StreamId) -> eyre::Result<()> {
+        // whether we've seen the final (non-1xx) response headers
+        let mut in_body = false;
+        // the header block being received, and whether it ends the stream
+        let mut block: Option<(Vec<u8>, bool)> = None;
+
+        loop {
+            let (frame, payload) = match self
+                .wait_for_frame(
+                    FrameT::Headers
+                        | FrameT::Continuation
+                        | FrameT::Data
+                        | FrameT::RstStream
+                        | FrameT::GoAway,
+                )
+                .await
+            {
+                FrameWaitOutcome::Success(frame, payload) => (frame, payload),
+                FrameWaitOutcome::Timeout { last_frame, .. } => {
+                    return Err(eyre!(
+                        "Timed out reading the response on stream {stream_id}, last frame: ({last_frame:?})"
+                    ))
+                }
+                FrameWaitOutcome::Eof { .. } => {
+                    return Err(eyre!(
+                        "Peer hung up before the response on stream {stream_id} was complete"
+                    ))
+                }
+                FrameWaitOutcome::IoError { error, .. } => {
+                    return Err(eyre!(
+                        "I/O error while reading the response on stream {stream_id}: {error}"
+                    ))
+                }
+            };
+
+            if let FrameType::GoAway = frame.frame_type {
+                return Err(eyre!(
+                    "Peer sent GOAWAY while we were reading the response on stream {stream_id}"
+                ));
+            }
+            if block.is_some()
+                && (frame.stream_id != stream_id
+                    || !matches!(frame.frame_type, FrameType::Continuation(_)))
+            {
+                // cf. Section 6.10
+                return Err(eyre!(
+                    "Got {frame:?} in the middle of the header block for stream {stream_id}"
+                ));
+            }
+            if frame.stream_id != stream_id {
+                continue;
+            }
+
+            let mut completed = None;
+            match frame.frame_type {
+                FrameType::Headers(flags) => {
+                    let fragment = headers_frame_fragment(flags, &payload[..])?;
+                    let end_stream = flags.contains(HeadersFlags::EndStream);
+                    if flags.contains(HeadersFlags::EndHeaders) {
+                        completed = Some((fragment.to_vec(), end_stream));
+                    } else {
+                        block = Some((fragment.to_vec(), end_stream));
+                    }
+                }
+                FrameType::Continuation(flags) => {
+                    let Some((buf, _)) = block.as_mut() else {
+                        return Err(eyre!(
+                            "Got a CONTINUATION frame on stream {stream_id} without a HEADERS frame before it"
+                        ));
+                    };
+                    buf.extend_from_slice(&payload[..]);
+                    if flags.contains(ContinuationFlags::EndHeaders) {
+                        completed = block.take();
+                    }
+                }
+                FrameType::Data(flags) => {
+                    if !in_body {
+                        self.write_rst_stream(stream_id, KnownErrorCode::ProtocolError)
+                            .await?;
+                        return Err(eyre!(
+                            "Got a DATA frame on stream {stream_id} before the response HEADERS"
+                        ));
+                    }
+                    if flags.contains(DataFlags::EndStream) {
+                        return Ok(());
+                    }
+                }
+                FrameType::RstStream => {
+                    return Err(eyre!(
+                        "Stream {stream_id} was reset before its response was complete"
+                    ));
+                }
+                _ => unreachable!(),
+            }
+
+            let Some((buf, end_stream)) = completed else {
+                continue;
+            };
+            let headers = self.decode_headers(buf.into())?;
+
+            if in_body {
+                if !end_stream {
+                    return Err(eyre!(
+                        "Trailers on stream {stream_id} don't have the END_STREAM flag"
+                    ));
+                }
+                return Ok(());
+            }
+
+            headers::check_response_pseudo_headers(&headers)
+                .map_err(|e| eyre!("Response on stream {stream_id}: {e}"))?;
+            let status = headers
+                .get_first(&":status".into())
+                .ok_or_else(|| eyre!("Response on stream {stream_id} has no :status"))?;
+            let informational = status.first() == Some(&b'1');
+            if informational {
+                if end_stream {
+                    return Err(eyre!(
+                        "Interim response on stream {stream_id} has the END_STREAM flag"
+                    ));
+                }
+            } else if end_stream {
+                return Ok(());
+            } else {
+                in_body = true;
+            }
+        }
+    }
+
+    /// Reads the interim (1xx) responses on `stream_id` (see Section 8.1),
+    /// up to and including the final response's header section, and returns
+    /// the header fields of each interim response, in order.
+    ///
+    /// The final header section is decoded, to keep the HPACK state in sync,
+    /// but not returned: callers can go on with [Conn::read_body].
+    pub async fn read_interim_responses(
+        &mut self,
+        stream_id: StreamId,
+    ) -> eyre::Result<Vec<Vec<(Vec<u8>, Vec<u8>)>>> {
+        let mut interims = Vec::new();
+
+        loop {
+            let (headers, end_stream) = self.read_header_block(stream_id).await?;
+            headers::check_response_pseudo_headers(&headers)
+                .map_err(|e| eyre!("Response on stream {stream_id}: {e}"))?;
+            let status = headers
+                .get_first(&":status".into())
+                .ok_or_else(|| eyre!("Response on stream {stream_id} has no :status"))?;
+            if status.first() != Some(&b'1') {
+                return Ok(interims);
+            }
+            if end_stream {
+                return Err(eyre!(
+                    "Interim response on stream {stream_id} has the END_STREAM flag"
+                ));
+            }
+
+            interims.push(
+                headers
+                    .into_iter()
+                    .map(|(k, v)| (k.to_vec(), v.to_vec()))
+                    .collect(),
+            );
+        }
+    }
+
+    /// Reads the next header block on `stream_id` (a HEADERS frame, then
+    /// CONTINUATION frames until END_HEADERS) and decodes it. Returns the
+    /// headers, and whether the HEADERS frame ended the stream.
+    async fn read_header_block(&mut self, stream_id: StreamId) -> eyre::Result<(Headers, bool)> {
+        self.read_header_block_from(stream_id, None).await
+    }
+
+    /// Like [Conn::read_header_block], for when its first frame was already
+    /// read by the caller.
+    async fn read_header_block_from(
+        &mut self,
+        stream_id: StreamId,
+        mut first: Option<(Frame, Roll)>,
+    ) -> eyre::Result<(Headers, bool)> {
+        let mut block: Option<(Vec<u8>, bool)> = None;
+
+        loop {
+            let outcome = match first.take() {
+                Some((frame, payload)) => FrameWaitOutcome::Success(frame, payload),
+                None => {
+                    self.wait_for_frame(
+                        FrameT::Headers | FrameT::Continuation | FrameT::RstStream | FrameT::GoAway,
+                    )
+                    .await
+                }
+            };
+            let (frame, payload) = match outcome {
+                FrameWaitOutcome::Success(frame, payload) => (frame, payload),
+                FrameWaitOutcome::Timeout { last_frame, .. } => {
+                    return Err(eyre!(
+                        "Timed out waiting for headers on stream {stream_id}, last frame: ({last_frame:?})"
+                    ))
+                }
+                FrameWaitOutcome::Eof { .. } => {
+                    return Err(eyre!(
+                        "Peer hung up while we were waiting for headers on stream {stream_id}"
+                    ))
+                }
+                FrameWaitOutcome::IoError { error, .. } => {
+                    return Err(eyre!(
+                        "I/O error while waiting for headers on stream {stream_id}: {error}"
+                    ))
+                }
+            };
+
+            if let FrameType::GoAway = frame.frame_type {
+                return Err(eyre!(
+                    "Peer sent GOAWAY while we were waiting for headers on stream {stream_id}"
+                ));
+            }
+            if block.is_some()
+                && (frame.stream_id != stream_id
+                    || !matches!(frame.frame_type, FrameType::Continuation(_)))
+            {
+                // cf. Section 6.10
+                return Err(eyre!(
+                    "Got {frame:?} in the middle of the header block for stream {stream_id}"
+                ));
+            }
+            if frame.stream_id != stream_id {
+                continue;
+            }
+
+            let completed = match frame.frame_type {
+                FrameType::Headers(flags) => {
+                    let fragment = headers_frame_fragment(flags, &payload[..])?.to_vec();
+                    let end_stream = flags.contains(HeadersFlags::EndStream);
+                    if flags.contains(HeadersFlags::EndHeaders) {
+                        Some((fragment, end_stream))
+                    } else {
+                        block = Some((fragment, end_stream));
+                        None
+                    }
+                }
+                FrameType::Continuation(flags) => {
+                    let Some((buf, _)) = block.as_mut() else {
+                        return Err(eyre!(
+                            "Got a CONTINUATION frame on stream {stream_id} without a HEADERS frame before it"
+                        ));
+                    };
+                    buf.extend_from_slice(&payload[..]);
+                    if flags.contains(ContinuationFlags::EndHeaders) {
+                        block.take()
+                    } else {
+                        None
+                    }
+                }
+                FrameType::RstStream => {
+                    return Err(eyre!(
+                        "Stream {stream_id} was reset while we were waiting for its headers"
+                    ));
+                }
+                _ => unreachable!(),
+            };
+
+            if let Some((buf, end_stream)) = completed {
+                let headers = self.decode_headers(buf.into())?;
+                return Ok((headers, end_stream));
+            }
+        }
+    }
+
+    /// Generates a set of dummy headers.
+    ///
+    /// # Parameters
+    /// - `len`: The number of headers to generate.
+    ///
+    /// # Returns
+    /// - A `Headers` map containing `len` number of headers, where each header
+    ///   has a key in the form of `x-dummy{i}` and a value of `dummy_bytes`
+    ///   with a length equal to `self.config.max_header_len`.
+    ///
+    /// # Properties
+    /// - The size of each header value is equal to
+    ///   `self.config.max_header_len`.
+    /// - The total number of headers in the returned `Headers` map is equal to
+    ///   `len`.
+    fn dummy_headers(&self, len: usize) -> Headers {
+        let mut headers = Headers::default();
+        let dummy = dummy_bytes(self.config.max_header_len);
+
+        for i in 0..len {
+            let name = format!("x-dummy{}", i);
+            headers.append(name.into_bytes(), dummy.clone());
+        }
+
+        headers
+    }
+
+    pub async fn write_rst_stream(
+        &mut self,
+        stream_id: StreamId,
+        error_code: impl Into<ErrorCode>,
+    ) -> eyre::Result<()> {
+        let error_code = error_code.into();
+        let rst_stream = RstStream { error_code };
+        self.write_frame(FrameType::RstStream.into_frame(stream_id), rst_stream)
+            .await
+    }
+
+    async fn write_window_update(
+        &mut self,
+        stream_id: StreamId,
+        increment: u32,
+    ) -> eyre::Result<()> {
+        let update = WindowUpdate {
+            reserved: 0,
+            increment,
+        };
+        let mut rm = RollMut::alloc().unwrap();
+        let piece = update.into_piece(&mut rm).unwrap();
+        tracing::debug!(?update, "writing window_update, bytes = {:x?}", &piece[..]);
+
+        self.write_frame(FrameType::WindowUpdate.into_frame(stream_id), update)
+            .await
+    }
+
+    // verify_settings_frame_with_ack verifies whether a SETTINGS frame with
+    // ACK flag was received.
+    pub async fn verify_settings_frame_with_ack(&mut self) -> eyre::Result<()> {
+        let (frame, _payload) = self.wait_for_frame(FrameT::Settings).await.unwrap();
+        assert!(frame.is_ack());
+        Ok(())
+    }
+
+    async fn send_req_and_expect_status(
+        &mut self,
+        stream_id: StreamId,
+        headers: &Headers,
+        expected_status: u16,
+    ) -> eyre::Result<()> {
+        self.encode_and_write_headers(
+            stream_id,
+            HeadersFlags::EndHeaders | HeadersFlags::EndStream,
+            headers,
+        )
+        .await?;
+
+        let (frame, payload) = self.wait_for_frame(FrameT::Headers).await.unwrap();
+        assert!(
+            frame.is_end_headers(),
+            "the server is free to answer with headers in several frames but this breaks that test"
+        );
+
+        let headers = self.decode_headers(payload.into())?;
+        let status = headers
+            .get_first(&":status".into())
+            .expect("response should contain :status");
+        let status = std::str::from_utf8(&status[..])
+            .expect("status should be valid utf-8")
+            .parse::<u16>()
+            .expect("status should be a u16");
+        assert_eq!(status, expected_status);
+
+        Ok(())
+    }
+
+    async fn send_req_and_expect_stream_rst(
+        &mut self,
+        stream_id: StreamId,
+        headers: &Headers,
+    ) -> eyre::Result<()> {
+        self.encode_and_write_headers(
+            stream_id,
+            HeadersFlags::EndHeaders | HeadersFlags::EndStream,
+            headers,
+        )
+        .await?;
+
+        tracing::debug!("verifying stream error...");
+        self.verify_stream_error(ErrorC::ProtocolError).await?;
+        tracing::debug!("verifying stream error... done!");
+
+        // self.w.shutdown().await?;
+
+        Ok(())
+    }
+}
+
+/// Parameters for tests
+pub struct Config {
+    /// which host to connect to
+    pub host: String,
+
+    /// which port to connect to
+    pub port: u16,
+
+    /// which path to request
+    pub path: String,
+
+    /// whether to use TLS
+    pub tls: bool,
+
+    /// how long to wait for a frame
+    pub timeout: Duration,
+
+    /// maximum length of a header
+    pub max_header_len: usize,
+
+    /// the first stream id `Conn::open_stream` hands out, must be odd
+    pub first_stream_id: StreamId,
+
+    /// whether to automatically acknowledge PING frames sent by the peer
+    /// (e.g. keepalives) while waiting for other frames, so long-running
+    /// connections stay healthy in tests that don't care about PINGs. Off by
+    /// default, so tests for RFC 9113 Section 6.7 see every PING.
+    ///
+    /// PINGs are answered when `Conn::wait_for_frame` (or anything built on
+    /// it, like `Conn::wait_until_idle`) comes across them: the receive loop
+    /// doesn't own the write half.
+    pub auto_ack_ping: bool,
+
+    /// whether to keep a copy of every frame received from the peer, see
+    /// [Conn::last_frames_bytes] and [Conn::frame_counts]. Off by default since it grows for the
+    /// whole lifetime of the connection.
+    pub record_frames: bool,
+
+    /// whether `Conn::read_body` sends WINDOW_UPDATE frames for the DATA it
+    /// consumes
+    pub auto_window_update: bool,
+
+    /// whether `Conn::wait_for_frame` keeps the frames it skips over, for
+    /// later calls to return, instead of discarding them
+    pub buffer_unmatched_frames: bool,
+
+    /// whether `Conn::handshake` sends the connection preface and our
+    /// SETTINGS frame in a single write, rather than one after the other
+    pub coalesce_handshake: bool,
+
+    /// how many bytes to reserve up front in the scratch buffer frame headers
+    /// and payloads get serialized into, to avoid reallocations when writing
+    /// large frames (e.g. GOAWAY with lots of debug data). `None` uses a
+    /// buffer from the pool. Either way, the scratch buffer grows as needed:
+    /// this is only tuning.
+    pub scratch_size: Option<usize>,
+
+    /// whether frames from the peer that carry flags undefined for their
+    /// type are treated as an I/O error, rather than having those flags
+    /// ignored. That's not RFC-compliant (cf. RFC 9113, Section 4.1): it's
+    /// for checking that a server doesn't send them, in tests only.
+    pub strict_flags: bool,
+
+    /// how many bytes a server may send back for every byte of a small
+    /// request, in tests that check it doesn't amplify traffic (cf. RFC 9113,
+    /// Section 10.5). The default leaves room for a modest response body:
+    /// raise it for servers that answer with larger pages.
+    pub max_amplification_factor: u32,
+
+    /// how long the receive loop waits before each read from the transport,
+    /// to simulate a slow client: the peer's writes back up, and, if we
+    /// also hold back WINDOW_UPDATE frames, tests can check it sticks to
+    /// flow control rather than buffering without bounds. `None` reads as
+    /// fast as possible. The pause doesn't count against [Config::timeout].
+    pub read_pacing: Option<Duration>,
+
+    /// the seed for the random GREASE values some tests send on top of the
+    /// reserved ones they always send. `None` picks one from the clock: a
+    /// failing test's error includes the seed it used, set it here to
+    /// reproduce the failure.
+    pub grease_seed: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            host: "localhost".into(),
+            port: 80,
+            path: "/".into(),
+            tls: false,
+
+            max_header_len: 4000,
+
+            first_stream_id: StreamId(1),
+            auto_ack_ping: false,
+            record_frames: false,
+            auto_window_update: false,
+            buffer_unmatched_frames: false,
+            coalesce_handshake: false,
+            scratch_size: None,
+            strict_flags: false,
+            max_amplification_factor: 64,
+            read_pacing: None,
+            grease_seed: None,
+
+            timeout: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Options for [Conn::send_request]
+#[derive(Clone, Copy, Debug)]
+pub struct RequestOptions {
+    /// for requests without a body: whether the HEADERS frame carries
+    /// END_STREAM, or whether it's sent on a separate, empty DATA frame
+    pub end_stream_on_headers: bool,
+}
+
+impl Default for RequestOptions {
+    fn default() -> Self {
+        Self {
+            end_stream_on_headers: true,
+        }
+    }
+}
+
+/// Checks that a server speaks HTTP/2 at all, before running the whole suite
+/// against it: connects with `make_conn`, goes through the handshake, sends
+/// a GET for [Config::path], and checks that a response with a valid
+/// `:status` comes back. The error says which of those steps failed.
+pub async fn smoke_test<IO, F, Fut>(config: Rc<Config>, make_conn: F) -> eyre::Result<()>
+where
+    IO: IntoHalves,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = std::io::Result<IO>>,
+{
+    let io = make_conn()
+        .await
+        .map_err(|e| eyre!("Could not connect: {e}"))?;
+    let mut conn = Conn::new(config, io);
+    conn.handshake()
+        .await
+        .map_err(|e| eyre!("HTTP/2 handshake failed: {e}"))?;
+
+    let stream_id = conn.open_stream()?;
+    let headers = conn.common_headers("GET");
+    conn.send_request(stream_id, &headers, &[], Default::default())
+        .await
+        .map_err(|e| eyre!("Could not send a GET request: {e}"))?;
+
+    let (headers, _end_stream) = conn
+        .read_header_block(stream_id)
+        .await
+        .map_err(|e| eyre!("No response to a GET request: {e}"))?;
+    let status = headers
+        .get_first(&":status".into())
+        .ok_or_else(|| eyre!("Response to a GET request has no :status"))?;
+    let valid = status.len() == 3
+        && status.iter().all(|b| b.is_ascii_digit())
+        && (b'1'..=b'5').contains(&status[0]);
+    if !valid {
+        return Err(eyre!(
+            "Response to a GET request has an invalid :status {:?}",
+            String::from_utf8_lossy(&status[..])
+        ));
+    }
+
+    Ok(())
+}
+
+// DummyString returns a dummy string with specified length.
+pub fn dummy_string(len: usize) -> String {
+    "x".repeat(len)
+}
+
+// DummyBytes returns an array of bytes with specified length.
+pub fn dummy_bytes(len: usize) -> Vec<u8> {
+    vec![b'x'; len]
+}
+
+/// Ways [Conn::send_malformed_request] can break a request, cf. Section 8
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MalformedRequest {
+    /// a field name with uppercase characters (Section 8.2.1)
+    UppercaseFieldName,
+    /// a pseudo-header field after a regular field (Section 8.3)
+    PseudoHeaderAfterRegular,
+    /// `:method` twice (Section 8.3)
+    DuplicateMethod,
+    /// no `:scheme` (Section 8.3.1)
+    MissingScheme,
+    /// a `connection` header field (Section 8.2.2)
+    ConnectionSpecificHeader,
+    /// a `te` header field with a value other than "trailers" (Section
+    /// 8.2.2)
+    TeNotTrailers,
+}
+
+/// Appends an HPACK string literal, without Huffman coding (RFC 7541
+/// Section 5.2): a 7-bit prefix integer length, then the bytes.
+fn encode_hpack_string(s: &[u8], out: &mut Vec<u8>) {
+    // cf. RFC 7541 Section 5.1
+    let mut len = s.len();
+    if len < 0x7f {
+        out.push(len as u8);
+    } else {
+        out.push(0x7f);
+        len -= 0x7f;
+        while len >= 0x80 {
+            out.push((len % 0x80) as u8 | 0x80);
+            len /= 0x80;
+        }
+        out.push(len as u8);
+    }
+    out.extend_from_slice(s);
+}
+
+/// Formats the wire bytes of a frame for humans: the 9-byte header broken
+/// down into its fields, then a hex dump of the payload. Handy to see
+/// exactly what was on the wire when a test fails, e.g. with
+/// [Conn::last_frames_bytes].
+pub fn hexdump_frame(bytes: &[u8]) -> String {
+    if bytes.len() < 9 {
+        return format!(
+            "truncated frame header ({} bytes): {}",
+            bytes.len(),
+            pretty_hex::simple_hex(&bytes)
+        );
+    }
+
+    let (header, payload) = bytes.split_at(9);
+    let len = u32::from_be_bytes([0, header[0], header[1], header[2]]);
+    let (ty, flags) = (header[3], header[4]);
+    let reserved = header[5] >> 7;
+    let stream_id = u32::from_be_bytes([header[5] & 0x7f, header[6], header[7], header[8]]);
+    let name = FrameType::decode(EncodedFrameType { ty, flags }).name();
+
+    let mut s = String::new();
+    s.push_str(&format!("length:    {len}"));
+    if len as usize != payload.len() {
+        s.push_str(&format!(" (but {} payload bytes follow)", payload.len()));
+    }
+    s.push_str(&format!("\ntype:      {ty:#04x} ({name})"));
+    s.push_str(&format!("\nflags:     {flags:#04x} ({flags:#010b})"));
+    s.push_str(&format!("\nstream id: {stream_id}"));
+    if reserved != 0 {
+        s.push_str(" (reserved bit set)");
+    }
+    if !payload.is_empty() {
+        s.push_str("\npayload:\n");
+        s.push_str(&pretty_hex::pretty_hex(&payload));
+    }
+    s
+}
+
+/// Returns the header block fragment of a HEADERS frame payload, without its
+/// padding and priority fields.
+fn headers_frame_fragment(flags: BitFlags<HeadersFlags>, payload: &[u8]) -> eyre::Result<&[u8]> {
+    let mut fragment = payload;
+    let mut pad_len = 0;
+    if flags.contains(HeadersFlags::Padded) {
+        let (&len, rest) = fragment
+            .split_first()
+            .ok_or_else(|| eyre!("padded HEADERS frame without a pad length"))?;
+        pad_len = len as usize;
+        fragment = rest;
+    }
+    if flags.contains(HeadersFlags::Priority) {
+        fragment = fragment
+            .get(5..)
+            .ok_or_else(|| eyre!("HEADERS frame too short for its priority fields"))?;
+    }
+    if pad_len > fragment.len() {
+        return Err(eyre!(
+            "HEADERS frame has {pad_len} bytes of padding but only {} bytes left",
+            fragment.len()
+        ));
+    }
+    Ok(&fragment[..fragment.len() - pad_len])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc, time::Duration};
+
+    use fluke_buffet::{Piece, RollMut};
+    use fluke_h2_parse::{
+        ContinuationFlags, DataFlags, FrameType, GoAway, HeadersFlags, IntoPiece, KnownErrorCode,
+        PingFlags, Setting, SettingPairs, Settings, SettingsFlags, StreamId, WindowUpdate, PREFACE,
+    };
+    use tokio::time::Instant;
+
+    #[allow(deprecated)]
+    use crate::ClientStreamIds;
+    use crate::{
+        dummy_bytes, encode_hpack_string, hexdump_frame,
+        rfc9113::{default_settings, DEFAULT_FRAME_SIZE},
+        smoke_test, Config, Conn, DuplexIo, DuplexPeer, ErrorC, ErrorOutcome, Ev, FrameT,
+        FrameWaitOutcome, Headers, MalformedRequest, StreamIdAllocator,
+    };
+
+    /// Spawns a peer that collects everything the `Conn` writes, until it
+    /// hangs up
+    fn collect_peer_writes(mut peer: DuplexPeer) -> tokio::task::JoinHandle<Vec<u8>> {
+        fluke_buffet::spawn(async move { recv_all(&mut peer).await })
+    }
+
+    /// Reads everything the `Conn` writes, until it hangs up
+    async fn recv_all(peer: &mut DuplexPeer) -> Vec<u8> {
+        let mut received = Vec::new();
+        while let Some(chunk) = peer.recv().await.unwrap() {
+            received.extend(chunk);
+        }
+        received
+    }
+
+    /// Reads the client preface and SETTINGS frame: letting the client go
+    /// first means neither side blocks on a write. Returns what was read.
+    async fn await_client_handshake(peer: &mut DuplexPeer) -> Vec<u8> {
+        let mut received = Vec::new();
+        while received.len() < PREFACE.len() + 9 + default_settings().0.len() * 6 {
+            received.extend(peer.recv().await.unwrap().unwrap());
+        }
+        received
+    }
+
+    /// Splits raw frames into (type, flags, stream id, payload)
+    fn split_frames(mut rest: &[u8]) -> Vec<(u8, u8, u32, &[u8])> {
+        let mut frames = Vec::new();
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes([0, rest[0], rest[1], rest[2]]) as usize;
+            let stream_id = u32::from_be_bytes([rest[5] & 0x7f, rest[6], rest[7], rest[8]]);
+            frames.push((rest[3], rest[4], stream_id, &rest[9..9 + len]));
+            rest = &rest[9 + len..];
+        }
+        frames
+    }
+
+    #[test]
+    fn stream_id_allocator() {
+        let mut client = StreamIdAllocator::client();
+        let ids: Vec<_> = (0..3).map(|_| client.allocate().unwrap()).collect();
+        assert_eq!(ids, [StreamId(1), StreamId(3), StreamId(5)]);
+
+        let mut server = StreamIdAllocator::server();
+        let ids: Vec<_> = (0..3).map(|_| server.allocate().unwrap()).collect();
+        assert_eq!(ids, [StreamId(2), StreamId(4), StreamId(6)]);
+
+        let mut client = StreamIdAllocator::client_starting_at(StreamId(StreamId::MAX.0 - 2));
+        assert_eq!(client.allocate().unwrap(), StreamId(StreamId::MAX.0 - 2));
+        assert_eq!(client.allocate().unwrap(), StreamId::MAX);
+        let err = client.allocate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("ran out of client stream ids (max is {})", StreamId::MAX)
+        );
+        // it stays exhausted
+        assert!(client.allocate().is_err());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn client_stream_ids_forwards_to_allocator() {
+        let ids: Vec<_> = ClientStreamIds::new().take(3).collect();
+        assert_eq!(ids, [StreamId(1), StreamId(3), StreamId(5)]);
+
+        // ends where the allocator runs out
+        let ids: Vec<_> = ClientStreamIds::starting_at(StreamId(StreamId::MAX.0 - 2)).collect();
+        assert_eq!(ids, [StreamId(StreamId::MAX.0 - 2), StreamId::MAX]);
+    }
+
+    #[test]
+    fn hexdump_ping_frame() {
+        let bytes = b"\x00\x00\x08\x06\x01\x00\x00\x00\x00pingpong";
+        let dump = hexdump_frame(bytes);
+        let mut lines = dump.lines();
+        assert_eq!(lines.next(), Some("length:    8"));
+        assert_eq!(lines.next(), Some("type:      0x06 (PING)"));
+        assert_eq!(lines.next(), Some("flags:     0x01 (0b00000001)"));
+        assert_eq!(lines.next(), Some("stream id: 0"));
+        assert_eq!(lines.next(), Some("payload:"));
+        assert!(dump.contains("70 69 6e 67  70 6f 6e 67"), "{dump}");
+
+        let dump = hexdump_frame(b"\x00\x00\x00\x04\x00\x80\x00\x00\x01");
+        assert!(dump.contains("type:      0x04 (SETTINGS)"), "{dump}");
+        assert!(dump.contains("stream id: 1 (reserved bit set)"), "{dump}");
+        assert!(!dump.contains("payload"), "{dump}");
+
+        assert!(hexdump_frame(b"\x00\x00").starts_with("truncated frame header (2 bytes)"));
+    }
+
+    #[test]
+    fn malformed_request_header_block() {
+        fluke_buffet::start(async move {
+            let (io, peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+            let peer_task = collect_peer_writes(peer);
+
+            let stream_id = conn
+                .send_malformed_request(MalformedRequest::PseudoHeaderAfterRegular)
+                .await
+                .unwrap();
+            assert_eq!(stream_id, StreamId(1));
+            drop(conn);
+
+            let received = peer_task.await.unwrap();
+            let (header, payload) = received.split_at(9);
+            assert_eq!(header[3], 0x01, "should be a HEADERS frame");
+
+            let mut dec = fluke_hpack::Decoder::new();
+            let fields = dec.decode(payload).unwrap();
+            let names: Vec<&[u8]> = fields.iter().map(|(k, _)| &k[..]).collect();
+            assert_eq!(
+                names,
+                [
+                    &b":method"[..],
+                    b":scheme",
+                    b":authority",
+                    b"x-regular",
+                    b":path"
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn send_response_frames() {
+        fluke_buffet::start(async move {
+            let (io, peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+            let peer_task = collect_peer_writes(peer);
+
+            let mut headers = Headers::default();
+            headers.append("content-type", "text/plain");
+            let body = dummy_bytes(DEFAULT_FRAME_SIZE as usize + 1);
+            conn.send_response(StreamId(1), 200, &headers, &body)
+                .await
+                .unwrap();
+            drop(conn);
+
+            let received = peer_task.await.unwrap();
+            let frames = split_frames(&received);
+
+            let types_and_flags: Vec<_> = frames.iter().map(|&(t, f, _, _)| (t, f)).collect();
+            // HEADERS with END_HEADERS, then DATA, then DATA with END_STREAM
+            assert_eq!(types_and_flags, [(0x1, 0x4), (0x0, 0x0), (0x0, 0x1)]);
+            assert_eq!(frames[1].3.len(), DEFAULT_FRAME_SIZE as usize);
+            assert_eq!(frames[2].3.len(), 1);
+
+            let mut dec = fluke_hpack::Decoder::new();
+            let fields = dec.decode(frames[0].3).unwrap();
+            assert_eq!(
+                fields,
+                [
+                    (b":status".to_vec(), b"200".to_vec()),
+                    (b"content-type".to_vec(), b"text/plain".to_vec())
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn open_next_stream() {
+        fluke_buffet::start(async move {
+            let (io, peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+            let peer_task = collect_peer_writes(peer);
+
+            let headers = conn.common_headers("GET");
+            let mut ids = Vec::new();
+            for end_stream in [true, false, true] {
+                ids.push(conn.open_next_stream(&headers, end_stream).await.unwrap());
+            }
+            assert_eq!(ids, [StreamId(1), StreamId(3), StreamId(5)]);
+            drop(conn);
+
+            // (type, stream id, flags) of each frame
+            let received = peer_task.await.unwrap();
+            let frames: Vec<_> = split_frames(&received)
+                .into_iter()
+                .map(|(t, f, stream_id, _)| (t, stream_id, f))
+                .collect();
+            assert_eq!(frames, [(0x1, 1, 0x5), (0x1, 3, 0x4), (0x1, 5, 0x5)]);
+        });
+    }
+
+    #[test]
+    fn hpack_string_long_length() {
+        let mut out = Vec::new();
+        encode_hpack_string(&[b'x'; 200], &mut out);
+        // 127 in the prefix, then 200 - 127 = 73
+        assert_eq!(&out[..2], &[0x7f, 73]);
+        assert_eq!(out.len(), 2 + 200);
+    }
+
+    #[test]
+    fn frame_t_name() {
+        let cases = [
+            (FrameT::Data, "DATA"),
+            (FrameT::Headers, "HEADERS"),
+            (FrameT::Priority, "PRIORITY"),
+            (FrameT::RstStream, "RST_STREAM"),
+            (FrameT::Settings, "SETTINGS"),
+            (FrameT::PushPromise, "PUSH_PROMISE"),
+            (FrameT::Ping, "PING"),
+            (FrameT::GoAway, "GOAWAY"),
+            (FrameT::WindowUpdate, "WINDOW_UPDATE"),
+            (FrameT::Continuation, "CONTINUATION"),
+            (FrameT::PriorityUpdate, "PRIORITY_UPDATE"),
+            (FrameT::Unknown, "UNKNOWN"),
+        ];
+        for (frame_t, name) in cases {
+            assert_eq!(frame_t.name(), name);
+            assert_eq!(format!("{frame_t}"), name);
+        }
+
+        assert_eq!(
+            FrameT::describe(FrameT::Headers | FrameT::Data | FrameT::RstStream),
+            "DATA | HEADERS | RST_STREAM"
+        );
+    }
+
+    /// Returns the chunks the handshake gets written in, as seen by the peer.
+    async fn handshake_writes(config: Config) -> Vec<Vec<u8>> {
+        let (io, mut peer) = DuplexIo::new();
+        let mut conn = Conn::new(Rc::new(config), io);
+
+        let peer_task = fluke_buffet::spawn(async move {
+            let mut chunks = Vec::new();
+            let mut len = 0;
+            while len < PREFACE.len() + 9 + default_settings().0.len() * 6 {
+                let chunk = peer.recv().await.unwrap().unwrap();
+                len += chunk.len();
+                chunks.push(chunk);
+            }
+
+            // cut the handshake short
+            peer.send_frame(
+                FrameType::GoAway.into_frame(StreamId::CONNECTION),
+                GoAway {
+                    last_stream_id: StreamId::CONNECTION,
+                    error_code: KnownErrorCode::NoError.into(),
+                    additional_debug_data: Piece::empty(),
+                },
+            )
+            .await
+            .unwrap();
+            peer.drain().await.unwrap();
+            chunks
+        });
+
+        assert!(conn.handshake().await.is_err());
+        drop(conn);
+        peer_task.await.unwrap()
+    }
+
+    #[test]
+    fn handshake_write_coalescing() {
+        fluke_buffet::start(async move {
+            let separate = handshake_writes(Config::default()).await;
+            let coalesced = handshake_writes(Config {
+                coalesce_handshake: true,
+                ..Default::default()
+            })
+            .await;
+
+            assert!(separate.len() > 1);
+            assert_eq!(coalesced.len(), 1);
+            assert_eq!(separate.concat(), coalesced.concat());
+        });
+    }
+
+    #[test]
+    fn connection_error_must_be_on_stream_zero() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+            fluke_buffet::spawn(async move {
+                peer.send_frame(
+                    FrameType::GoAway.into_frame(StreamId(1)),
+                    GoAway {
+                        last_stream_id: StreamId::CONNECTION,
+                        error_code: KnownErrorCode::ProtocolError.into(),
+                        additional_debug_data: Piece::empty(),
+                    },
+                )
+                .await
+                .unwrap();
+                peer.drain().await.unwrap();
+            });
+
+            let msg = conn
+                .verify_connection_error(ErrorC::ProtocolError)
+                .await
+                .unwrap_err()
+                .to_string();
+            assert!(msg.contains("on stream 1"), "{msg}");
+        });
+    }
+
+    #[test]
+    fn handshake_fails_on_goaway_before_settings() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+            fluke_buffet::spawn(async move {
+                peer.send_frame(
+                    FrameType::GoAway.into_frame(StreamId::CONNECTION),
+                    GoAway {
+                        last_stream_id: StreamId::CONNECTION,
+                        error_code: KnownErrorCode::EnhanceYourCalm.into(),
+                        additional_debug_data: Piece::empty(),
+                    },
+                )
+                .await
+                .unwrap();
+                peer.drain().await.unwrap();
+            });
+
+            let err = conn.handshake().await.unwrap_err();
+            let msg = err.to_string();
+            assert!(msg.contains("GOAWAY before its SETTINGS"), "{msg}");
+            assert!(msg.contains("EnhanceYourCalm"), "{msg}");
+        });
+    }
+
+    #[test]
+    fn response_framing_rejects_data_before_headers() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+            let peer_task = fluke_buffet::spawn(async move {
+                peer.send_frame(
+                    FrameType::Data(DataFlags::EndStream.into()).into_frame(StreamId(1)),
+                    &b"too early"[..],
+                )
+                .await
+                .unwrap();
+
+                recv_all(&mut peer).await
+            });
+
+            let err = conn.expect_response_framing(StreamId(1)).await.unwrap_err();
+            let msg = err.to_string();
+            assert!(msg.contains("before the response HEADERS"), "{msg}");
+            drop(conn);
+
+            // RST_STREAM on stream 1, with PROTOCOL_ERROR
+            let received = peer_task.await.unwrap();
+            assert_eq!(received, [0, 0, 4, 0x3, 0, 0, 0, 0, 1, 0, 0, 0, 0x1]);
+        });
+    }
+
+    #[test]
+    fn unmatched_frames_are_buffered() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(
+                Rc::new(Config {
+                    buffer_unmatched_frames: true,
+                    ..Default::default()
+                }),
+                io,
+            );
+
+            fluke_buffet::spawn(async move {
+                peer.send_frame(
+                    FrameType::Ping(PingFlags::Ack.into()).into_frame(StreamId::CONNECTION),
+                    &b"pingpong"[..],
+                )
+                .await
+                .unwrap();
+                peer.send_frame(
+                    FrameType::Data(DataFlags::EndStream.into()).into_frame(StreamId(1)),
+                    &b"hi"[..],
+                )
+                .await
+                .unwrap();
+                peer.drain().await.unwrap();
+            });
+
+            let (frame, payload) = conn.wait_for_frame(FrameT::Data).await.unwrap();
+            assert_eq!(frame.stream_id, StreamId(1));
+            assert_eq!(&payload[..], b"hi");
+
+            // the PING arrived first, and is still there
+            let (frame, payload) = conn.next_frame().await.unwrap();
+            assert!(matches!(frame.frame_type, FrameType::Ping(_)));
+            assert_eq!(&payload[..], b"pingpong");
+        });
+    }
+
+    #[test]
+    fn wait_for_frame_or_error_returns_resets() {
+        fluke_buffet::start(async move {
+            let (io, peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+            fluke_buffet::spawn(async move {
+                peer.reset().await;
+            });
+
+            let res = conn.wait_for_frame_or_error(FrameT::GoAway).await;
+            assert!(res.is_err(), "a reset should surface as an error");
+        });
+    }
+
+    #[test]
+    fn expect_connection_closed() {
+        fluke_buffet::start(async move {
+            let (io, peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+            fluke_buffet::spawn(async move {
+                peer.reset().await;
+            });
+            conn.expect_connection_closed().await.unwrap();
+
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+            fluke_buffet::spawn(async move {
+                peer.send_frame(
+                    FrameType::Ping(PingFlags::Ack.into()).into_frame(StreamId::CONNECTION),
+                    &b"pingpong"[..],
+                )
+                .await
+                .unwrap();
+                peer.drain().await.unwrap();
+            });
+            let msg = conn
+                .expect_connection_closed()
+                .await
+                .unwrap_err()
+                .to_string();
+            assert!(msg.contains("Ping"), "{msg}");
+        });
+    }
+
+    #[test]
+    fn frame_observer_sees_every_frame() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+            let seen: Rc<RefCell<Vec<(FrameT, usize)>>> = Default::default();
+            conn.with_frame_observer({
+                let seen = seen.clone();
+                move |frame, payload| {
+                    seen.borrow_mut()
+                        .push((frame.frame_type.into(), payload.len()))
+                }
+            });
+
+            fluke_buffet::spawn(async move {
+                peer.send_frame(
+                    FrameType::Data(Default::default()).into_frame(StreamId(1)),
+                    &b"hi"[..],
+                )
+                .await
+                .unwrap();
+                peer.send_frame(
+                    FrameType::Ping(PingFlags::Ack.into()).into_frame(StreamId::CONNECTION),
+                    &b"pingpong"[..],
+                )
+                .await
+                .unwrap();
+                peer.drain().await.unwrap();
+            });
+
+            // the DATA frame is skipped, but still observed
+            conn.wait_for_frame(FrameT::Ping).await.unwrap();
+            assert_eq!(*seen.borrow(), [(FrameT::Data, 2), (FrameT::Ping, 8)]);
+        });
+    }
+
+    #[test]
+    fn oversized_frame_is_rejected() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+            fluke_buffet::spawn(async move {
+                // only the header: the payload must not be waited for
+                let frame = FrameType::Data(Default::default())
+                    .into_frame(StreamId(1))
+                    .with_len(DEFAULT_FRAME_SIZE + 1);
+                let header = frame.into_piece(&mut RollMut::alloc().unwrap()).unwrap();
+                peer.send(header).await.unwrap();
+                peer.drain().await.unwrap();
+            });
+
+            let err = conn
+                .wait_for_frame_or_error(FrameT::Data)
+                .await
+                .unwrap_err();
+            let msg = err.to_string();
+            assert!(msg.contains("SETTINGS_MAX_FRAME_SIZE (16384)"), "{msg}");
+        });
+    }
+
+    #[test]
+    fn frame_timestamps() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let config = Config {
+                buffer_unmatched_frames: true,
+                ..Default::default()
+            };
+            let mut conn = Conn::new(Rc::new(config), io);
+
+            fluke_buffet::spawn(async move {
+                for i in 0..4u8 {
+                    let mut ping = vec![0, 0, 8, 0x6, 0x1, 0, 0, 0, 0];
+                    ping.extend_from_slice(&[i; 8]);
+                    peer.send(ping).await.unwrap();
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+                peer.send(vec![0, 0, 0, 0x0, 0x1, 0, 0, 0, 1])
+                    .await
+                    .unwrap();
+                peer.drain().await.unwrap();
+            });
+
+            let start = Instant::now();
+            // skipping over the PINGs buffers them, timestamps and all
+            let (_, _, data_at) = conn.wait_for_frame_timed(FrameT::Data).await.unwrap();
+
+            let mut last = start;
+            for i in 0..4u8 {
+                let (_, payload, received_at) =
+                    conn.wait_for_frame_timed(FrameT::Ping).await.unwrap();
+                assert_eq!(&payload[..], &[i; 8]);
+                assert!(received_at >= last, "timestamps went backwards");
+                last = received_at;
+            }
+            assert!(data_at >= last);
+            assert!(last.duration_since(start) >= Duration::from_millis(15));
+        });
+    }
+
+    #[test]
+    fn transcript() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let config = Config {
+                record_frames: true,
+                ..Default::default()
+            };
+            let mut conn = Conn::new(Rc::new(config), io);
+
+            fluke_buffet::spawn(async move {
+                // wait for each of the client's frames before answering, so
+                // the order is deterministic
+                await_client_handshake(&mut peer).await;
+                peer.send(vec![0, 0, 0, 0x4, 0x0, 0, 0, 0, 0])
+                    .await
+                    .unwrap();
+                peer.recv().await.unwrap().unwrap();
+                peer.send(vec![0, 0, 0, 0x4, 0x1, 0, 0, 0, 0])
+                    .await
+                    .unwrap();
+                peer.drain().await.unwrap();
+            });
+
+            conn.handshake().await.unwrap();
+            let golden = format!(
+                "
+                > SETTINGS stream=0 flags=0x00 len={}
+                < SETTINGS stream=0 flags=0x00 len=0
+                > SETTINGS stream=0 flags=0x01 len=0
+                < SETTINGS stream=0 flags=0x01 len=0
+                ",
+                default_settings().0.len() * 6
+            );
+            conn.transcript().assert_matches(&golden).unwrap();
+        });
+    }
+
+    #[test]
+    fn replay() {
+        fluke_buffet::start(async move {
+            let settings = [0, 0, 0, 0x4, 0x0, 0, 0, 0, 0];
+            let settings_ack = vec![0, 0, 0, 0x4, 0x1, 0, 0, 0, 0];
+            let mut ping = vec![0, 0, 8, 0x6, 0x0, 0, 0, 0, 0];
+            ping.extend_from_slice(b"pingpong");
+
+            // the first frame header is split across two reads
+            let mut conn = Conn::replay(
+                Rc::new(Config::default()),
+                [
+                    settings[..4].to_vec(),
+                    settings[4..].to_vec(),
+                    settings_ack,
+                    ping,
+                ],
+            );
+            conn.handshake().await.unwrap();
+
+            let (frame, payload) = conn.wait_for_frame(FrameT::Ping).await.unwrap();
+            assert!(!frame.is_ack());
+            assert_eq!(&payload[..], b"pingpong");
+
+            assert!(matches!(
+                conn.next_frame().await,
+                FrameWaitOutcome::Eof { .. }
+            ));
+        });
+    }
+
+    #[test]
+    fn padded_data_round_trip() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+            // the peer sends back whatever it gets
+            fluke_buffet::spawn(async move {
+                while let Some(chunk) = peer.recv().await.unwrap() {
+                    peer.send(chunk).await.unwrap();
+                }
+            });
+
+            conn.send_data_padded(StreamId(1), b"hello", 3, true)
+                .await
+                .unwrap();
+            let body = conn.read_body(StreamId(1), 100).await.unwrap();
+            assert_eq!(body, b"hello");
+
+            // 6 bytes of padding announced, none sent: the padding spills
+            // over the data and the pad length octet
+            conn.send_data_padded_unchecked(StreamId(3), b"Test", 6, 0, true)
+                .await
+                .unwrap();
+            let err = conn.read_body(StreamId(3), 100).await.unwrap_err();
+            assert!(err.to_string().contains("6 bytes of padding"), "{err}");
+        });
+    }
+
+    #[test]
+    fn shutdown_write() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+            let peer_task = fluke_buffet::spawn(async move {
+                let received = recv_all(&mut peer).await;
+                // we only respond once the harness is done sending
+                peer.send(vec![0, 0, 0, 0x0, 0x1, 0, 0, 0, 1])
+                    .await
+                    .unwrap();
+                peer.drain().await.unwrap();
+                received
+            });
+
+            conn.write_ping(false, vec![0u8; 8]).await.unwrap();
+            conn.shutdown_write().await.unwrap();
+            assert!(conn.write_ping(false, vec![0u8; 8]).await.is_err());
+
+            let (frame, _) = conn.wait_for_frame(FrameT::Data).await.unwrap();
+            assert_eq!(frame.stream_id, StreamId(1));
+            drop(conn);
+
+            let received = peer_task.await.unwrap();
+            assert_eq!(received.len(), 9 + 8, "only the first PING went out");
+        });
+    }
+
+    #[test]
+    fn auto_ack_ping() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let config = Config {
+                auto_ack_ping: true,
+                ..Default::default()
+            };
+            let mut conn = Conn::new(Rc::new(config), io);
+
+            let peer_task = fluke_buffet::spawn(async move {
+                let mut ping = vec![0, 0, 8, 0x6, 0x0, 0, 0, 0, 0];
+                ping.extend_from_slice(b"keepaliv");
+                peer.send(ping).await.unwrap();
+                peer.send(vec![0, 0, 0, 0x0, 0x1, 0, 0, 0, 1])
+                    .await
+                    .unwrap();
+
+                recv_all(&mut peer).await
+            });
+
+            // the PING is answered and skipped, even though we asked for PINGs
+            let (frame, _) = conn
+                .wait_for_frame(FrameT::Ping | FrameT::Data)
+                .await
+                .unwrap();
+            assert!(matches!(frame.frame_type, FrameType::Data(_)));
+            drop(conn);
+
+            let mut pong = vec![0, 0, 8, 0x6, 0x1, 0, 0, 0, 0];
+            pong.extend_from_slice(b"keepaliv");
+            assert_eq!(peer_task.await.unwrap(), pong);
+        });
+    }
+
+    #[test]
+    fn byte_counts() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+            fluke_buffet::spawn(async move {
+                let mut data = vec![0, 0, 5, 0x0, 0x0, 0, 0, 0, 1];
+                data.extend_from_slice(b"hello");
+                peer.send(data).await.unwrap();
+                peer.send(vec![0, 0, 0, 0x0, 0x1, 0, 0, 0, 1])
+                    .await
+                    .unwrap();
+                peer.drain().await.unwrap();
+            });
+
+            assert_eq!(conn.sent_byte_count(), 0);
+            conn.send(PREFACE.to_vec()).await.unwrap();
+            conn.write_ping(false, vec![0u8; 8]).await.unwrap();
+            assert_eq!(conn.sent_byte_count(), (PREFACE.len() + 9 + 8) as u64);
+
+            let (frame, _) = conn.wait_for_frame(FrameT::Data).await.unwrap();
+            assert!(!frame.is_end_stream());
+            let (frame, _) = conn.wait_for_frame(FrameT::Data).await.unwrap();
+            assert!(frame.is_end_stream());
+            assert_eq!(conn.received_byte_count(), 9 + 5 + 9);
+        });
+    }
+
+    #[test]
+    fn send_unknown_frame() {
+        fluke_buffet::start(async move {
+            let (io, peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+            let peer_task = collect_peer_writes(peer);
+
+            conn.send_unknown_frame(0xfe, 0x21, StreamId(3), b"abc")
+                .await
+                .unwrap();
+            drop(conn);
+
+            assert_eq!(
+                peer_task.await.unwrap(),
+                [0, 0, 3, 0xfe, 0x21, 0, 0, 0, 3, b'a', b'b', b'c']
+            );
+        });
+    }
+
+    #[test]
+    fn send_settings_then() {
+        fluke_buffet::start(async move {
+            let (io, peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+            let peer_task = collect_peer_writes(peer);
+
+            let ping = FrameType::Ping(Default::default()).into_frame(StreamId::CONNECTION);
+            conn.send_settings_then(
+                SettingPairs(&[(Setting::InitialWindowSize, 1)]),
+                vec![(ping, Piece::from(b"pingpong".to_vec()))],
+            )
+            .await
+            .unwrap();
+            assert_eq!(conn.sent_byte_count(), 9 + 6 + 9 + 8);
+            drop(conn);
+
+            let mut expected = vec![0, 0, 6, 0x4, 0x0, 0, 0, 0, 0, 0, 0x4, 0, 0, 0, 1];
+            expected.extend_from_slice(&[0, 0, 8, 0x6, 0x0, 0, 0, 0, 0]);
+            expected.extend_from_slice(b"pingpong");
+            assert_eq!(peer_task.await.unwrap(), expected);
+        });
+    }
+
+    #[test]
+    fn verify_no_response_on_stream() {
+        fluke_buffet::start(async move {
+            // a DATA frame on another stream, then the frame on stream 3
+            let cases: [(Vec<u8>, bool); 3] = [
+                (vec![0, 0, 4, 0x3, 0x0, 0, 0, 0, 3, 0, 0, 0, 0x7], true),
+                (vec![0, 0, 4, 0x3, 0x0, 0, 0, 0, 3, 0, 0, 0, 0x2], false),
+                (vec![0, 0, 1, 0x0, 0x1, 0, 0, 0, 3, b'x'], false),
+            ];
+            for (frame, ok) in cases {
+                let (io, mut peer) = DuplexIo::new();
+                let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+                fluke_buffet::spawn(async move {
+                    peer.send(vec![0, 0, 1, 0x0, 0x1, 0, 0, 0, 1, b'x'])
+                        .await
+                        .unwrap();
+                    peer.send(frame).await.unwrap();
+                    peer.drain().await.unwrap();
+                });
+
+                let res = conn
+                    .verify_no_response_on_stream(StreamId(3), Duration::from_millis(100))
+                    .await;
+                assert_eq!(res.is_ok(), ok, "{res:?}");
+            }
+        });
+    }
+
+    #[test]
+    fn expect_error_or_close() {
+        fluke_buffet::start(async move {
+            let cases: [(Vec<u8>, Option<ErrorOutcome>); 4] = [
+                (
+                    vec![0, 0, 4, 0x3, 0x0, 0, 0, 0, 3, 0, 0, 0, 0x1],
+                    Some(ErrorOutcome::RstStream {
+                        error: ErrorC::ProtocolError,
+                        stream_id: StreamId(3),
+                    }),
+                ),
+                (
+                    vec![0, 0, 8, 0x7, 0x0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0x1],
+                    Some(ErrorOutcome::GoAway {
+                        error: ErrorC::ProtocolError,
+                        last_stream_id: StreamId(1),
+                    }),
+                ),
+                // nothing but EOF
+                (vec![], Some(ErrorOutcome::Closed)),
+                // the wrong error code
+                (vec![0, 0, 4, 0x3, 0x0, 0, 0, 0, 3, 0, 0, 0, 0x8], None),
+            ];
+            for (frame, expected) in cases {
+                let (io, mut peer) = DuplexIo::new();
+                let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+                fluke_buffet::spawn(async move {
+                    // a frame to skip, first
+                    peer.send(vec![0, 0, 1, 0x0, 0x0, 0, 0, 0, 1, b'x'])
+                        .await
+                        .unwrap();
+                    peer.send(frame).await.unwrap();
+                });
+
+                let res = conn.expect_error_or_close(ErrorC::ProtocolError).await;
+                assert_eq!(res.ok(), expected);
+            }
+        });
+    }
+
+    #[test]
+    fn read_pacing() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let config = Config {
+                read_pacing: Some(Duration::from_millis(20)),
+                timeout: Duration::from_secs(1),
+                ..Default::default()
+            };
+            let mut conn = Conn::new(Rc::new(config), io);
+
+            fluke_buffet::spawn(async move {
+                // each write is a read of its own
+                for _ in 0..3 {
+                    peer.send(vec![0, 0, 1, 0x0, 0x0, 0, 0, 0, 1, b'x'])
+                        .await
+                        .unwrap();
+                }
+                peer.drain().await.unwrap();
+            });
+
+            let start = Instant::now();
+            for _ in 0..3 {
+                conn.wait_for_frame(FrameT::Data).await.unwrap();
+            }
+            assert!(start.elapsed() >= Duration::from_millis(60));
+        });
+    }
+
+    #[test]
+    fn smoke_test_reports_failed_step() {
+        fluke_buffet::start(async move {
+            let err = smoke_test(Default::default(), || async {
+                // nobody's listening on the other end
+                let (io, peer) = DuplexIo::new();
+                drop(peer);
+                Ok(io)
+            })
+            .await
+            .unwrap_err()
+            .to_string();
+            assert!(err.starts_with("HTTP/2 handshake failed"), "{err}");
+        });
+    }
+
+    #[test]
+    fn hpack_table_sizes() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+            fluke_buffet::spawn(async move { peer.drain().await.unwrap() });
+
+            assert_eq!(conn.hpack_encoder_table_size(), 0);
+            assert_eq!(conn.hpack_decoder_table_size(), 0);
+
+            // pseudo-header names are in the static table, so those fields
+            // aren't added: only the custom one is, the first time around
+            let mut headers = conn.common_headers("GET");
+            headers.append("x-custom", "value");
+            for _ in 0..3 {
+                let stream_id = conn.open_stream().unwrap();
+                conn.send_request(stream_id, &headers, &[], Default::default())
+                    .await
+                    .unwrap();
+                assert_eq!(conn.hpack_encoder_table_size(), 8 + 5 + 32);
+            }
+
+            headers.append("x-other", "v");
+            let stream_id = conn.open_stream().unwrap();
+            conn.send_request(stream_id, &headers, &[], Default::default())
+                .await
+                .unwrap();
+            assert_eq!(conn.hpack_encoder_table_size(), 2 * 32 + 8 + 5 + 7 + 1);
+
+            // the decoder side follows what the peer's encoder adds
+            let mut peer_enc = fluke_hpack::Encoder::new();
+            let block = peer_enc.encode([
+                (&b":status"[..], &b"200"[..]),
+                (&b"x-served-by"[..], &b"us"[..]),
+            ]);
+            conn.decode_headers(block.into()).unwrap();
+            assert_eq!(conn.hpack_decoder_table_size(), peer_enc.table_size());
+            assert_eq!(conn.hpack_decoder_table_size(), 11 + 2 + 32);
+        });
+    }
+
+    #[test]
+    fn strict_flags() {
+        fluke_buffet::start(async move {
+            for strict_flags in [false, true] {
+                let (io, mut peer) = DuplexIo::new();
+                let config = Config {
+                    strict_flags,
+                    ..Default::default()
+                };
+                let mut conn = Conn::new(Rc::new(config), io);
+
+                fluke_buffet::spawn(async move {
+                    // an empty DATA frame with END_HEADERS (0x4), which isn't
+                    // defined for DATA
+                    peer.send(vec![0, 0, 0, 0x0, 0x4, 0, 0, 0, 1])
+                        .await
+                        .unwrap();
+                    peer.drain().await.unwrap();
+                });
+
+                let res = conn.wait_for_frame_or_error(FrameT::Data).await;
+                if strict_flags {
+                    let msg = res.unwrap_err().to_string();
+                    assert!(msg.contains("undefined flags"), "{msg}");
+                    assert!(msg.contains("0x04"), "{msg}");
+                } else {
+                    let (frame, _) = res.unwrap();
+                    assert!(matches!(frame.frame_type, FrameType::Data(flags) if flags.is_empty()));
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn preface_test_requires_settings_first() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let conn = Conn::new(Rc::new(Config::default()), io);
+
+            fluke_buffet::spawn(async move {
+                peer.send_frame(
+                    FrameType::WindowUpdate.into_frame(StreamId::CONNECTION),
+                    WindowUpdate {
+                        reserved: 0,
+                        increment: 1024,
+                    },
+                )
+                .await
+                .unwrap();
+                peer.send_frame(
+                    FrameType::Settings(Default::default()).into_frame(StreamId::CONNECTION),
+                    (),
+                )
+                .await
+                .unwrap();
+                peer.drain().await.unwrap();
+            });
+
+            let err = crate::rfc9113::_3_starting_http2::sends_client_connection_preface(conn)
+                .await
+                .unwrap_err();
+            let msg = err.to_string();
+            assert!(msg.contains("got Conn:WindowUpdate"), "{msg}");
+        });
+    }
+
+    #[test]
+    fn expect_window_update_filters_by_stream() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+            fluke_buffet::spawn(async move {
+                for (stream_id, increment) in [(StreamId(1), 10), (StreamId::CONNECTION, 1000)] {
+                    peer.send_frame(
+                        FrameType::WindowUpdate.into_frame(stream_id),
+                        WindowUpdate {
+                            reserved: 0,
+                            increment,
+                        },
+                    )
+                    .await
+                    .unwrap();
+                }
+                peer.drain().await.unwrap();
+            });
+
+            let window = conn.send_window();
+            assert_eq!(conn.expect_window_update(None, 500).await.unwrap(), 1000);
+            assert_eq!(conn.send_window(), window + 1000);
+        });
+    }
+
+    #[test]
+    fn expect_window_update_reports_small_increments() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+            fluke_buffet::spawn(async move {
+                peer.send_frame(
+                    FrameType::WindowUpdate.into_frame(StreamId(1)),
+                    WindowUpdate {
+                        reserved: 0,
+                        increment: 10,
+                    },
+                )
+                .await
+                .unwrap();
+                peer.drain().await.unwrap();
+            });
+
+            let msg = conn
+                .expect_window_update(Some(StreamId(1)), 100)
+                .await
+                .unwrap_err()
+                .to_string();
+            assert!(msg.contains("increment of 10"), "{msg}");
+            assert!(msg.contains("at least 100"), "{msg}");
+        });
+    }
+
+    #[test]
+    fn measure_rtt_skips_other_acks() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+            fluke_buffet::spawn(async move {
+                let mut ping = Vec::new();
+                while ping.len() < 9 + 8 {
+                    ping.extend(peer.recv().await.unwrap().unwrap());
+                }
+                let ack = FrameType::Ping(PingFlags::Ack.into()).into_frame(StreamId::CONNECTION);
+
+                // a stale ACK first, then the right one
+                peer.send_frame(ack, &b"stalepng"[..]).await.unwrap();
+                peer.send_frame(ack, ping[9..].to_vec()).await.unwrap();
+                peer.drain().await.unwrap();
+            });
+
+            conn.measure_rtt().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn payload_larger_than_scratch() {
+        fluke_buffet::start(async move {
+            for scratch_size in [None, Some(64), Some(1 << 20)] {
+                let (io, peer) = DuplexIo::new();
+                let mut conn = Conn::new(
+                    Rc::new(Config {
+                        scratch_size,
+                        ..Default::default()
+                    }),
+                    io,
+                );
+
+                let peer_task = collect_peer_writes(peer);
+
+                let debug_data = dummy_bytes(100_000);
+                conn.write_frame(
+                    FrameType::GoAway.into_frame(StreamId::CONNECTION),
+                    GoAway {
+                        last_stream_id: StreamId::CONNECTION,
+                        error_code: KnownErrorCode::NoError.into(),
+                        additional_debug_data: debug_data.clone().into(),
+                    },
+                )
+                .await
+                .unwrap();
+                drop(conn);
+
+                let received = peer_task.await.unwrap();
+                assert_eq!(received.len(), 9 + 8 + debug_data.len());
+                assert_eq!(&received[9 + 8..], &debug_data[..]);
+            }
+        });
+    }
+
+    #[test]
+    fn interim_responses() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+            fluke_buffet::spawn(async move {
+                let mut enc = fluke_hpack::Encoder::new();
+                for status in [&b"100"[..], &b"200"[..]] {
+                    let block = enc.encode(vec![(&b":status"[..], status)]);
+                    peer.send_frame(
+                        FrameType::Headers(HeadersFlags::EndHeaders.into()).into_frame(StreamId(1)),
+                        block,
+                    )
+                    .await
+                    .unwrap();
+                }
+                peer.drain().await.unwrap();
+            });
+
+            let interims = conn.read_interim_responses(StreamId(1)).await.unwrap();
+            assert_eq!(interims, [vec![(b":status".to_vec(), b"100".to_vec())]]);
+        });
+    }
+
+    #[test]
+    fn read_body_decodes_header_blocks() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+            let peer_task = fluke_buffet::spawn(async move {
+                let mut enc = fluke_hpack::Encoder::new();
+
+                // response headers, split across HEADERS and CONTINUATION
+                let block = enc.encode([
+                    (&b":status"[..], &b"200"[..]),
+                    (&b"x-served-by"[..], &b"us"[..]),
+                ]);
+                peer.send_frame(
+                    FrameType::Headers(Default::default()).into_frame(StreamId(1)),
+                    block[..4].to_vec(),
+                )
+                .await
+                .unwrap();
+                peer.send_frame(
+                    FrameType::Continuation(ContinuationFlags::EndHeaders.into())
+                        .into_frame(StreamId(1)),
+                    block[4..].to_vec(),
+                )
+                .await
+                .unwrap();
+
+                // another stream's headers, also in the HPACK context
+                let block =
+                    enc.encode([(&b":status"[..], &b"204"[..]), (&b"x-other"[..], &b"v"[..])]);
+                peer.send_frame(
+                    FrameType::Headers(HeadersFlags::EndHeaders | HeadersFlags::EndStream)
+                        .into_frame(StreamId(3)),
+                    block,
+                )
+                .await
+                .unwrap();
+
+                peer.send_frame(
+                    FrameType::Data(Default::default()).into_frame(StreamId(1)),
+                    &b"hello"[..],
+                )
+                .await
+                .unwrap();
+
+                let block = enc.encode([(&b"x-trailer"[..], &b"t"[..])]);
+                peer.send_frame(
+                    FrameType::Headers(HeadersFlags::EndHeaders | HeadersFlags::EndStream)
+                        .into_frame(StreamId(1)),
+                    block,
+                )
+                .await
+                .unwrap();
+
+                // a CONTINUATION without a HEADERS frame before it
+                peer.send_frame(
+                    FrameType::Continuation(ContinuationFlags::EndHeaders.into())
+                        .into_frame(StreamId(5)),
+                    vec![0x88],
+                )
+                .await
+                .unwrap();
+                peer.drain().await.unwrap();
+                enc.table_size()
+            });
+
+            let body = conn.read_body(StreamId(1), 100).await.unwrap();
+            assert_eq!(body, b"hello");
+
+            let err = conn.read_body(StreamId(5), 100).await.unwrap_err();
+            assert!(err.to_string().contains("without a HEADERS frame"), "{err}");
+
+            let table_size = conn.hpack_decoder_table_size();
+            drop(conn);
+            let peer_table_size = peer_task.await.unwrap();
+            assert_eq!(table_size, peer_table_size);
+            assert_eq!(peer_table_size, (11 + 2 + 32) + (7 + 1 + 32) + (9 + 1 + 32));
+        });
     }
 
-    pub async fn encode_and_write_headers(
-        &mut self,
-        stream_id: StreamId,
-        flags: impl Into<BitFlags<HeadersFlags>>,
-        headers: &Headers,
-    ) -> eyre::Result<()> {
-        let flags = flags.into();
-        let block_fragment = self.encode_headers(headers)?;
-        self.write_headers(stream_id, flags, block_fragment).await?;
-        Ok(())
-    }
+    #[test]
+    fn frame_header_split_across_reads() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
 
-    pub async fn write_headers(
-        &mut self,
-        stream_id: StreamId,
-        flags: impl Into<BitFlags<HeadersFlags>>,
-        block_fragment: Piece,
-    ) -> eyre::Result<()> {
-        let flags = flags.into();
-        let frame = Frame::new(FrameType::Headers(flags), stream_id);
-        self.write_frame(frame, block_fragment).await?;
-        Ok(())
+            let mut scratch = RollMut::alloc().unwrap();
+            let header = FrameType::Ping(Default::default())
+                .into_frame(StreamId::CONNECTION)
+                .with_len(8)
+                .into_piece(&mut scratch)
+                .unwrap();
+
+            fluke_buffet::spawn(async move {
+                // one read per byte of the frame header, then the payload
+                peer.send_chunked(header, 1).await.unwrap();
+                peer.send(&b"pingpong"[..]).await.unwrap();
+                peer.drain().await.unwrap();
+            });
+
+            match conn.ev_rx.recv().await {
+                Some(Ev::Frame { frame, payload, .. }) => {
+                    assert!(matches!(frame.frame_type, FrameType::Ping(_)));
+                    assert_eq!(frame.len, 8);
+                    assert_eq!(&payload[..], b"pingpong");
+                }
+                _ => panic!("expected a single PING frame"),
+            }
+
+            // the receive loop gives up once the peer stays quiet: there
+            // should be no other event before that
+            assert!(conn.ev_rx.recv().await.is_none());
+        });
     }
 
-    pub async fn write_headers_with_priority(
-        &mut self,
-        stream_id: StreamId,
-        flags: impl Into<BitFlags<HeadersFlags>>,
-        priority_spec: PrioritySpec,
-        block_fragment: Piece,
-    ) -> eyre::Result<()> {
-        let flags = flags.into() | HeadersFlags::Priority;
-        let frame = Frame::new(FrameType::Headers(flags), stream_id);
+    #[test]
+    fn handshake_with_empty_settings() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+            let initial_settings = conn.settings;
 
-        let payload = block_fragment.into_piece(&mut self.scratch)?;
-        let frame = frame.with_len(payload.len().try_into().unwrap());
+            let peer_task = fluke_buffet::spawn(async move {
+                let mut received = await_client_handshake(&mut peer).await;
 
-        let priority_spec_piece = priority_spec.into_piece(&mut self.scratch)?;
+                // no parameters at all
+                let settings = Settings::default();
+                peer.send_frame(
+                    FrameType::Settings(Default::default()).into_frame(StreamId::CONNECTION),
+                    settings.encode_changes_from(&settings),
+                )
+                .await
+                .unwrap();
+                peer.send_frame(
+                    FrameType::Settings(SettingsFlags::Ack.into()).into_frame(StreamId::CONNECTION),
+                    (),
+                )
+                .await
+                .unwrap();
 
-        let header = frame.into_piece(&mut self.scratch)?;
-        self.w
-            .writev_all_owned(
-                PieceList::single(header)
-                    .followed_by(priority_spec_piece)
-                    .followed_by(payload),
-            )
-            .await?;
+                received.extend(recv_all(&mut peer).await);
+                received
+            });
 
-        Ok(())
-    }
+            conn.handshake().await.unwrap();
+            assert_eq!(conn.settings.encode(), initial_settings.encode());
+            assert!(!conn.peer_advertised(Setting::MaxFrameSize));
+            drop(conn);
 
-    pub async fn write_continuation(
-        &mut self,
-        stream_id: StreamId,
-        flags: impl Into<BitFlags<ContinuationFlags>>,
-        block_fragment: Piece,
-    ) -> eyre::Result<()> {
-        let flags = flags.into();
-        let frame = Frame::new(FrameType::Continuation(flags), stream_id);
-        self.write_frame(frame, block_fragment).await?;
-        Ok(())
+            let received = peer_task.await.unwrap();
+            let ack = [0, 0, 0, 0x4, 0x1, 0, 0, 0, 0];
+            assert!(
+                received.ends_with(&ack),
+                "the client should ACK empty SETTINGS"
+            );
+        });
     }
 
-    pub async fn write_data(
-        &mut self,
-        stream_id: StreamId,
-        end_stream: bool,
-        data: impl Into<Piece>,
-    ) -> eyre::Result<()> {
-        let frame = Frame::new(
-            FrameType::Data(if end_stream {
-                DataFlags::EndStream.into()
-            } else {
-                Default::default()
-            }),
-            stream_id,
-        );
-        self.write_frame(frame, data.into()).await?;
-        Ok(())
-    }
+    #[test]
+    fn handshake_capture() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
 
-    /// Generates a set of dummy headers.
-    ///
-    /// # Parameters
-    /// - `len`: The number of headers to generate.
-    ///
-    /// # Returns
-    /// - A `Headers` map containing `len` number of headers, where each header
-    ///   has a key in the form of `x-dummy{i}` and a value of `dummy_bytes`
-    ///   with a length equal to `self.config.max_header_len`.
-    ///
-    /// # Properties
-    /// - The size of each header value is equal to
-    ///   `self.config.max_header_len`.
-    /// - The total number of headers in the returned `Headers` map is equal to
-    ///   `len`.
-    fn dummy_headers(&self, len: usize) -> Headers {
-        let mut headers = Headers::default();
-        let dummy = dummy_bytes(self.config.max_header_len);
+            let peer_task = fluke_buffet::spawn(async move {
+                let mut received = await_client_handshake(&mut peer).await;
 
-        for i in 0..len {
-            let name = format!("x-dummy{}", i);
-            headers.append(name.into_bytes(), dummy.clone());
-        }
+                let payload = [0, 0x3, 0, 0, 0, 7, 0, 0x5, 0, 0, 0x80, 0];
+                let mut sent = vec![0, 0, payload.len() as u8, 0x4, 0, 0, 0, 0, 0];
+                sent.extend_from_slice(&payload);
+                peer.send(sent.clone()).await.unwrap();
 
-        headers
-    }
+                let ack = [0, 0, 0, 0x4, 0x1, 0, 0, 0, 0];
+                peer.send(ack.to_vec()).await.unwrap();
+                sent.extend_from_slice(&ack);
 
-    pub async fn write_rst_stream(
-        &mut self,
-        stream_id: StreamId,
-        error_code: impl Into<ErrorCode>,
-    ) -> eyre::Result<()> {
-        let error_code = error_code.into();
-        let rst_stream = RstStream { error_code };
-        self.write_frame(FrameType::RstStream.into_frame(stream_id), rst_stream)
-            .await
-    }
+                received.extend(recv_all(&mut peer).await);
+                (sent, received)
+            });
 
-    async fn write_window_update(
-        &mut self,
-        stream_id: StreamId,
-        increment: u32,
-    ) -> eyre::Result<()> {
-        let update = WindowUpdate {
-            reserved: 0,
-            increment,
-        };
-        let mut rm = RollMut::alloc().unwrap();
-        let piece = update.into_piece(&mut rm).unwrap();
-        tracing::debug!(?update, "writing window_update, bytes = {:x?}", &piece[..]);
+            let record = conn.handshake_capture().await.unwrap();
+            drop(conn);
+            let (peer_sent, peer_received) = peer_task.await.unwrap();
 
-        self.write_frame(FrameType::WindowUpdate.into_frame(stream_id), update)
-            .await
+            let settings: Vec<_> = record
+                .settings
+                .iter()
+                .map(|&(k, v)| (k.repr(), v))
+                .collect();
+            assert_eq!(settings, [(0x3, 7), (0x5, 1 << 15)]);
+            assert!(record.settings_ack_after.is_some());
+            assert!(record.sent.starts_with(PREFACE));
+            assert_eq!(record.sent, peer_received);
+            assert_eq!(record.received, peer_sent);
+        });
     }
 
-    // verify_settings_frame_with_ack verifies whether a SETTINGS frame with
-    // ACK flag was received.
-    async fn verify_settings_frame_with_ack(&mut self) -> eyre::Result<()> {
-        let (frame, _payload) = self.wait_for_frame(FrameT::Settings).await.unwrap();
-        assert!(frame.is_ack());
-        Ok(())
-    }
+    #[test]
+    fn handshake_with_coalesced_settings_ack() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let config = Config {
+                buffer_unmatched_frames: true,
+                ..Default::default()
+            };
+            let mut conn = Conn::new(Rc::new(config), io);
 
-    async fn send_req_and_expect_status(
-        &mut self,
-        stream_id: StreamId,
-        headers: &Headers,
-        expected_status: u16,
-    ) -> eyre::Result<()> {
-        self.encode_and_write_headers(
-            stream_id,
-            HeadersFlags::EndHeaders | HeadersFlags::EndStream,
-            headers,
-        )
-        .await?;
+            fluke_buffet::spawn(async move {
+                await_client_handshake(&mut peer).await;
 
-        let (frame, payload) = self.wait_for_frame(FrameT::Headers).await.unwrap();
-        assert!(
-            frame.is_end_headers(),
-            "the server is free to answer with headers in several frames but this breaks that test"
-        );
+                // SETTINGS, WINDOW_UPDATE, then the ACK of ours, in a
+                // single read
+                let mut coalesced = vec![0, 0, 0, 0x4, 0x0, 0, 0, 0, 0];
+                coalesced.extend_from_slice(&[0, 0, 4, 0x8, 0x0, 0, 0, 0, 0, 0, 0, 0x03, 0xe8]);
+                coalesced.extend_from_slice(&[0, 0, 0, 0x4, 0x1, 0, 0, 0, 0]);
+                peer.send(coalesced).await.unwrap();
+                peer.drain().await.unwrap();
+            });
 
-        let headers = self.decode_headers(payload.into())?;
-        let status = headers
-            .get_first(&":status".into())
-            .expect("response should contain :status");
-        let status = std::str::from_utf8(&status[..])
-            .expect("status should be valid utf-8")
-            .parse::<u16>()
-            .expect("status should be a u16");
-        assert_eq!(status, expected_status);
+            let record = conn.handshake_capture().await.unwrap();
+            assert!(record.settings_ack_after.is_some());
 
-        Ok(())
+            // the WINDOW_UPDATE is still there for tests to look at, and
+            // only counts once it's picked up
+            assert_eq!(conn.send_window(), (1 << 16) - 1);
+            let increment = conn.expect_window_update(None, 1).await.unwrap();
+            assert_eq!(increment, 1000);
+            assert_eq!(conn.send_window(), (1 << 16) - 1 + 1000);
+        });
     }
 
-    async fn send_req_and_expect_stream_rst(
-        &mut self,
-        stream_id: StreamId,
-        headers: &Headers,
-    ) -> eyre::Result<()> {
-        self.encode_and_write_headers(
-            stream_id,
-            HeadersFlags::EndHeaders | HeadersFlags::EndStream,
-            headers,
-        )
-        .await?;
+    #[test]
+    fn handshake_rejects_settings_ack_with_payload() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
 
-        tracing::debug!("verifying stream error...");
-        self.verify_stream_error(ErrorC::ProtocolError).await?;
-        tracing::debug!("verifying stream error... done!");
+            fluke_buffet::spawn(async move {
+                await_client_handshake(&mut peer).await;
 
-        // self.w.shutdown().await?;
+                peer.send(vec![0, 0, 0, 0x4, 0x0, 0, 0, 0, 0])
+                    .await
+                    .unwrap();
+                // an ACK carrying a setting
+                peer.send(vec![0, 0, 6, 0x4, 0x1, 0, 0, 0, 0, 0, 0x3, 0, 0, 0, 100])
+                    .await
+                    .unwrap();
+                peer.drain().await.unwrap();
+            });
 
-        Ok(())
+            let msg = conn.handshake_capture().await.unwrap_err().to_string();
+            assert!(msg.contains("6-byte payload"), "{msg}");
+        });
     }
-}
 
-/// Parameters for tests
-pub struct Config {
-    /// which host to connect to
-    pub host: String,
+    #[test]
+    fn settings_changed_mid_connection() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
 
-    /// which port to connect to
-    pub port: u16,
+            let peer_task = fluke_buffet::spawn(async move {
+                let settings = FrameType::Settings(Default::default());
+                let settings_ack = FrameType::Settings(SettingsFlags::Ack.into());
 
-    /// which path to request
-    pub path: String,
+                let mut received = await_client_handshake(&mut peer).await;
 
-    /// whether to use TLS
-    pub tls: bool,
+                // handshake
+                peer.send_frame(settings.into_frame(StreamId::CONNECTION), ())
+                    .await
+                    .unwrap();
+                peer.send_frame(settings_ack.into_frame(StreamId::CONNECTION), ())
+                    .await
+                    .unwrap();
 
-    /// how long to wait for a frame
-    pub timeout: Duration,
+                // then change our mind about the max frame size
+                peer.send_frame(
+                    settings.into_frame(StreamId::CONNECTION),
+                    SettingPairs(&[(Setting::MaxFrameSize, 1 << 15)]),
+                )
+                .await
+                .unwrap();
+                peer.send_frame(
+                    FrameType::Ping(PingFlags::Ack.into()).into_frame(StreamId::CONNECTION),
+                    &b"pingpong"[..],
+                )
+                .await
+                .unwrap();
 
-    /// maximum length of a header
-    pub max_header_len: usize,
-}
+                received.extend(recv_all(&mut peer).await);
+                received
+            });
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            host: "localhost".into(),
-            port: 80,
-            path: "/".into(),
-            tls: false,
+            conn.handshake().await.unwrap();
+            conn.wait_for_frame(FrameT::Ping).await.unwrap();
+            assert_eq!(conn.settings.max_frame_size, 1 << 15);
+            drop(conn);
 
-            max_header_len: 4000,
+            // one SETTINGS ACK for the handshake, one for the change
+            let received = peer_task.await.unwrap();
+            let ack = [0, 0, 0, 0x4, 0x1, 0, 0, 0, 0];
+            assert_eq!(received.windows(ack.len()).filter(|w| *w == ack).count(), 2);
+        });
+    }
 
-            timeout: Duration::from_millis(100),
-        }
+    #[test]
+    fn invalid_settings_mid_connection() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
+
+            let peer_task = fluke_buffet::spawn(async move {
+                let settings = FrameType::Settings(Default::default());
+                let settings_ack = FrameType::Settings(SettingsFlags::Ack.into());
+
+                let mut received = await_client_handshake(&mut peer).await;
+
+                peer.send_frame(settings.into_frame(StreamId::CONNECTION), ())
+                    .await
+                    .unwrap();
+                peer.send_frame(settings_ack.into_frame(StreamId::CONNECTION), ())
+                    .await
+                    .unwrap();
+
+                // above the maximum window size
+                peer.send_frame(
+                    settings.into_frame(StreamId::CONNECTION),
+                    SettingPairs(&[(Setting::InitialWindowSize, 1 << 31)]),
+                )
+                .await
+                .unwrap();
+
+                received.extend(recv_all(&mut peer).await);
+                received
+            });
+
+            conn.handshake().await.unwrap();
+            match conn.wait_for_frame(FrameT::Ping).await {
+                FrameWaitOutcome::IoError { error, .. } => {
+                    assert!(error.to_string().contains("INITIAL_WINDOW_SIZE"), "{error}")
+                }
+                _ => panic!("expected invalid settings to be a connection error"),
+            }
+            drop(conn);
+
+            // no ACK for the invalid settings, a GOAWAY instead
+            let received = peer_task.await.unwrap();
+            let ack = [0, 0, 0, 0x4, 0x1, 0, 0, 0, 0];
+            assert_eq!(received.windows(ack.len()).filter(|w| *w == ack).count(), 1);
+            let goaway = [0, 0, 8, 0x7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x3];
+            assert!(received.ends_with(&goaway), "{received:?}");
+        });
     }
-}
 
-// DummyString returns a dummy string with specified length.
-pub fn dummy_string(len: usize) -> String {
-    "x".repeat(len)
-}
+    #[test]
+    fn initial_window_size_change_adjusts_stream_windows() {
+        fluke_buffet::start(async move {
+            let (io, mut peer) = DuplexIo::new();
+            let mut conn = Conn::new(Rc::new(Config::default()), io);
 
-// DummyBytes returns an array of bytes with specified length.
-pub fn dummy_bytes(len: usize) -> Vec<u8> {
-    vec![b'x'; len]
+            let peer_task = fluke_buffet::spawn(async move {
+                let settings = FrameType::Settings(Default::default());
+                let settings_ack = FrameType::Settings(SettingsFlags::Ack.into());
+
+                await_client_handshake(&mut peer).await;
+
+                peer.send_frame(settings.into_frame(StreamId::CONNECTION), ())
+                    .await
+                    .unwrap();
+                peer.send_frame(settings_ack.into_frame(StreamId::CONNECTION), ())
+                    .await
+                    .unwrap();
+
+                peer.send_frame(
+                    settings.into_frame(StreamId::CONNECTION),
+                    SettingPairs(&[(Setting::InitialWindowSize, 1000)]),
+                )
+                .await
+                .unwrap();
+                peer.send_frame(
+                    FrameType::Ping(PingFlags::Ack.into()).into_frame(StreamId::CONNECTION),
+                    &b"pingpong"[..],
+                )
+                .await
+                .unwrap();
+
+                peer.drain().await.unwrap();
+            });
+
+            conn.handshake().await.unwrap();
+            let initial = conn.settings.initial_window_size as i64;
+            conn.write_data(StreamId(1), false, dummy_bytes(100))
+                .await
+                .unwrap();
+            assert_eq!(conn.stream_send_window(StreamId(1)), initial - 100);
+
+            // the change is applied while we wait: it shrinks the window of
+            // stream 1, and sets the one of streams we haven't sent on yet
+            conn.wait_for_frame(FrameT::Ping).await.unwrap();
+            assert_eq!(conn.stream_send_window(StreamId(1)), 900);
+            assert_eq!(conn.stream_send_window(StreamId(3)), 1000);
+            // the connection window stays as it was
+            assert_eq!(conn.send_window(), (1 << 16) - 1 - 100);
+
+            drop(conn);
+            peer_task.await.unwrap();
+        });
+    }
 }
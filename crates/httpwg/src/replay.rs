@@ -0,0 +1,81 @@
+//! An in-memory transport that plays back a fixed byte sequence, to test
+//! the receive loop and parsers deterministically, without a peer to script.
+
+use std::collections::VecDeque;
+
+use fluke_buffet::{bufpool::BufResult, pipe, IntoHalves, Piece, PipeRead, WriteOwned};
+
+/// A transport for [crate::Conn::new] that reads back a predefined byte
+/// sequence, then EOF, and discards whatever is written to it.
+///
+/// The bytes are scripted as chunks: each chunk is delivered as a single
+/// read (or several, if the reader's buffer is smaller than the chunk), so a
+/// test controls exactly where reads are split, e.g. in the middle of a frame
+/// header:
+///
+/// ```ignore
+/// let io = ReplayIo::chunked([&settings[..4], &settings[4..]]);
+/// let mut conn = Conn::new(Rc::new(Config::default()), io);
+/// ```
+///
+/// See also [crate::Conn::replay].
+///
+/// Unlike [crate::DuplexIo], what the `Conn` reads doesn't depend on what it
+/// writes.
+pub struct ReplayIo {
+    chunks: VecDeque<Piece>,
+}
+
+impl ReplayIo {
+    /// Plays back `bytes`, delivered as a single chunk.
+    pub fn new(bytes: impl Into<Piece>) -> Self {
+        Self::chunked([bytes])
+    }
+
+    /// Plays back `chunks`, in order, each delivered as a separate read.
+    /// Empty chunks are ignored.
+    pub fn chunked<C: Into<Piece>>(chunks: impl IntoIterator<Item = C>) -> Self {
+        Self {
+            chunks: chunks
+                .into_iter()
+                .map(Into::into)
+                .filter(|chunk: &Piece| !chunk.is_empty())
+                .collect(),
+        }
+    }
+}
+
+impl IntoHalves for ReplayIo {
+    type Read = PipeRead;
+    type Write = DiscardWrite;
+
+    /// Must be called from within a local task set: the chunks are fed to the
+    /// read half from a task of their own.
+    fn into_halves(self) -> (Self::Read, Self::Write) {
+        let (mut w, r) = pipe();
+        fluke_buffet::spawn(async move {
+            for chunk in self.chunks {
+                if w.write_all_owned(chunk).await.is_err() {
+                    // the read half is gone, nobody's listening
+                    return;
+                }
+            }
+            // dropping `w` signals EOF
+        });
+        (r, DiscardWrite)
+    }
+}
+
+/// The write half of a [ReplayIo]: accepts and drops everything.
+pub struct DiscardWrite;
+
+impl WriteOwned for DiscardWrite {
+    async fn write_owned(&mut self, buf: impl Into<Piece>) -> BufResult<usize, Piece> {
+        let buf = buf.into();
+        (Ok(buf.len()), buf)
+    }
+
+    async fn shutdown(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+//! Section 10: Security Considerations
+
+use fluke_buffet::IntoHalves;
+
+use eyre::eyre;
+
+use crate::{Conn, RequestOptions};
+
+//---- Section 10.5: Denial-of-Service Considerations
+
+/// An endpoint that doesn't monitor use of these features exposes itself
+/// to a risk of denial of service. Implementations SHOULD track the use of
+/// these features and set limits on their use.
+///
+/// (This is the "Rapid Reset" attack, CVE-2023-44487: streams are opened and
+/// cancelled right away, so they never count against
+/// SETTINGS_MAX_CONCURRENT_STREAMS, but the server may still do work for each
+/// of them. The server passes if it limits the flood, with a GOAWAY carrying
+/// ENHANCE_YOUR_CALM or NO_ERROR, or if it still answers a PING afterwards.
+/// A server that never limits it only passes as long as it keeps up: one
+/// that falls behind fails this test.)
+pub async fn sends_rapid_resets<IO: IntoHalves>(mut conn: Conn<IO>) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    if let Err(e) = conn.send_rapid_resets(1000).await {
+        // the server is allowed to hang up in the middle of it
+        tracing::debug!("server stopped reading during the flood: {e}");
+    }
+
+    conn.verify_flood_handled().await?;
+
+    Ok(())
+}
+
+/// An HTTP/2 connection can demand a greater commitment of resources to
+/// operate than an HTTP/1.1 connection.
+///
+/// (This checks the other direction: a small request shouldn't make the
+/// server send back more than `Config::max_amplification_factor`
+/// times as many bytes, counting frame headers and payloads on both sides,
+/// but not the handshake. A server that does can be used to amplify
+/// traffic.)
+pub async fn small_request_is_not_amplified<IO: IntoHalves>(
+    mut conn: Conn<IO>,
+) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    let sent_before = conn.sent_byte_count();
+    let received_before = conn.received_byte_count();
+
+    let stream_id = conn.open_stream()?;
+    let headers = conn.common_headers("GET");
+    conn.send_request(stream_id, &headers, &[], RequestOptions::default())
+        .await?;
+    conn.verify_stream_close(stream_id).await?;
+
+    let sent = conn.sent_byte_count() - sent_before;
+    let received = conn.received_byte_count() - received_before;
+    let max_factor = conn.config.max_amplification_factor as u64;
+    if received > sent * max_factor {
+        return Err(eyre!(
+            "server sent {received} bytes in response to a {sent}-byte request, more than {max_factor} times as many"
+        ));
+    }
+
+    Ok(())
+}
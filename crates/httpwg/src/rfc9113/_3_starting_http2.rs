@@ -1,5 +1,7 @@
 //! Section 3: Starting HTTP/2
 
+use std::time::Duration;
+
 use fluke_buffet::IntoHalves;
 use fluke_h2_parse::PREFACE;
 
@@ -13,13 +15,44 @@ use crate::{rfc9113::default_settings, Conn, ErrorC, FrameT};
 pub async fn sends_client_connection_preface<IO: IntoHalves>(
     mut conn: Conn<IO>,
 ) -> eyre::Result<()> {
-    conn.send(PREFACE).await?;
+    conn.send_preface(PREFACE).await?;
+
+    let settings = default_settings();
+    conn.write_settings(settings).await?;
+
+    // `wait_for_frame` would skip over anything sent before the SETTINGS
+    // frame, so look at the very first frame instead
+    let (frame, _) = conn.next_frame().await.unwrap();
+    if FrameT::from(frame.frame_type) != FrameT::Settings || frame.is_ack() {
+        return Err(eyre::eyre!(
+            "The server connection preface MUST be the first frame the server sends in the HTTP/2 connection, got {frame:?} first"
+        ));
+    }
+
+    Ok(())
+}
+
+/// That is, the connection preface starts with the string
+/// "PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n".
+///
+/// (Nothing says it arrives in a single read: here it's split in two, with
+/// a pause in between.)
+pub async fn sends_client_connection_preface_in_fragments<IO: IntoHalves>(
+    mut conn: Conn<IO>,
+) -> eyre::Result<()> {
+    conn.send_preface_fragmented(10, Duration::from_millis(50))
+        .await?;
 
     let settings = default_settings();
     conn.write_settings(settings).await?;
 
     let (frame, _) = conn.wait_for_frame(FrameT::Settings).await.unwrap();
-    assert!(!frame.is_ack(), "The server connection preface MUST be the first frame the server sends in the HTTP/2 connection.");
+    if frame.is_ack() {
+        return Err(eyre::eyre!(
+            "expected the server's SETTINGS before its SETTINGS ACK, got {frame:?}"
+        ));
+    }
+    conn.verify_connection_still_alive().await?;
 
     Ok(())
 }
@@ -29,7 +62,22 @@ pub async fn sends_client_connection_preface<IO: IntoHalves>(
 pub async fn sends_invalid_connection_preface<IO: IntoHalves>(
     mut conn: Conn<IO>,
 ) -> eyre::Result<()> {
-    conn.send("INVALID CONNECTION PREFACE\r\n\r\n").await?;
+    conn.send_preface(b"INVALID CONNECTION PREFACE\r\n\r\n")
+        .await?;
+    conn.verify_connection_error(ErrorC::ProtocolError).await?;
+
+    Ok(())
+}
+
+/// Clients and servers MUST treat an invalid connection preface as
+/// a connection error (Section 5.4.1) of type PROTOCOL_ERROR.
+///
+/// This one is only a byte away from the real preface.
+pub async fn sends_connection_preface_with_wrong_version<IO: IntoHalves>(
+    mut conn: Conn<IO>,
+) -> eyre::Result<()> {
+    conn.send_preface(b"PRI * HTTP/2.1\r\n\r\nSM\r\n\r\n")
+        .await?;
     conn.verify_connection_error(ErrorC::ProtocolError).await?;
 
     Ok(())
@@ -2,9 +2,11 @@
 
 use crate::{dummy_bytes, Conn, ErrorC};
 use enumflags2::BitFlags;
+use eyre::eyre;
 use fluke_buffet::IntoHalves;
 use fluke_h2_parse::{
-    ContinuationFlags, EncodedFrameType, Frame, FrameType, HeadersFlags, PrioritySpec, StreamId,
+    ContinuationFlags, EncodedFrameType, Frame, FrameType, HeadersFlags, PrioritySpec, Setting,
+    SettingPairs, StreamId,
 };
 
 //---- Section 4.1: Frame Format
@@ -13,15 +15,8 @@ use fluke_h2_parse::{
 pub async fn sends_frame_with_unknown_type<IO: IntoHalves>(mut conn: Conn<IO>) -> eyre::Result<()> {
     conn.handshake().await?;
 
-    conn.write_frame(
-        FrameType::Unknown(EncodedFrameType {
-            ty: 0xff,
-            flags: 0x0,
-        })
-        .into_frame(StreamId(0)),
-        dummy_bytes(8),
-    )
-    .await?;
+    conn.send_unknown_frame(0xff, 0x0, StreamId::CONNECTION, &dummy_bytes(8))
+        .await?;
 
     conn.verify_connection_still_alive().await?;
 
@@ -71,6 +66,25 @@ pub async fn sends_frame_with_reserved_bit_set<IO: IntoHalves>(
     Ok(())
 }
 
+/// The reserved bit MUST be ignored when receiving: a SETTINGS frame that
+/// has it set must still be processed normally, and acknowledged.
+pub async fn sends_settings_frame_with_reserved_bit_set<IO: IntoHalves>(
+    mut conn: Conn<IO>,
+) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    conn.write_frame(
+        FrameType::Settings(Default::default())
+            .into_frame(StreamId::CONNECTION)
+            .with_reserved_bit(),
+        SettingPairs(&[(Setting::InitialWindowSize, 65535)]),
+    )
+    .await?;
+    conn.verify_settings_frame_with_ack().await?;
+
+    Ok(())
+}
+
 //--- Section 4.2: Frame Size
 
 // All implementations MUST be capable of receiving and minimally
@@ -92,6 +106,52 @@ pub async fn data_frame_with_max_length<IO: IntoHalves>(mut conn: Conn<IO>) -> e
     Ok(())
 }
 
+/// The size of a frame payload is limited by the maximum size that a
+/// receiver advertises in the SETTINGS_MAX_FRAME_SIZE setting.
+///
+/// (Right at the limit is where off-by-one errors hide: a DATA frame of
+/// exactly the SETTINGS_MAX_FRAME_SIZE the server advertised must be
+/// accepted, and the request served with a successful status. The frame
+/// must fit in the flow-control windows too.)
+pub async fn data_frame_with_exact_advertised_max_length<IO: IntoHalves>(
+    mut conn: Conn<IO>,
+) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    let max_frame_size = conn.settings.max_frame_size;
+    let window = conn
+        .send_window()
+        .min(conn.settings.initial_window_size as i64);
+    if max_frame_size as i64 > window {
+        return Err(eyre!(
+            "can't send a {max_frame_size}-byte DATA frame, the server's flow-control window is only {window} bytes"
+        ));
+    }
+
+    let stream_id = conn.open_stream()?;
+    conn.encode_and_write_headers(
+        stream_id,
+        HeadersFlags::EndHeaders,
+        &conn.common_headers("POST"),
+    )
+    .await?;
+    conn.write_data(stream_id, true, dummy_bytes(max_frame_size as usize))
+        .await?;
+
+    let (headers, _end_stream) = conn.read_header_block(stream_id).await?;
+    let status = headers
+        .get_first(&":status".into())
+        .ok_or_else(|| eyre!("response has no :status"))?;
+    if status.first() != Some(&b'2') {
+        return Err(eyre!(
+            "server answered a request with a {max_frame_size}-byte DATA frame with status {}",
+            String::from_utf8_lossy(&status[..])
+        ));
+    }
+
+    Ok(())
+}
+
 /// An endpoint MUST send an error code of FRAME_SIZE_ERROR if a frame
 /// exceeds the size defined in SETTINGS_MAX_FRAME_SIZE, exceeds any
 /// limit defined for the frame type, or is too small to contain mandatory frame
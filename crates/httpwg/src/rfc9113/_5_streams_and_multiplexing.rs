@@ -1,12 +1,14 @@
 //! Section 5: Streams and Multiplexing
 
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use enumflags2::BitFlags;
 use fluke_buffet::IntoHalves;
 use fluke_h2_parse::{
-    ContinuationFlags, EncodedFrameType, FrameType, HeadersFlags, Setting, StreamId,
+    ContinuationFlags, FrameType, HeadersFlags, PriorityParams, Setting, StreamId,
 };
 
-use crate::{dummy_bytes, Conn, ErrorC};
+use crate::{dummy_bytes, Conn, ErrorC, MalformedRequest, StreamState};
 
 //---- Section 5.1: Stream States
 
@@ -263,6 +265,32 @@ pub async fn closed_sends_continuation_frame<IO: IntoHalves>(
     Ok(())
 }
 
+/// closed:
+/// An endpoint MUST NOT send frames other than PRIORITY on a closed
+/// stream.
+///
+/// (The RST_STREAM is provoked with a malformed request.)
+pub async fn closed_after_server_rst_stream<IO: IntoHalves>(
+    mut conn: Conn<IO>,
+) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    let stream_id = conn
+        .send_malformed_request(MalformedRequest::UppercaseFieldName)
+        .await?;
+    conn.verify_stream_error(ErrorC::ProtocolError).await?;
+
+    if conn.stream_state(stream_id) != Some(StreamState::Closed) {
+        // the server went for a connection error instead, which is allowed
+        // for malformed requests too: the stream is moot
+        return Ok(());
+    }
+    let within = conn.config.timeout;
+    conn.verify_no_frame_on_stream(stream_id, within).await?;
+
+    Ok(())
+}
+
 //--- Section 5.1.1: Stream Identifiers
 
 /// An endpoint that receives an unexpected stream identifier
@@ -380,17 +408,150 @@ pub async fn unknown_extension_frame_in_header_block<IO: IntoHalves>(
     conn.write_headers(stream_id, HeadersFlags::EndStream, block_fragment)
         .await?;
 
+    conn.send_unknown_frame(0xff, 0x0, stream_id, &dummy_bytes(8))
+        .await?;
+
+    conn.verify_connection_error(ErrorC::ProtocolError).await?;
+
+    Ok(())
+}
+
+/// Implementations MUST ignore unknown or unsupported values in all
+/// extensible protocol elements. Implementations MUST discard frames
+/// that have unknown or unsupported types.
+///
+/// (Every reserved GREASE type and identifier, plus `Config::grease_seed`.)
+pub async fn sends_grease_frame_and_setting<IO: IntoHalves>(
+    mut conn: Conn<IO>,
+) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    let seed = conn.config.grease_seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64
+    });
+    send_grease_then_request(&mut conn, seed)
+        .await
+        .map_err(|e| e.wrap_err(format!("with GREASE seed {seed}")))
+}
+
+async fn send_grease_then_request<IO: IntoHalves>(
+    conn: &mut Conn<IO>,
+    seed: u64,
+) -> eyre::Result<()> {
+    // splitmix64: no need for a proper RNG, this only has to vary between
+    // seeds
+    let mut n = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    n = (n ^ (n >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    n = (n ^ (n >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    n ^= n >> 31;
+
+    conn.send_unknown_frame(
+        0x0b + 0x1f * (n % 8) as u8,
+        (n >> 8) as u8,
+        StreamId::CONNECTION,
+        &dummy_bytes((n >> 16) as usize % 64),
+    )
+    .await?;
+
+    let mut settings = Vec::new();
+    settings.extend_from_slice(&(0x0a0a + 0x1f1f * ((n >> 24) % 8) as u16).to_be_bytes());
+    settings.extend_from_slice(&((n >> 32) as u32).to_be_bytes());
+    for i in 0..8u8 {
+        let frame_type = 0x0b + 0x1f * i;
+        // vary the flags and payload length too
+        let flags = 0x1f * i;
+        conn.send_unknown_frame(
+            frame_type,
+            flags,
+            StreamId::CONNECTION,
+            &dummy_bytes(4 * i as usize),
+        )
+        .await?;
+
+        let setting_id = 0x0a0a + 0x1f1f * i as u16;
+        settings.extend_from_slice(&setting_id.to_be_bytes());
+        settings.extend_from_slice(&(0x1f1f_1f1f * i as u32).to_be_bytes());
+    }
+
     conn.write_frame(
-        FrameType::Unknown(EncodedFrameType {
-            ty: 0xff,
-            flags: 0x0,
-        })
-        .into_frame(stream_id),
-        dummy_bytes(8),
+        FrameType::Settings(Default::default()).into_frame(StreamId::CONNECTION),
+        settings,
     )
     .await?;
+    conn.verify_settings_frame_with_ack().await?;
 
-    conn.verify_connection_error(ErrorC::ProtocolError).await?;
+    let stream_id = conn.open_stream()?;
+    conn.send_empty_post_to_root(stream_id).await?;
+    conn.verify_headers_frame(stream_id).await?;
+
+    Ok(())
+}
+
+/// Implementations MUST discard frames that have unknown or unsupported
+/// types.
+///
+/// (Here between the HEADERS and DATA of a request, which must be served.)
+pub async fn unknown_frame_on_open_stream<IO: IntoHalves>(mut conn: Conn<IO>) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    let stream_id = conn.open_stream()?;
+    let headers = conn.common_headers("POST");
+    conn.encode_and_write_headers(stream_id, HeadersFlags::EndHeaders, &headers)
+        .await?;
+    conn.send_unknown_frame(0xff, 0x0, stream_id, &dummy_bytes(8))
+        .await?;
+    conn.write_data(stream_id, true, b"test").await?;
+
+    conn.verify_headers_frame(stream_id).await?;
+    conn.verify_stream_close(stream_id).await?;
+
+    Ok(())
+}
+
+/// Implementations MUST discard frames that have unknown or unsupported
+/// types.
+///
+/// (An RFC 9218 extension frame: the request it's about must be served.)
+pub async fn sends_priority_update_frame<IO: IntoHalves>(mut conn: Conn<IO>) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    // RFC 9218 allows updating the priority of a stream that isn't open yet
+    let stream_id = StreamId(1);
+    let params = PriorityParams {
+        urgency: 1,
+        incremental: true,
+    };
+    conn.send_priority_update(stream_id, &params.encode())
+        .await?;
+
+    conn.send_empty_post_to_root(stream_id).await?;
+    conn.verify_headers_frame(stream_id).await?;
+
+    Ok(())
+}
+
+/// Implementations MUST discard frames that have unknown or unsupported
+/// types.
+///
+/// (RFC 9218, Section 4: out-of-range values MUST be ignored.)
+pub async fn sends_priority_update_frame_with_out_of_range_urgency<IO: IntoHalves>(
+    mut conn: Conn<IO>,
+) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    let stream_id = StreamId(1);
+    let params = PriorityParams {
+        urgency: PriorityParams::MAX_URGENCY + 1,
+        incremental: false,
+    };
+    conn.send_priority_update(stream_id, &params.encode())
+        .await?;
+
+    conn.send_empty_post_to_root(stream_id).await?;
+    conn.verify_headers_frame(stream_id).await?;
 
     Ok(())
 }
@@ -4,7 +4,7 @@ use enumflags2::BitFlags;
 use fluke_buffet::{IntoHalves, Piece};
 use fluke_h2_parse::{
     ContinuationFlags, Frame, FrameType, GoAway, HeadersFlags, IntoPiece, KnownErrorCode,
-    PrioritySpec, Setting, SettingPairs, SettingsFlags, StreamId,
+    PrioritySpec, Setting, SettingPairs, StreamId,
 };
 
 use crate::{dummy_bytes, Conn, ErrorC, FrameT};
@@ -54,6 +54,27 @@ pub async fn sends_data_frame_on_invalid_stream_state<IO: IntoHalves>(
     Ok(())
 }
 
+/// DATA frames MAY also contain padding. Padding can be added to DATA
+/// frames to obscure the size of messages.
+///
+/// (Padding doesn't count towards content-length.)
+pub async fn sends_data_frame_with_padding<IO: IntoHalves>(mut conn: Conn<IO>) -> eyre::Result<()> {
+    let stream_id = StreamId(1);
+
+    conn.handshake().await?;
+
+    let mut headers = conn.common_headers("POST");
+    headers.append("content-length", "4");
+    conn.encode_and_write_headers(stream_id, HeadersFlags::EndHeaders, &headers)
+        .await?;
+
+    conn.send_data_padded(stream_id, b"test", 6, true).await?;
+
+    conn.verify_headers_frame(stream_id).await?;
+
+    Ok(())
+}
+
 /// If the length of the padding is the length of the frame payload
 /// or greater, the recipient MUST treat this as a connection error
 /// (Section 5.4.1) of type PROTOCOL_ERROR.
@@ -71,10 +92,9 @@ pub async fn sends_data_frame_with_invalid_pad_length<IO: IntoHalves>(
     conn.write_headers(stream_id, HeadersFlags::EndHeaders, block_fragment)
         .await?;
 
-    // DATA frame:
     // frame length: 5, pad length: 6
-    conn.send(b"\x00\x00\x05\x00\x09\x00\x00\x00\x01").await?;
-    conn.send(b"\x06\x54\x65\x73\x74").await?;
+    conn.send_data_padded_unchecked(stream_id, b"Test", 6, 0, true)
+        .await?;
 
     conn.verify_connection_error(ErrorC::ProtocolError).await?;
 
@@ -269,12 +289,30 @@ pub async fn sends_settings_frame_with_ack_and_payload<IO: IntoHalves>(
 ) -> eyre::Result<()> {
     conn.handshake().await?;
 
+    conn.write_frame(Frame::settings_ack(), b"\x00").await?;
+
+    conn.verify_connection_error(ErrorC::FrameSizeError).await?;
+
+    Ok(())
+}
+
+/// ACK (0x1):
+/// When set, bit 0 indicates that this frame acknowledges receipt
+/// and application of the peer's SETTINGS frame. When this bit is
+/// set, the payload of the SETTINGS frame MUST be empty. Receipt of
+/// a SETTINGS frame with the ACK flag set and a length field value
+/// other than 0 MUST be treated as a connection error (Section 5.4.1)
+/// of type FRAME_SIZE_ERROR.
+///
+/// (Unlike the 1-byte payload above, this is a well-formed setting.)
+pub async fn sends_settings_frame_with_ack_and_setting_payload<IO: IntoHalves>(
+    mut conn: Conn<IO>,
+) -> eyre::Result<()> {
+    conn.handshake().await?;
+
     conn.write_frame(
-        Frame::new(
-            FrameType::Settings(SettingsFlags::Ack.into()),
-            StreamId::CONNECTION,
-        ),
-        b"\x00",
+        Frame::settings_ack(),
+        SettingPairs(&[(Setting::MaxConcurrentStreams, 0x64)]),
     )
     .await?;
 
@@ -405,6 +443,55 @@ pub async fn sends_settings_max_frame_size_with_invalid_value_above_max<IO: Into
     Ok(())
 }
 
+/// SETTINGS_MAX_HEADER_LIST_SIZE (0x6):
+/// This advisory setting informs a peer of the maximum field section
+/// size that the sender is prepared to accept, in units of octets.
+///
+/// (cf. Section 10.5.1. Skipped if the server advertises no limit, or 0.)
+pub async fn sends_header_list_exceeding_max_header_list_size<IO: IntoHalves>(
+    mut conn: Conn<IO>,
+) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    let limit = match conn.peer_max_header_list_size() {
+        Some(limit) if limit > 0 => limit as usize,
+        _ => {
+            tracing::debug!("server advertises no SETTINGS_MAX_HEADER_LIST_SIZE, skipping");
+            return Ok(());
+        }
+    };
+
+    // each field line counts its name, its value, and 32 octets
+    let mut headers = conn.common_headers("POST");
+    let dummy_size = "x-dummy0".len() + conn.config.max_header_len + 32;
+    headers.extend(conn.dummy_headers(limit / dummy_size + 1));
+    let block = conn.encode_headers(&headers)?;
+
+    let stream_id = StreamId(1);
+    conn.write_headers_in_fragments(stream_id, true, block)
+        .await?;
+
+    let (frame, payload) = conn
+        .wait_for_frame(FrameT::Headers | FrameT::RstStream | FrameT::GoAway)
+        .await
+        .into_result()?;
+    // a stream or connection error is fine too
+    if let FrameType::Headers(_) = frame.frame_type {
+        // the response may come in several frames
+        conn.put_back_frame(frame, payload);
+        let (headers, _end_stream) = conn.read_header_block(stream_id).await?;
+        let status = headers.get_first(&":status".into()).map(|s| s.to_vec());
+        if status.as_deref() != Some(&b"431"[..]) {
+            return Err(eyre::eyre!(
+                "expected the server to reject a header list larger than its SETTINGS_MAX_HEADER_LIST_SIZE ({limit}), got a response with status {:?}",
+                status.map(|s| String::from_utf8_lossy(&s).into_owned())
+            ));
+        }
+    }
+
+    Ok(())
+}
+
 /// An endpoint that receives a SETTINGS frame with any unknown
 /// or unsupported identifier MUST ignore that setting.
 pub async fn sends_settings_frame_with_unknown_identifier<IO: IntoHalves>(
@@ -467,6 +554,85 @@ pub async fn sends_multiple_values_of_settings_initial_window_size<IO: IntoHalve
     Ok(())
 }
 
+/// The values in the SETTINGS frame MUST be processed in the order
+/// they appear, with no other frame processing between values.
+///
+/// (If the server kept the first value, it would only send 1 byte.)
+pub async fn sends_multiple_values_of_settings_initial_window_size_last_one_wins<IO: IntoHalves>(
+    mut conn: Conn<IO>,
+) -> eyre::Result<()> {
+    let stream_id = StreamId(1);
+
+    conn.handshake().await?;
+
+    conn.write_frame(
+        Frame::new(
+            FrameType::Settings(Default::default()),
+            StreamId::CONNECTION,
+        ),
+        SettingPairs(&[
+            (Setting::InitialWindowSize, 1),
+            (Setting::InitialWindowSize, 100),
+        ]),
+    )
+    .await?;
+
+    conn.verify_settings_frame_with_ack().await?;
+
+    let block_fragment = conn.encode_headers(&conn.common_headers("POST"))?;
+    conn.write_headers(
+        stream_id,
+        HeadersFlags::EndStream | HeadersFlags::EndHeaders,
+        block_fragment,
+    )
+    .await?;
+
+    let (frame, _payload) = conn.wait_for_frame(FrameT::Data).await.unwrap();
+    assert!(
+        frame.len > 1 && frame.len <= 100,
+        "server should have applied the last INITIAL_WINDOW_SIZE value (100), not the first (1), \
+        but sent {} bytes of DATA",
+        frame.len
+    );
+
+    Ok(())
+}
+
+/// Most values in SETTINGS benefit from or require an understanding of
+/// when the peer has received and applied the changed parameter values.
+/// In order to provide such synchronization timepoints, the recipient of
+/// a SETTINGS frame in which the ACK flag is not set MUST apply the
+/// updated settings as soon as possible upon receipt.
+///
+/// (The request goes out in the same write as the SETTINGS frame.)
+pub async fn sends_request_right_behind_settings_frame<IO: IntoHalves>(
+    mut conn: Conn<IO>,
+) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    let stream_id = conn.open_stream()?;
+    let block_fragment = conn.encode_headers(&conn.common_headers("POST"))?;
+    let headers = Frame::new(
+        FrameType::Headers(HeadersFlags::EndStream | HeadersFlags::EndHeaders),
+        stream_id,
+    );
+    conn.send_settings_then(
+        SettingPairs(&[(Setting::InitialWindowSize, 1)]),
+        vec![(headers, block_fragment)],
+    )
+    .await?;
+
+    let (frame, _payload) = conn.wait_for_frame(FrameT::Data).await.unwrap();
+    if frame.len != 1 {
+        return Err(eyre::eyre!(
+            "server sent {} bytes of DATA on a stream opened after INITIAL_WINDOW_SIZE was set to 1",
+            frame.len
+        ));
+    }
+
+    Ok(())
+}
+
 /// Once all values have been processed, the recipient MUST
 /// immediately emit a SETTINGS frame with the ACK flag set.
 pub async fn sends_settings_frame_without_ack_flag<IO: IntoHalves>(
@@ -488,6 +654,52 @@ pub async fn sends_settings_frame_without_ack_flag<IO: IntoHalves>(
     Ok(())
 }
 
+/// Once all values have been processed, the recipient MUST
+/// immediately emit a SETTINGS frame with the ACK flag set.
+///
+/// (Our initial SETTINGS frame must be acknowledged exactly once.)
+pub async fn acknowledges_settings_frame_exactly_once<IO: IntoHalves>(
+    mut conn: Conn<IO>,
+) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    // stay under the read timeout, so the connection is still being read
+    // from when we check it's alive
+    let window = conn.config.timeout / 2;
+    let extra_acks = conn.count_settings_acks(window).await?;
+    if extra_acks != 0 {
+        return Err(eyre::eyre!(
+            "server acknowledged our SETTINGS {} times",
+            extra_acks + 1
+        ));
+    }
+
+    conn.verify_connection_still_alive().await?;
+
+    Ok(())
+}
+
+/// Once all values have been processed, the recipient MUST
+/// immediately emit a SETTINGS frame with the ACK flag set.
+///
+/// (A SETTINGS frame without any parameter must be acknowledged too.)
+pub async fn sends_empty_settings_frame<IO: IntoHalves>(mut conn: Conn<IO>) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    conn.write_frame(
+        Frame::new(
+            FrameType::Settings(Default::default()),
+            StreamId::CONNECTION,
+        ),
+        SettingPairs(&[]),
+    )
+    .await?;
+
+    conn.verify_settings_frame_with_ack().await?;
+
+    Ok(())
+}
+
 // (Note: Section 6.6 is skipped: push promise is discouraged nowadays)
 
 //---- Section 6.7: PING
@@ -587,6 +799,45 @@ pub async fn sends_goaway_frame_with_non_zero_stream_id<IO: IntoHalves>(
     Ok(())
 }
 
+/// Once the GOAWAY is sent, the sender will ignore frames sent on
+/// streams initiated by the receiver if the stream has an identifier
+/// higher than the included last stream identifier.
+///
+/// (Refusing it with REFUSED_STREAM, or hanging up, passes too.)
+pub async fn sends_request_above_goaway_last_stream_id<IO: IntoHalves>(
+    mut conn: Conn<IO>,
+) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    let stream_id = conn.open_stream()?;
+    conn.send_empty_post_to_root(stream_id).await?;
+    conn.verify_stream_close(stream_id).await?;
+
+    // PING frames are connection-scoped, this is a connection error
+    conn.write_frame(
+        Frame::new(FrameType::Ping(Default::default()), stream_id),
+        dummy_bytes(8),
+    )
+    .await?;
+
+    let Some(goaway) = conn.wait_for_goaway().await? else {
+        // the server hung up without a GOAWAY, nothing more to check
+        return Ok(());
+    };
+
+    // the next client-initiated stream above the last stream identifier
+    let above = StreamId((goaway.last_stream_id.0 + 1) | 1);
+    if conn.send_empty_post_to_root(above).await.is_err() {
+        // the server already hung up
+        return Ok(());
+    }
+
+    let within = conn.config.timeout;
+    conn.verify_no_response_on_stream(above, within).await?;
+
+    Ok(())
+}
+
 //---- Section 6.9: WINDOW_UPDATE
 
 /// A receiver MUST treat the receipt of a WINDOW_UPDATE frame with
@@ -682,6 +933,65 @@ pub async fn sends_settings_frame_to_set_initial_window_size_to_1_and_sends_head
     Ok(())
 }
 
+/// The sender MUST NOT send a flow-controlled frame with a length
+/// that exceeds the space available in either of the flow-control
+/// windows advertised by the receiver.
+///
+/// (Skipped if the server keeps replenishing the window as it's filled.)
+pub async fn sends_data_frames_exceeding_connection_flow_control_window<IO: IntoHalves>(
+    mut conn: Conn<IO>,
+) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    if conn.settings.initial_window_size == 0 {
+        return Err(eyre::eyre!(
+            "this test needs the server to let us send DATA on streams, but its SETTINGS_INITIAL_WINDOW_SIZE is 0"
+        ));
+    }
+    let max_frame_size = conn.settings.max_frame_size as i64;
+    let headers = conn.common_headers("POST");
+
+    let mut stream_id = conn.open_next_stream(&headers, false).await?;
+    let mut rounds = 0;
+    loop {
+        // fill the connection window, opening streams as theirs run out
+        while conn.send_window() > 0 {
+            if conn.stream_send_window(stream_id) <= 0 {
+                stream_id = conn.open_next_stream(&headers, false).await?;
+            }
+            let len = conn
+                .send_window()
+                .min(conn.stream_send_window(stream_id))
+                .min(max_frame_size);
+            conn.write_data(stream_id, false, dummy_bytes(len as usize))
+                .await?;
+        }
+
+        // credit whatever WINDOW_UPDATE frames the server sent so far
+        conn.wait_until_idle(conn.config.timeout / 4).await?;
+        if conn.send_window() <= 0 {
+            break;
+        }
+
+        rounds += 1;
+        if rounds == 8 {
+            tracing::debug!("server keeps replenishing the connection window, skipping");
+            return Ok(());
+        }
+    }
+
+    // the connection window is now exhausted: one more octet is one too many
+    if conn.stream_send_window(stream_id) <= 0 {
+        stream_id = conn.open_next_stream(&headers, false).await?;
+    }
+    conn.write_data(stream_id, true, dummy_bytes(1)).await?;
+
+    conn.verify_connection_error(ErrorC::FlowControlError)
+        .await?;
+
+    Ok(())
+}
+
 /// A sender MUST NOT allow a flow-control window to exceed 2^31-1
 /// octets. If a sender receives a WINDOW_UPDATE that causes a
 /// flow-control window to exceed this maximum, it MUST terminate
@@ -891,6 +1201,24 @@ pub async fn sends_continuation_frame_followed_by_non_continuation_frame<IO: Int
     Ok(())
 }
 
+/// END_HEADERS (0x4):
+/// If the END_HEADERS bit is not set, this frame MUST be followed
+/// by another CONTINUATION frame. A receiver MUST treat the receipt
+/// of any other type of frame or a frame on a different stream as
+/// a connection error (Section 5.4.1) of type PROTOCOL_ERROR.
+pub async fn sends_headers_frame_followed_by_frame_on_another_stream<IO: IntoHalves>(
+    mut conn: Conn<IO>,
+) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    conn.send_headers_then_interleave(StreamId(1), StreamId(3))
+        .await?;
+
+    conn.verify_connection_error(ErrorC::ProtocolError).await?;
+
+    Ok(())
+}
+
 /// CONTINUATION frames MUST be associated with a stream. If a
 /// CONTINUATION frame is received whose stream identifier field is
 /// 0x0, the recipient MUST respond with a connection error
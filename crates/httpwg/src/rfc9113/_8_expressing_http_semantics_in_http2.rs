@@ -3,7 +3,8 @@
 use std::io::Write;
 
 use fluke_buffet::IntoHalves;
-use fluke_h2_parse::{pack_bit_and_u31, FrameType, HeadersFlags, StreamId};
+use fluke_h2_parse::{pack_bit_and_u31, FrameType, HeadersFlags, Setting, StreamId};
+use tracing::debug;
 
 use crate::{Conn, ErrorC, FrameT, Headers};
 
@@ -473,6 +474,24 @@ pub async fn sends_headers_frame_with_te_not_trailers<IO: IntoHalves>(
     Ok(())
 }
 
+/// The only exception to this is the TE header field, which MAY be present in
+/// an HTTP/2 request; when it is, it MUST NOT contain any value other than
+/// "trailers".
+///
+/// (A list that merely includes "trailers" is malformed too.)
+pub async fn sends_headers_frame_with_te_trailers_and_gzip<IO: IntoHalves>(
+    mut conn: Conn<IO>,
+) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    let mut headers = conn.common_headers("POST");
+    headers.append("te", "trailers, gzip");
+    conn.send_req_and_expect_stream_rst(StreamId(1), &headers)
+        .await?;
+
+    Ok(())
+}
+
 //---- Section 8.2.3: Compressing the Cookie Header Field
 
 // That can't really be tested without controlling both sides of the
@@ -711,14 +730,45 @@ pub async fn client_sends_push_promise_frame<IO: IntoHalves>(
             s.write_all(&block_fragment)?;
             Ok(())
         })?;
-    conn.write_frame(FrameType::PushPromise.into_frame(stream_id), payload)
-        .await?;
+    conn.write_frame(
+        FrameType::PushPromise(Default::default()).into_frame(stream_id),
+        payload,
+    )
+    .await?;
 
     conn.verify_connection_error(ErrorC::ProtocolError).await?;
 
     Ok(())
 }
 
+/// A client can use the SETTINGS_MAX_CONCURRENT_STREAMS setting to limit the
+/// number of responses that can be concurrently pushed by a server.
+///
+/// Servers that don't push pass trivially.
+pub async fn server_respects_max_concurrent_pushes<IO: IntoHalves>(
+    mut conn: Conn<IO>,
+) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    conn.write_settings(&[(Setting::EnablePush, 1), (Setting::MaxConcurrentStreams, 1)])
+        .await?;
+    conn.verify_settings_frame_with_ack().await?;
+
+    let stream_id = conn.open_stream()?;
+    let headers = conn.common_headers("GET");
+    conn.encode_and_write_headers(
+        stream_id,
+        HeadersFlags::EndHeaders | HeadersFlags::EndStream,
+        &headers,
+    )
+    .await?;
+
+    let pushes = conn.verify_max_concurrent_pushes(stream_id, 1).await?;
+    debug!("server pushed {pushes} streams");
+
+    Ok(())
+}
+
 //---- Section 8.5: The CONNECT Method
 
 /// The CONNECT method (Section 9.3.6 of [HTTP]) is used to convert an HTTP
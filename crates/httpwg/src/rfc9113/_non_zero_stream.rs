@@ -0,0 +1,64 @@
+//! Connection-scoped frames on a non-zero stream
+//!
+//! SETTINGS, PING and GOAWAY frames apply to the connection as a whole:
+//! receiving one on a stream other than 0x0 is a connection error of type
+//! PROTOCOL_ERROR. This is the mirror of the stream-zero suite.
+
+use fluke_buffet::{IntoHalves, Piece};
+use fluke_h2_parse::{FrameType, GoAway, KnownErrorCode, Setting, SettingPairs, StreamId};
+
+use super::sends_frame_on_wrong_stream;
+use crate::{dummy_bytes, Conn};
+
+/// SETTINGS frames always apply to a connection, never a single stream.
+/// The stream identifier for a SETTINGS frame MUST be zero (0x0). If an
+/// endpoint receives a SETTINGS frame whose stream identifier field is
+/// anything other than 0x0, the endpoint MUST respond with a connection
+/// error (Section 5.4.1) of type PROTOCOL_ERROR. (Section 6.5)
+pub async fn sends_settings_frame<IO: IntoHalves>(mut conn: Conn<IO>) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    sends_frame_on_wrong_stream(
+        conn,
+        FrameType::Settings(Default::default()),
+        StreamId(1),
+        SettingPairs(&[(Setting::MaxConcurrentStreams, 100)]),
+    )
+    .await
+}
+
+/// PING frames are not associated with any individual stream. If a PING
+/// frame is received with a Stream Identifier field value other than 0x0,
+/// the recipient MUST respond with a connection error (Section 5.4.1) of
+/// type PROTOCOL_ERROR. (Section 6.7)
+pub async fn sends_ping_frame<IO: IntoHalves>(mut conn: Conn<IO>) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    sends_frame_on_wrong_stream(
+        conn,
+        FrameType::Ping(Default::default()),
+        StreamId(1),
+        dummy_bytes(8),
+    )
+    .await
+}
+
+/// The GOAWAY frame applies to the connection, not a specific stream. An
+/// endpoint MUST treat a GOAWAY frame with a stream identifier other than
+/// 0x0 as a connection error (Section 5.4.1) of type PROTOCOL_ERROR.
+/// (Section 6.8)
+pub async fn sends_goaway_frame<IO: IntoHalves>(mut conn: Conn<IO>) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    sends_frame_on_wrong_stream(
+        conn,
+        FrameType::GoAway,
+        StreamId(1),
+        GoAway {
+            last_stream_id: StreamId::CONNECTION,
+            error_code: KnownErrorCode::NoError.into(),
+            additional_debug_data: Piece::empty(),
+        },
+    )
+    .await
+}
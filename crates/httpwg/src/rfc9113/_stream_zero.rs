@@ -0,0 +1,94 @@
+//! Stream-scoped frames on stream 0
+//!
+//! DATA, HEADERS, RST_STREAM, PUSH_PROMISE and CONTINUATION frames are
+//! always associated with a stream: receiving one on stream 0x0 is a
+//! connection error of type PROTOCOL_ERROR. Section 6 states the rule once
+//! per frame type, this checks it for each of them the same way.
+
+use fluke_buffet::IntoHalves;
+use fluke_h2_parse::{
+    pack_bit_and_u31, ContinuationFlags, FrameType, HeadersFlags, IntoPiece, KnownErrorCode,
+    RstStream, StreamId,
+};
+
+use super::sends_frame_on_wrong_stream;
+use crate::Conn;
+
+async fn sends_frame_on_stream_zero<IO: IntoHalves>(
+    conn: Conn<IO>,
+    frame_type: FrameType,
+    payload: impl IntoPiece,
+) -> eyre::Result<()> {
+    sends_frame_on_wrong_stream(conn, frame_type, StreamId::CONNECTION, payload).await
+}
+
+/// DATA frames MUST be associated with a stream. If a DATA frame is
+/// received whose stream identifier field is 0x0, the recipient MUST
+/// respond with a connection error (Section 5.4.1) of type
+/// PROTOCOL_ERROR. (Section 6.1)
+pub async fn sends_data_frame<IO: IntoHalves>(mut conn: Conn<IO>) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    sends_frame_on_stream_zero(conn, FrameType::Data(Default::default()), b"test".to_vec()).await
+}
+
+/// If a HEADERS frame is received whose stream identifier field is 0x0,
+/// the recipient MUST respond with a connection error (Section 5.4.1) of
+/// type PROTOCOL_ERROR. (Section 6.2)
+pub async fn sends_headers_frame<IO: IntoHalves>(mut conn: Conn<IO>) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    let block_fragment = conn.encode_headers(&conn.common_headers("POST"))?;
+    sends_frame_on_stream_zero(
+        conn,
+        FrameType::Headers(HeadersFlags::EndStream | HeadersFlags::EndHeaders),
+        block_fragment,
+    )
+    .await
+}
+
+/// If a RST_STREAM frame is received with a stream identifier of 0x0,
+/// the recipient MUST treat this as a connection error (Section 5.4.1)
+/// of type PROTOCOL_ERROR. (Section 6.4)
+pub async fn sends_rst_stream_frame<IO: IntoHalves>(mut conn: Conn<IO>) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    sends_frame_on_stream_zero(
+        conn,
+        FrameType::RstStream,
+        RstStream {
+            error_code: KnownErrorCode::Cancel.into(),
+        },
+    )
+    .await
+}
+
+/// If the stream identifier field specifies the value 0x0, a recipient
+/// MUST respond with a connection error (Section 5.4.1) of type
+/// PROTOCOL_ERROR. (Section 6.6)
+///
+/// (Clients can't push either, so a server has two reasons to reject this.)
+pub async fn sends_push_promise_frame<IO: IntoHalves>(mut conn: Conn<IO>) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    let block_fragment = conn.encode_headers(&conn.common_headers("GET"))?;
+    let mut payload = pack_bit_and_u31(0, 2).to_vec();
+    payload.extend_from_slice(&block_fragment[..]);
+    sends_frame_on_stream_zero(conn, FrameType::PushPromise(Default::default()), payload).await
+}
+
+/// CONTINUATION frames MUST be associated with a stream. If a
+/// CONTINUATION frame is received whose stream identifier field is 0x0,
+/// the recipient MUST respond with a connection error (Section 5.4.1) of
+/// type PROTOCOL_ERROR. (Section 6.10)
+pub async fn sends_continuation_frame<IO: IntoHalves>(mut conn: Conn<IO>) -> eyre::Result<()> {
+    conn.handshake().await?;
+
+    let block_fragment = conn.encode_headers(&conn.dummy_headers(1))?;
+    sends_frame_on_stream_zero(
+        conn,
+        FrameType::Continuation(ContinuationFlags::EndHeaders.into()),
+        block_fragment,
+    )
+    .await
+}
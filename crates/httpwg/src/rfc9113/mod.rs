@@ -10,7 +10,10 @@
 //!
 //! cf. <https://httpwg.org/specs/rfc9113.html>
 
-use fluke_h2_parse::{Setting, SettingPairs};
+use fluke_buffet::IntoHalves;
+use fluke_h2_parse::{FrameType, IntoPiece, Setting, SettingPairs, StreamId};
+
+use crate::{Conn, ErrorC};
 
 pub const DEFAULT_WINDOW_SIZE: u32 = 65536;
 pub const DEFAULT_FRAME_SIZE: u32 = 16384;
@@ -25,9 +28,30 @@ pub fn default_settings() -> SettingPairs<'static> {
     )
 }
 
+/// Sends a frame of the given type on a stream it can't be on (stream 0 for
+/// stream-scoped frames, any other stream for connection-scoped ones), then
+/// checks that the server treats it as a connection error of type
+/// PROTOCOL_ERROR. Expects the handshake to be done already.
+pub(crate) async fn sends_frame_on_wrong_stream<IO: IntoHalves>(
+    mut conn: Conn<IO>,
+    frame_type: FrameType,
+    stream_id: StreamId,
+    payload: impl IntoPiece,
+) -> eyre::Result<()> {
+    conn.write_frame(frame_type.into_frame(stream_id), payload)
+        .await?;
+
+    conn.verify_connection_error(ErrorC::ProtocolError).await?;
+
+    Ok(())
+}
+
+pub mod _10_security_considerations;
 pub mod _3_starting_http2;
 pub mod _4_http_frames;
 pub mod _5_streams_and_multiplexing;
 pub mod _6_frame_definitions;
 pub mod _7_error_codes;
 pub mod _8_expressing_http_semantics_in_http2;
+pub mod _non_zero_stream;
+pub mod _stream_zero;
@@ -0,0 +1,186 @@
+//! Tracks the state of streams from our side of the connection, based on
+//! the frames we send and receive.
+
+use std::collections::HashMap;
+
+use fluke_h2_parse::{Frame, FrameType, StreamId};
+
+/// The state of a stream (cf. RFC 9113, Section 5.1), see
+/// [crate::Conn::stream_state]. Idle streams aren't tracked, and reserved
+/// ones (server push) aren't either.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamState {
+    Open,
+    /// we sent END_STREAM
+    HalfClosedLocal,
+    /// the peer sent END_STREAM
+    HalfClosedRemote,
+    Closed,
+}
+
+#[derive(Default)]
+pub(crate) struct StreamStates {
+    states: HashMap<StreamId, StreamState>,
+    /// the highest client-initiated (odd) stream id a HEADERS frame was
+    /// sent or received on
+    highest_client: Option<StreamId>,
+}
+
+impl StreamStates {
+    pub(crate) fn on_frame_sent(&mut self, frame: &Frame) {
+        self.transition(frame, true)
+    }
+
+    pub(crate) fn on_frame_received(&mut self, frame: &Frame) {
+        self.transition(frame, false)
+    }
+
+    pub(crate) fn get(&self, stream_id: StreamId) -> Option<StreamState> {
+        self.states.get(&stream_id).copied()
+    }
+
+    /// The highest client-initiated stream id seen so far: a new stream must
+    /// have a higher one (cf. RFC 9113, Section 5.1.1)
+    pub(crate) fn highest_client_stream_id(&self) -> Option<StreamId> {
+        self.highest_client
+    }
+
+    /// How many streams are open or half-closed, which is what counts
+    /// towards SETTINGS_MAX_CONCURRENT_STREAMS (cf. RFC 9113, Section 5.1.2)
+    pub(crate) fn open_count(&self) -> usize {
+        self.states
+            .values()
+            .filter(|state| **state != StreamState::Closed)
+            .count()
+    }
+
+    fn transition(&mut self, frame: &Frame, local: bool) {
+        use StreamState::*;
+
+        let stream_id = frame.stream_id;
+        if stream_id == StreamId::CONNECTION {
+            return;
+        }
+
+        match frame.frame_type {
+            FrameType::RstStream => {
+                self.states.insert(stream_id, Closed);
+            }
+            FrameType::Headers(_) | FrameType::Data(_) => {
+                if matches!(frame.frame_type, FrameType::Headers(_)) && stream_id.0 % 2 == 1 {
+                    self.highest_client = self.highest_client.max(Some(stream_id));
+                }
+
+                let state = match self.states.get(&stream_id) {
+                    Some(state) => *state,
+                    // HEADERS opens an idle stream; DATA on one is a protocol
+                    // error, not a transition
+                    None if matches!(frame.frame_type, FrameType::Headers(_)) => Open,
+                    None => return,
+                };
+                if !frame.is_end_stream() {
+                    self.states.insert(stream_id, state);
+                    return;
+                }
+
+                let next = match (state, local) {
+                    (Open, true) => HalfClosedLocal,
+                    (Open, false) => HalfClosedRemote,
+                    (HalfClosedRemote, true) | (HalfClosedLocal, false) => Closed,
+                    // END_STREAM twice from the same side: an error, but
+                    // not ours to flag here
+                    (state, _) => state,
+                };
+                self.states.insert(stream_id, next);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use enumflags2::BitFlags;
+    use fluke_h2_parse::{DataFlags, Frame, FrameType, HeadersFlags, StreamId};
+
+    use super::{StreamState, StreamStates};
+
+    #[test]
+    fn open_count() {
+        let headers = |stream_id: u32, end_stream: bool| {
+            let mut flags: BitFlags<HeadersFlags> = HeadersFlags::EndHeaders.into();
+            if end_stream {
+                flags |= HeadersFlags::EndStream;
+            }
+            Frame::new(FrameType::Headers(flags), StreamId(stream_id))
+        };
+        let data_end_stream = |stream_id: u32| {
+            Frame::new(
+                FrameType::Data(DataFlags::EndStream.into()),
+                StreamId(stream_id),
+            )
+        };
+        let rst_stream = |stream_id: u32| Frame::new(FrameType::RstStream, StreamId(stream_id));
+
+        let mut states = StreamStates::default();
+        assert_eq!(states.open_count(), 0);
+
+        // a request with a body, and one without
+        states.on_frame_sent(&headers(1, false));
+        states.on_frame_sent(&headers(3, true));
+        assert_eq!(states.open_count(), 2);
+
+        // half-closed streams still count
+        states.on_frame_sent(&data_end_stream(1));
+        assert_eq!(states.open_count(), 2);
+
+        // the response to stream 3 closes it, trailers included
+        states.on_frame_received(&headers(3, false));
+        states.on_frame_received(&headers(3, true));
+        assert_eq!(states.open_count(), 1);
+
+        // RST_STREAM closes a stream, from either side
+        states.on_frame_sent(&headers(5, false));
+        assert_eq!(states.open_count(), 2);
+        states.on_frame_received(&rst_stream(1));
+        states.on_frame_sent(&rst_stream(5));
+        assert_eq!(states.open_count(), 0);
+        assert_eq!(states.get(StreamId(1)), Some(StreamState::Closed));
+
+        // a response still in flight doesn't reopen a reset stream
+        states.on_frame_received(&headers(5, true));
+        assert_eq!(states.open_count(), 0);
+
+        // DATA doesn't open idle streams
+        states.on_frame_received(&data_end_stream(7));
+        assert_eq!(states.open_count(), 0);
+    }
+
+    #[test]
+    fn highest_client_stream_id() {
+        let headers = |stream_id: u32| {
+            Frame::new(
+                FrameType::Headers(HeadersFlags::EndHeaders.into()),
+                StreamId(stream_id),
+            )
+        };
+
+        let mut states = StreamStates::default();
+        assert_eq!(states.highest_client_stream_id(), None);
+
+        states.on_frame_sent(&headers(5));
+        assert_eq!(states.highest_client_stream_id(), Some(StreamId(5)));
+
+        // going back down doesn't lower it, and neither do server streams
+        states.on_frame_sent(&headers(3));
+        states.on_frame_received(&headers(8));
+        assert_eq!(states.highest_client_stream_id(), Some(StreamId(5)));
+
+        // DATA doesn't open streams
+        states.on_frame_sent(&Frame::new(
+            FrameType::Data(Default::default()),
+            StreamId(7),
+        ));
+        assert_eq!(states.highest_client_stream_id(), Some(StreamId(5)));
+    }
+}
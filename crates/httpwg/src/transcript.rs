@@ -0,0 +1,206 @@
+//! Snapshots of a whole exchange, to compare against a golden copy, see
+//! [Transcript].
+
+use std::fmt;
+
+use eyre::eyre;
+use fluke_h2_parse::Frame;
+
+/// Which way a frame went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// Every frame sent and received on a connection, in order, as recorded by
+/// [crate::Conn::transcript].
+///
+/// It renders to a stable text format (its [fmt::Display] impl), one frame
+/// per line: `>` for frames we sent, `<` for frames we received, then the
+/// frame type, stream, flags and payload length:
+///
+/// ```text
+/// > SETTINGS stream=0 flags=0x00 len=18
+/// < SETTINGS stream=0 flags=0x00 len=0
+/// > SETTINGS stream=0 flags=0x01 len=0
+/// < SETTINGS stream=0 flags=0x01 len=0
+/// ```
+///
+/// Payloads are left out: they're better checked with per-frame
+/// assertions, and HPACK-encoded ones change with the encoder's state.
+#[derive(Clone, Default)]
+pub struct Transcript {
+    frames: Vec<(Direction, Frame)>,
+}
+
+impl Transcript {
+    pub(crate) fn push(&mut self, direction: Direction, frame: Frame) {
+        self.frames.push((direction, frame));
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    /// The recorded frames, in order
+    pub fn frames(&self) -> &[(Direction, Frame)] {
+        &self.frames
+    }
+
+    /// Checks that the transcript renders to `golden`. Leading and trailing
+    /// whitespace, empty lines, and lines starting with `#` (comments) are
+    /// ignored in `golden`.
+    ///
+    /// On mismatch, the error has a line diff, `-` for `golden` and `+` for
+    /// this transcript.
+    pub fn assert_matches(&self, golden: &str) -> eyre::Result<()> {
+        let expected: Vec<&str> = golden
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+        let rendered = self.to_string();
+        let actual: Vec<&str> = rendered.lines().collect();
+
+        if expected == actual {
+            return Ok(());
+        }
+        Err(eyre!(
+            "transcript differs (- golden, + actual):{}",
+            diff(&expected, &actual)
+        ))
+    }
+}
+
+impl fmt::Display for Transcript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (direction, frame) in &self.frames {
+            let arrow = match direction {
+                Direction::Sent => '>',
+                Direction::Received => '<',
+            };
+            writeln!(
+                f,
+                "{arrow} {} stream={} flags={:#04x} len={}",
+                frame.frame_type.name(),
+                frame.stream_id,
+                frame.flags(),
+                frame.len
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A line diff, from the longest common subsequence of both sides
+fn diff(expected: &[&str], actual: &[&str]) -> String {
+    let (n, m) = (expected.len(), actual.len());
+
+    // lcs[i][j] is the length of the longest common subsequence of
+    // expected[i..] and actual[j..]
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if expected[i] == actual[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && expected[i] == actual[j] {
+            out.push_str(&format!("\n  {}", expected[i]));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push_str(&format!("\n- {}", expected[i]));
+            i += 1;
+        } else {
+            out.push_str(&format!("\n+ {}", actual[j]));
+            j += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use fluke_h2_parse::{Frame, FrameType, HeadersFlags, StreamId};
+
+    use super::{Direction, Transcript};
+
+    fn transcript() -> Transcript {
+        let mut transcript = Transcript::default();
+        transcript.push(
+            Direction::Sent,
+            Frame::new(
+                FrameType::Headers(HeadersFlags::EndHeaders | HeadersFlags::EndStream),
+                StreamId(1),
+            )
+            .with_len(14),
+        );
+        transcript.push(
+            Direction::Received,
+            Frame::new(
+                FrameType::Headers(HeadersFlags::EndHeaders.into()),
+                StreamId(1),
+            )
+            .with_len(3),
+        );
+        transcript.push(
+            Direction::Received,
+            Frame::new(FrameType::RstStream, StreamId(1)).with_len(4),
+        );
+        transcript
+    }
+
+    #[test]
+    fn renders_one_line_per_frame() {
+        assert_eq!(
+            transcript().to_string(),
+            "> HEADERS stream=1 flags=0x05 len=14\n\
+             < HEADERS stream=1 flags=0x04 len=3\n\
+             < RST_STREAM stream=1 flags=0x00 len=4\n"
+        );
+    }
+
+    #[test]
+    fn matches_golden() {
+        transcript()
+            .assert_matches(
+                "
+                # a request, then a reset response
+                > HEADERS stream=1 flags=0x05 len=14
+                < HEADERS stream=1 flags=0x04 len=3
+
+                < RST_STREAM stream=1 flags=0x00 len=4
+                ",
+            )
+            .unwrap();
+
+        let msg = transcript()
+            .assert_matches(
+                "
+                > HEADERS stream=1 flags=0x05 len=14
+                < HEADERS stream=1 flags=0x04 len=3
+                < DATA stream=1 flags=0x01 len=5
+                ",
+            )
+            .unwrap_err()
+            .to_string();
+        assert!(
+            msg.ends_with(
+                "\n  > HEADERS stream=1 flags=0x05 len=14\
+                 \n  < HEADERS stream=1 flags=0x04 len=3\
+                 \n- < DATA stream=1 flags=0x01 len=5\
+                 \n+ < RST_STREAM stream=1 flags=0x00 len=4"
+            ),
+            "{msg}"
+        );
+    }
+}